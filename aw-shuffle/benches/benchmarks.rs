@@ -1,16 +1,25 @@
 use std::convert::TryInto;
+use std::hash::Hasher;
 use std::time::{Duration, Instant};
 
 use aw_shuffle::_secret_do_not_use::Rbtree;
-use aw_shuffle::{AwShuffler, NewItemHandling, Shuffler};
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use aw_shuffle::{
+    AwShuffler, IdentityHasher, NewItemHandling, Shuffler, ShufflerBuilder, ShufflerGeneric,
+};
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion};
 use rand::distributions::Uniform;
-use rand::prelude::{Distribution, SliceRandom};
+use rand::prelude::{Distribution, SliceRandom, StdRng};
 use rand::Rng;
 
 const CHARACTERS: &[u8] =
     b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456790123456789._-...";
 
+// Trees are built with a fixed hasher so every run hashes items identically and produces the
+// same tree shapes. With a random hasher the shapes, and so the timings, vary between runs enough
+// to hide regressions.
+const HASH_SEEDS: [u64; 4] = [0x243f_6a88, 0x85a3_08d3, 0x1319_8a2e, 0x0370_7344];
+
 static SEQUENTIAL_COUNTS: &[usize] = &[1, 10, 100, 1000, 10000, 50000, 100_000, 500_000, 1_000_000];
 
 fn random_strings(n: usize) -> Vec<String> {
@@ -48,7 +57,7 @@ fn sequential_inserts(c: &mut Criterion) {
                 for _i in 0..iters {
                     let input = strings.clone();
                     let start = Instant::now();
-                    let mut rb = Rbtree::default();
+                    let mut rb = Rbtree::new_seeded(HASH_SEEDS);
                     input.into_iter().enumerate().for_each(|(i, s)| {
                         rb.insert(s, i.try_into().unwrap());
                     });
@@ -79,7 +88,7 @@ fn shuffled_inserts(c: &mut Criterion) {
                     input.shuffle(&mut rng);
 
                     let start = Instant::now();
-                    let mut rb = Rbtree::default();
+                    let mut rb = Rbtree::new_seeded(HASH_SEEDS);
                     input.into_iter().enumerate().for_each(|(i, s)| {
                         rb.insert(s, i.try_into().unwrap());
                     });
@@ -106,7 +115,7 @@ fn insert_random(c: &mut Criterion) {
                 for _i in 0..iters {
                     let input = strings.clone();
                     let start = Instant::now();
-                    let mut rb = Rbtree::default();
+                    let mut rb = Rbtree::new_seeded(HASH_SEEDS);
                     input.into_iter().enumerate().for_each(|(i, s)| {
                         rb.insert(s, i.try_into().unwrap());
                     });
@@ -138,7 +147,7 @@ fn sequential(c: &mut Criterion) {
                     deletes.shuffle(&mut rng);
 
                     let start = Instant::now();
-                    let mut rb = Rbtree::default();
+                    let mut rb = Rbtree::new_seeded(HASH_SEEDS);
                     input.into_iter().enumerate().for_each(|(i, s)| {
                         rb.insert(s, i.try_into().unwrap());
                     });
@@ -164,7 +173,7 @@ fn find_next(c: &mut Criterion) {
         let mut input = sequential_strings(*n);
         input.shuffle(&mut rng);
 
-        let mut rb = Rbtree::default();
+        let mut rb = Rbtree::new_seeded(HASH_SEEDS);
         input.into_iter().enumerate().for_each(|(i, s)| {
             rb.insert(s, i.try_into().unwrap());
         });
@@ -199,6 +208,155 @@ fn shuffler_next(c: &mut Criterion) {
     }
 }
 
+fn bench_u64_hasher<H>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    n: u64,
+    items: &[u64],
+    mut shuffler: ShufflerGeneric<u64, H, StdRng>,
+) where
+    H: Hasher + Clone,
+{
+    group.bench_with_input(BenchmarkId::new(format!("{name}_add"), n), &n, |b, _s| {
+        b.iter(|| {
+            for i in items {
+                let _ignored = shuffler.add(*i);
+            }
+        })
+    });
+    group.bench_with_input(BenchmarkId::new(format!("{name}_next"), n), &n, |b, _s| {
+        b.iter(|| {
+            let _ignored = shuffler.next();
+        })
+    });
+}
+
+fn identity_hasher(c: &mut Criterion) {
+    let mut group = c.benchmark_group("identity_hasher");
+
+    for n in [1000, 100_000, 1_000_000] {
+        let mut items: Vec<u64> = (0..n).collect();
+        items.shuffle(&mut rand::thread_rng());
+
+        bench_u64_hasher(&mut group, "ahash", n, &items, ShufflerBuilder::new().build());
+        let shuffler = ShufflerBuilder::new().hasher(IdentityHasher::default()).build();
+        bench_u64_hasher(&mut group, "identity", n, &items, shuffler);
+    }
+}
+
+#[cfg(feature = "rocks")]
+fn persistent_load(c: &mut Criterion) {
+    use aw_shuffle::persistent::{rocksdb, PersistentShuffler};
+
+    let mut group = c.benchmark_group("persistent_load");
+    group.sample_size(10);
+
+    for n in &[100, 1000, 10000] {
+        let dir = tempfile::tempdir().unwrap();
+        let strings = sequential_strings(*n);
+        let mut shuffler =
+            rocksdb::Shuffler::new_default(dir.path().join("db"), Some(strings.clone())).unwrap();
+
+        let mut bench = |name: &str, load: fn(&mut rocksdb::Shuffler<String>, Vec<String>)| {
+            group.bench_with_input(BenchmarkId::new(name, n), n, |b, _s| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::from_secs(0);
+
+                    for _i in 0..iters {
+                        for s in &strings {
+                            shuffler.soft_remove(s).unwrap();
+                        }
+                        let input = strings.clone();
+                        let start = Instant::now();
+                        load(&mut shuffler, input);
+                        total += start.elapsed();
+                    }
+                    total
+                })
+            });
+        };
+
+        bench("load", |shuffler, input| {
+            for s in input {
+                shuffler.load(s).unwrap();
+            }
+        });
+        bench("load_all_items", |shuffler, input| {
+            shuffler.load_all_items(input).unwrap();
+        });
+    }
+}
+
+#[cfg(feature = "rocks")]
+fn persistent_open(c: &mut Criterion) {
+    use aw_shuffle::persistent::{rocksdb, Options, PersistentShuffler};
+
+    let mut group = c.benchmark_group("persistent_open");
+    group.sample_size(10);
+
+    for n in &[10000, 100_000, 1_000_000] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        rocksdb::Shuffler::new_default(&path, Some(sequential_strings(*n)))
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let mut bench = |name: &str, options: fn(usize) -> Options| {
+            group.bench_with_input(BenchmarkId::new(name, n), n, |b, n| {
+                b.iter(|| {
+                    rocksdb::Shuffler::<String>::new(&path, options(*n), None)
+                        .unwrap()
+                        .close()
+                        .unwrap();
+                });
+            });
+        };
+
+        bench("default", |_| Options::default());
+        bench("expected_items", |n| Options::default().expected_items(n));
+    }
+}
+
+// Compares closing normally against skipping the teardown of the in-memory shuffler. Every
+// close_no_dealloc iteration leaks its shuffler, which is fine for a benchmark process.
+#[cfg(feature = "rocks")]
+fn persistent_close(c: &mut Criterion) {
+    use aw_shuffle::persistent::{rocksdb, PersistentShuffler};
+
+    let mut group = c.benchmark_group("persistent_close");
+    group.sample_size(10);
+
+    for n in &[100_000, 1_000_000] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        rocksdb::Shuffler::new_default(&path, Some(sequential_strings(*n)))
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let mut bench = |name: &str, close: fn(rocksdb::Shuffler<String>)| {
+            group.bench_with_input(BenchmarkId::new(name, n), n, |b, _n| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::from_secs(0);
+
+                    for _i in 0..iters {
+                        let shuffler =
+                            rocksdb::Shuffler::<String>::new_default(&path, None).unwrap();
+                        let start = Instant::now();
+                        close(shuffler);
+                        total += start.elapsed();
+                    }
+                    total
+                })
+            });
+        };
+
+        bench("close", |shuffler| shuffler.close().unwrap());
+        bench("close_no_dealloc", |shuffler| shuffler.close_no_dealloc().unwrap());
+    }
+}
+
 criterion_group!(
     benches,
     sequential_inserts,
@@ -207,5 +365,12 @@ criterion_group!(
     sequential,
     find_next,
     shuffler_next,
+    identity_hasher,
 );
+#[cfg(feature = "rocks")]
+criterion_group!(persistent, persistent_load, persistent_open, persistent_close);
+
+#[cfg(not(feature = "rocks"))]
 criterion_main!(benches);
+#[cfg(feature = "rocks")]
+criterion_main!(benches, persistent);
@@ -0,0 +1,176 @@
+use alloc::collections::VecDeque;
+use core::borrow::Borrow;
+
+use crate::{AwShuffler, Query};
+
+/// A wrapper around any [`AwShuffler`] that dispenses every item exactly once per cycle, like a
+/// music shuffle that won't replay a song until the whole album has been played.
+///
+/// Each cycle starts by selecting every item with [`unique_n`](AwShuffler::unique_n), so the order
+/// within a cycle is weighted the same way as the wrapped shuffler's selections. A new cycle
+/// starts automatically once every item from the previous one has been dispensed.
+///
+/// Items added mid-cycle are first dispensed in the next cycle. Items removed mid-cycle are never
+/// dispensed again.
+///
+/// ```
+/// use aw_shuffle::{NewItemHandling, ShuffleBag, Shuffler};
+///
+/// let mut bag = ShuffleBag::new(Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 1));
+/// for i in 0..3 {
+///     bag.add(i).unwrap();
+/// }
+///
+/// let mut cycle: Vec<_> = (0..3).map(|_| bag.next().unwrap().unwrap()).collect();
+/// cycle.sort_unstable();
+/// assert_eq!(cycle, [0, 1, 2]);
+/// ```
+#[derive(Debug)]
+pub struct ShuffleBag<S: AwShuffler> {
+    shuffler: S,
+    remaining: VecDeque<S::Item>,
+}
+
+impl<S> ShuffleBag<S>
+where
+    S: AwShuffler,
+    S::Item: Clone,
+{
+    /// Wraps `shuffler`. The first cycle starts on the first call to [`next`](Self::next).
+    pub const fn new(shuffler: S) -> Self {
+        Self { shuffler, remaining: VecDeque::new() }
+    }
+
+    /// Returns the next item of the current cycle, starting a new cycle first if the current one
+    /// is finished.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<S::Item>, S::Error> {
+        if self.remaining.is_empty() {
+            match self.shuffler.unique_n_owned(self.shuffler.size())? {
+                Some(items) => self.remaining = items.into(),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(self.remaining.pop_front())
+    }
+
+    /// Adds the item to the wrapped shuffler. It will not be dispensed until the next cycle.
+    ///
+    /// Returns `true` if the item was not already present.
+    pub fn add(&mut self, item: S::Item) -> Result<bool, S::Error> {
+        self.shuffler.add(item)
+    }
+
+    /// Removes the item from the wrapped shuffler and from the rest of the current cycle.
+    pub fn remove<Q>(&mut self, item: &Q) -> Result<Option<S::Item>, S::Error>
+    where
+        S::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        let removed = self.shuffler.remove(item)?;
+        if removed.is_some() {
+            self.remaining.retain(|i| i.borrow() != item);
+        }
+        Ok(removed)
+    }
+
+    /// Returns the number of items left to dispense in the current cycle.
+    pub fn remaining(&self) -> usize {
+        self.remaining.len()
+    }
+
+    /// Abandons the current cycle. The next call to [`next`](Self::next) starts a new one.
+    pub fn reset(&mut self) {
+        self.remaining.clear();
+    }
+
+    /// Returns a reference to the wrapped shuffler.
+    pub const fn shuffler(&self) -> &S {
+        &self.shuffler
+    }
+
+    /// Returns the wrapped shuffler, abandoning the current cycle.
+    pub fn into_inner(self) -> S {
+        self.shuffler
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::ShuffleBag;
+    use crate::{AwShuffler, NewItemHandling, Shuffler};
+
+    fn new_bag(n: u32) -> ShuffleBag<Shuffler<u32>> {
+        let mut bag = ShuffleBag::new(Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 3));
+        for i in 0..n {
+            assert!(bag.add(i).unwrap());
+        }
+        bag
+    }
+
+    #[test]
+    fn full_cycles() {
+        let mut bag = new_bag(20);
+
+        for _ in 0..5 {
+            let mut cycle: Vec<_> = (0..20).map(|_| bag.next().unwrap().unwrap()).collect();
+            assert_eq!(bag.remaining(), 0);
+            cycle.sort_unstable();
+            assert_eq!(cycle, (0..20).collect::<Vec<_>>());
+        }
+        assert_eq!(bag.shuffler().size(), 20);
+    }
+
+    #[test]
+    fn add_and_remove_mid_cycle() {
+        let mut bag = new_bag(10);
+
+        let mut seen = HashSet::new();
+        for _ in 0..5 {
+            seen.insert(bag.next().unwrap().unwrap());
+        }
+        assert_eq!(bag.remaining(), 5);
+
+        let unseen = (0..10).find(|i| !seen.contains(i)).unwrap();
+        assert_eq!(bag.remove(&unseen).unwrap(), Some(unseen));
+        assert_eq!(bag.remove(&unseen).unwrap(), None);
+        assert!(bag.add(10).unwrap());
+        assert!(!bag.add(*seen.iter().next().unwrap()).unwrap());
+        assert_eq!(bag.remaining(), 4);
+
+        for _ in 0..4 {
+            let item = bag.next().unwrap().unwrap();
+            assert!(item != unseen && item != 10);
+            assert!(seen.insert(item));
+        }
+        assert_eq!(seen.len(), 9);
+
+        let mut cycle: Vec<_> = (0..10).map(|_| bag.next().unwrap().unwrap()).collect();
+        cycle.sort_unstable();
+        let expected: Vec<_> = (0..=10).filter(|i| *i != unseen).collect();
+        assert_eq!(cycle, expected);
+    }
+
+    #[test]
+    fn empty_and_reset() {
+        let mut bag = new_bag(0);
+        assert_eq!(bag.next().unwrap(), None);
+
+        bag.add(1).unwrap();
+        bag.add(2).unwrap();
+        bag.next().unwrap().unwrap();
+        assert_eq!(bag.remaining(), 1);
+        bag.reset();
+        assert_eq!(bag.remaining(), 0);
+        bag.next().unwrap().unwrap();
+        assert_eq!(bag.remaining(), 1);
+
+        let mut shuffler = bag.into_inner();
+        assert_eq!(shuffler.remove(&1).unwrap(), Some(1));
+    }
+}
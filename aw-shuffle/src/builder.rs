@@ -0,0 +1,367 @@
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hasher};
+use core::marker::PhantomData;
+
+use ahash::{AHasher, RandomState};
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::rbtree::Rbtree;
+use crate::{Item, NewItemHandling, ShufflerGeneric, WrapStrategy};
+
+/// A builder for in-memory shufflers with custom settings, hashers, or random number generators.
+///
+/// Unless otherwise specified the resulting shuffler uses a randomly seeded [`AHasher`] and a
+/// [`StdRng`] seeded from the operating system, the same as [`Shuffler`](crate::Shuffler).
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use aw_shuffle::{InfallibleShuffler, NewItemHandling, ShufflerBuilder};
+///
+/// let mut shuffler = ShufflerBuilder::new()
+///     .bias(3.0)
+///     .new_item_handling(NewItemHandling::RecentlySelected)
+///     .seed(42)
+///     .build();
+///
+/// shuffler.inf_add("a");
+/// shuffler.inf_add("b");
+/// assert!(shuffler.inf_next().is_some());
+/// ```
+///
+/// Custom hashers and random number generators change the type of the resulting shuffler.
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use std::collections::hash_map::DefaultHasher;
+///
+/// use aw_shuffle::{ShufflerBuilder, ShufflerGeneric};
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let shuffler: ShufflerGeneric<u32, DefaultHasher, StdRng> = ShufflerBuilder::new()
+///     .hasher(DefaultHasher::new())
+///     .rng(StdRng::seed_from_u64(7))
+///     .build();
+/// ```
+#[derive(Debug)]
+pub struct ShufflerBuilder<T, H = AHasher, R = StdRng> {
+    bias: f64,
+    new_item_handling: NewItemHandling,
+    wrap: WrapStrategy,
+    comparator: Option<fn(&T, &T) -> Ordering>,
+    seed: Option<u64>,
+    hasher: H,
+    rng: R,
+    _item: PhantomData<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> Default for ShufflerBuilder<T> {
+    fn default() -> Self {
+        Self {
+            bias: 2.0,
+            new_item_handling: NewItemHandling::NeverSelected,
+            wrap: WrapStrategy::Wrap,
+            comparator: None,
+            seed: None,
+            hasher: RandomState::new().build_hasher(),
+            rng: StdRng::from_entropy(),
+            _item: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> ShufflerBuilder<T> {
+    /// Creates a new builder with the default settings.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, H, R> ShufflerBuilder<T, H, R> {
+    /// Controls how strongly the shuffler is biased towards older items. See
+    /// [`Shuffler::new`](crate::Shuffler::new).
+    ///
+    /// The bias is validated when calling [`build`](Self::build).
+    #[must_use]
+    pub const fn bias(mut self, bias: f64) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// See [`Shuffler::new`](crate::Shuffler::new)
+    #[must_use]
+    pub const fn new_item_handling(mut self, new_item_handling: NewItemHandling) -> Self {
+        self.new_item_handling = new_item_handling;
+        self
+    }
+
+    /// Controls what happens when no item after the randomly chosen position is old enough to be
+    /// selected. See [`WrapStrategy`].
+    #[must_use]
+    pub const fn wrap_strategy(mut self, wrap: WrapStrategy) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Orders items with identical hashes using `cmp` instead of their [`Ord`] implementation, or
+    /// instead of the order they were inserted in with [`build_hash_only`](Self::build_hash_only),
+    /// which affects the order of [`values`](crate::AwShuffler::values) and
+    /// [`dump`](crate::AwShuffler::dump).
+    ///
+    /// `cmp` must be a total order that is consistent with [`Eq`], returning
+    /// [`Ordering::Equal`] only for equal items. Otherwise it is a logic error, the same as an
+    /// inconsistent [`Ord`] implementation, and items may be lost or not found. Looking up items
+    /// with identical hashes takes time proportional to the number of items sharing the hash,
+    /// since `cmp` can't be applied to borrowed forms of items.
+    #[must_use]
+    pub const fn comparator(mut self, cmp: fn(&T, &T) -> Ordering) -> Self {
+        self.comparator = Some(cmp);
+        self
+    }
+
+    /// Uses a custom hasher for the shuffler.
+    #[must_use]
+    pub fn hasher<H2: Hasher + Clone>(self, hasher: H2) -> ShufflerBuilder<T, H2, R> {
+        ShufflerBuilder {
+            bias: self.bias,
+            new_item_handling: self.new_item_handling,
+            wrap: self.wrap,
+            comparator: self.comparator,
+            seed: self.seed,
+            hasher,
+            rng: self.rng,
+            _item: PhantomData,
+        }
+    }
+
+    /// Uses a custom random number generator for the shuffler. This replaces any seed set with
+    /// [`seed`](ShufflerBuilder::seed).
+    #[must_use]
+    pub fn rng<R2: Rng>(self, rng: R2) -> ShufflerBuilder<T, H, R2> {
+        ShufflerBuilder {
+            bias: self.bias,
+            new_item_handling: self.new_item_handling,
+            wrap: self.wrap,
+            comparator: self.comparator,
+            seed: None,
+            hasher: self.hasher,
+            rng,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T, R: SeedableRng + Rng> ShufflerBuilder<T, AHasher, R> {
+    /// Replaces both the random number generator and the hasher with ones seeded from `seed`,
+    /// the same as [`Shuffler::from_seed`](crate::Shuffler::from_seed).
+    ///
+    /// Items are ordered by their hashes, so seeding the random number generator alone is not
+    /// enough for selections to be reproducible. This replaces any hasher set previously. With a
+    /// custom hasher pass a seeded random number generator to [`rng`](Self::rng) instead.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        let mut rng = R::seed_from_u64(seed);
+        let hasher = RandomState::with_seeds(rng.gen(), rng.gen(), rng.gen(), rng.gen());
+        self.hasher = hasher.build_hasher();
+        self.rng = rng;
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl<T, H, R> ShufflerBuilder<T, H, R>
+where
+    T: Item,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    /// Builds the shuffler.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    #[must_use]
+    pub fn build(self) -> ShufflerGeneric<T, H, R>
+    where
+        T: Ord,
+    {
+        self.build_with(Rbtree::new)
+    }
+
+    /// Builds a shuffler for items that don't implement [`Ord`].
+    ///
+    /// Items with identical hashes are ordered by the [`comparator`](Self::comparator) if one was
+    /// set, otherwise by when they were inserted. Without a comparator colliding items are
+    /// order-ambiguous: their positions depend on the order they were added rather than on the
+    /// items themselves, so two shufflers with the same seed can lay them out differently, and
+    /// finding one of them takes time proportional to the number of items sharing its hash. With
+    /// a 64-bit hash this is rare in practice.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    #[must_use]
+    pub fn build_hash_only(self) -> ShufflerGeneric<T, H, R> {
+        self.build_with(Rbtree::new_hash_only)
+    }
+
+    fn build_with(self, new_tree: fn(H) -> Rbtree<T, H>) -> ShufflerGeneric<T, H, R> {
+        let tree = new_tree(self.hasher);
+        let mut shuffler =
+            ShufflerGeneric::new_custom(self.bias, self.new_item_handling, tree, self.rng);
+        shuffler.set_wrap_strategy(self.wrap);
+        if let Some(cmp) = self.comparator {
+            shuffler.set_comparator(cmp);
+        }
+        shuffler.set_seed(self.seed);
+        shuffler
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::hash::{BuildHasher, Hasher};
+    use std::panic::catch_unwind;
+
+    use ahash::RandomState;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::ShufflerBuilder;
+    use crate::{AwShuffler, Generation, NewItemHandling, Shuffler, WrapStrategy};
+
+    #[test]
+    fn seeded() {
+        let build = || {
+            let mut shuffler = ShufflerBuilder::new().seed(5).build();
+            for i in 0..100 {
+                shuffler.add(i).unwrap();
+            }
+            shuffler
+        };
+
+        let mut a = build();
+        let mut b = build();
+
+        for _ in 0..10 {
+            a.next_n(10).unwrap();
+            b.next_n(10).unwrap();
+        }
+        assert_eq!(a.dump_sorted(), b.dump_sorted());
+    }
+
+    #[test]
+    fn seeded_layout() {
+        let items: Vec<u32> = (0..100).collect();
+        let mut a = ShufflerBuilder::new().seed(9).build();
+        let hasher = RandomState::new().build_hasher();
+        let mut b = ShufflerBuilder::new().hasher(hasher).seed(9).build();
+        let mut c = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 9);
+
+        for i in &items {
+            a.add(*i).unwrap();
+            c.add(*i).unwrap();
+        }
+        for i in items.iter().rev() {
+            b.add(*i).unwrap();
+        }
+
+        // values() returns items in the order of the tree, which depends on the hasher.
+        assert_eq!(a.values(), b.values());
+        assert_eq!(a.values(), c.values());
+
+        let mut d = ShufflerBuilder::new().seed(10).build();
+        for i in &items {
+            d.add(*i).unwrap();
+        }
+        assert_ne!(a.values(), d.values());
+    }
+
+    #[test]
+    fn seed() {
+        assert_eq!(ShufflerBuilder::<u32>::new().build().seed(), None);
+        assert_eq!(ShufflerBuilder::<u32>::new().seed(8).build().seed(), Some(8));
+
+        let hasher = RandomState::new().build_hasher();
+        assert_eq!(ShufflerBuilder::<u32>::new().seed(8).hasher(hasher).build().seed(), Some(8));
+        let rng = StdRng::seed_from_u64(8);
+        assert_eq!(ShufflerBuilder::<u32>::new().seed(8).rng(rng).build().seed(), None);
+    }
+
+    #[test]
+    fn settings() {
+        let mut shuffler = ShufflerBuilder::new()
+            .bias(0.0)
+            .new_item_handling(NewItemHandling::RecentlySelected)
+            .build();
+
+        shuffler.add(0).unwrap();
+        shuffler.next().unwrap();
+        shuffler.add(1).unwrap();
+        assert_eq!(shuffler.dump_sorted(), [(&0, Generation::new(1)), (&1, Generation::new(1))]);
+        assert_eq!(shuffler.bias, 0.0);
+    }
+
+    #[test]
+    fn invalid_bias() {
+        assert!(catch_unwind(|| ShufflerBuilder::<u32>::new().bias(f64::NAN).build()).is_err());
+        assert!(catch_unwind(|| ShufflerBuilder::<u32>::new().bias(-1.0).build()).is_err());
+    }
+
+    #[test]
+    fn wrap_strategy() {
+        for wrap in [WrapStrategy::Wrap, WrapStrategy::Widen] {
+            let mut shuffler = ShufflerBuilder::new().wrap_strategy(wrap).seed(7).build();
+            assert_eq!(shuffler.wrap, wrap);
+
+            // A few very old items scattered among many recently selected ones.
+            for i in 0..200u64 {
+                let gen = if i % 50 == 0 { i } else { 1_000_000 + i };
+                shuffler.add_with_generation(i, Generation::new(gen)).unwrap();
+            }
+
+            for _ in 0..1_000 {
+                assert!(shuffler.next().unwrap().is_some());
+            }
+            shuffler.verify_integrity().unwrap();
+            assert_eq!(shuffler.size(), 200);
+        }
+    }
+
+    #[test]
+    fn comparator() {
+        // Every item collides, so they're ordered entirely by the comparator.
+        #[derive(Clone, Default)]
+        struct Colliding;
+
+        impl Hasher for Colliding {
+            fn finish(&self) -> u64 {
+                0
+            }
+
+            fn write(&mut self, _bytes: &[u8]) {}
+        }
+
+        let mut shuffler = ShufflerBuilder::new()
+            .hasher(Colliding)
+            .comparator(|a: &(u32, &str), b| b.1.cmp(a.1).then(a.0.cmp(&b.0)))
+            .rng(StdRng::seed_from_u64(4))
+            .build();
+
+        for item in [(1, "b"), (2, "a"), (1, "c"), (0, "b"), (3, "c")] {
+            assert!(shuffler.add(item).unwrap());
+        }
+        assert!(!shuffler.add((1, "b")).unwrap());
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+
+        let order: Vec<_> = shuffler.values().into_iter().copied().collect();
+        assert_eq!(order, [(1, "c"), (3, "c"), (0, "b"), (1, "b"), (2, "a")]);
+
+        assert_eq!(shuffler.remove(&(0, "b")).unwrap(), Some((0, "b")));
+        assert_eq!(shuffler.remove(&(0, "b")).unwrap(), None);
+        assert_eq!(shuffler.unique_n(4).unwrap().unwrap().len(), 4);
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+    }
+}
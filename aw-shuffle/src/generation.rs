@@ -0,0 +1,93 @@
+use core::fmt::Display;
+
+/// How recently an item was selected, relative to the other items in the same shuffler. Larger
+/// generations were selected more recently.
+///
+/// Generations are only meaningful when compared to other generations taken from the same
+/// shuffler at the same time. Shufflers reset their generations when they would overflow, see
+/// [`AwShuffler::last_reset_occurred`](crate::AwShuffler::last_reset_occurred), and
+/// [`rebase_generations`](crate::AwShuffler::rebase_generations) renumbers them, so a stored
+/// generation can't be compared with one returned after either happens. Only the order of
+/// generations and their distances from the current oldest and newest generations affect
+/// selection.
+///
+/// The raw value is available from [`get`](Self::get) for logging or storage, and
+/// [`new`](Self::new) recreates a generation, such as to restore an item with
+/// [`add_with_generation`](crate::AwShuffler::add_with_generation).
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use aw_shuffle::{AwShuffler, Generation, NewItemHandling, Shuffler};
+///
+/// let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+/// shuffler.add_with_generation("old", Generation::new(1)).unwrap();
+/// shuffler.add_with_generation("new", Generation::new(5)).unwrap();
+///
+/// let gens = shuffler.generations_of(["old", "new"].iter());
+/// assert!(gens[0] < gens[1]);
+/// assert_eq!(gens[1].map(Generation::get), Some(5));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Generation(u64);
+
+impl Generation {
+    /// Creates a generation from its raw value.
+    #[must_use]
+    pub const fn new(generation: u64) -> Self {
+        Self(generation)
+    }
+
+    /// Returns the raw value of the generation.
+    #[must_use]
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Generation {
+    fn from(generation: u64) -> Self {
+        Self(generation)
+    }
+}
+
+impl From<Generation> for u64 {
+    fn from(generation: Generation) -> Self {
+        generation.0
+    }
+}
+
+impl Display for Generation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Generation;
+
+    #[test]
+    fn round_trip() {
+        for raw in [0, 1, 12345, u64::MAX] {
+            assert_eq!(Generation::new(raw).get(), raw);
+            assert_eq!(u64::from(Generation::from(raw)), raw);
+            assert_eq!(Generation::new(raw).to_string(), raw.to_string());
+        }
+        assert_eq!(Generation::default(), Generation::new(0));
+    }
+
+    #[test]
+    fn ordering() {
+        let mut gens: Vec<_> = [5, 0, u64::MAX, 3, 3].into_iter().map(Generation::new).collect();
+        gens.sort_unstable();
+        assert_eq!(gens.into_iter().map(Generation::get).collect::<Vec<_>>(), [
+            0,
+            3,
+            3,
+            5,
+            u64::MAX
+        ]);
+        assert!(Generation::new(1) > Generation::new(0));
+        assert_eq!(Generation::new(7).max(Generation::new(2)), Generation::new(7));
+    }
+}
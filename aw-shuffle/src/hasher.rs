@@ -0,0 +1,139 @@
+use core::hash::Hasher;
+
+/// A [`Hasher`] for integer items that uses the item itself as its hash, skipping any hashing
+/// work.
+///
+/// Items are ordered by their hashes, so with this hasher they are ordered by value. Selection
+/// is fairest when an item's position is unrelated to how recently it was selected. Items with
+/// consecutive values that are added or selected together end up next to each other, and the
+/// item after such a run is selected more often than it should be. Prefer the default hasher
+/// unless hashing shows up when profiling.
+///
+/// Only the last integer written is kept, except for bytes. Other types are folded into the hash a
+/// byte at a time, which is neither fast nor well distributed.
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use aw_shuffle::{InfallibleShuffler, IdentityHasher, ShufflerBuilder, ShufflerGeneric};
+/// use rand::rngs::StdRng;
+///
+/// let mut shuffler: ShufflerGeneric<u64, IdentityHasher, StdRng> =
+///     ShufflerBuilder::new().hasher(IdentityHasher::default()).build();
+///
+/// for i in 0..10 {
+///     shuffler.inf_add(i);
+/// }
+/// assert!(shuffler.inf_next().is_some());
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*b);
+        }
+    }
+
+    // Strings end with a 0xff byte written through write_u8, so it can't replace the hash.
+    fn write_u8(&mut self, i: u8) {
+        self.write(&[i]);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.0 = u64::from(i);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0 = u64::from(i);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.0 = i as u64;
+    }
+
+    // Signed integers are stored as their two's complement bit patterns.
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_usize(i as usize);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::IdentityHasher;
+    use crate::{AwShuffler, ShufflerBuilder};
+
+    fn hash<T: Hash>(t: T) -> u64 {
+        let mut hasher = IdentityHasher::default();
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(hash(0_u64), 0);
+        assert_eq!(hash(u64::MAX), u64::MAX);
+        assert_eq!(hash(12345_u32), 12345);
+        assert_eq!(hash(7_u8), 7);
+        assert_eq!(hash(99_usize), 99);
+        assert_eq!(hash(-1_i32), u64::from(u32::MAX));
+        assert_eq!(hash(-1_i64), u64::MAX);
+        assert_ne!(hash("a"), hash("b"));
+    }
+
+    #[test]
+    fn shuffler() {
+        let mut shuffler = ShufflerBuilder::new()
+            .hasher(IdentityHasher::default())
+            .rng(StdRng::seed_from_u64(3))
+            .build();
+
+        for i in (0..1000_u64).rev() {
+            assert!(shuffler.add(i).unwrap());
+        }
+        assert!(!shuffler.add(500).unwrap());
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+
+        // Items are laid out in order of their values.
+        let values: Vec<_> = shuffler.values().into_iter().copied().collect();
+        assert_eq!(values, (0..1000).collect::<Vec<_>>());
+
+        let mut unique = shuffler.unique_n(1000).unwrap().unwrap();
+        unique.sort_unstable();
+        assert!(unique.into_iter().copied().eq(0..1000));
+
+        assert_eq!(shuffler.remove(&500).unwrap(), Some(500));
+        assert_eq!(shuffler.remove(&500).unwrap(), None);
+        assert!(matches!(shuffler.generations_of(&[499, 500])[..], [Some(_), None]));
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+    }
+}
@@ -1,6 +1,8 @@
-use std::convert::Infallible;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::convert::Infallible;
 
-use crate::{AwShuffler, Item};
+use crate::{AwShuffler, Generation, Item, Query};
 
 #[allow(clippy::module_name_repetitions)]
 /// In-memory shufflers are infallible. This interface simplifies usage when there are no
@@ -11,14 +13,70 @@ pub trait InfallibleShuffler: AwShuffler {
     /// Returns true if the item was not already present.
     fn inf_add(&mut self, item: Self::Item) -> bool;
 
+    /// Adds the item to the shuffler with the given generation, ignoring [`NewItemHandling`].
+    ///
+    /// Returns true if the item was not already present.
+    ///
+    /// [`NewItemHandling`]: crate::NewItemHandling
+    fn inf_add_with_generation(&mut self, item: Self::Item, generation: Generation) -> bool;
+
+    /// Adds the item to the shuffler, returning whether it was not already present and its
+    /// generation. See [`AwShuffler::add_or_get_generation`].
+    fn inf_add_or_get_generation(&mut self, item: Self::Item) -> (bool, Generation);
+
     /// Removes the item from the shuffler, returning it if it was present.
-    fn inf_remove(&mut self, item: &Self::Item) -> Option<Self::Item>;
+    ///
+    /// See [`AwShuffler::remove`] for the borrowed forms of items that can be used.
+    fn inf_remove<Q>(&mut self, item: &Q) -> Option<Self::Item>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized;
+
+    /// Removes the item from the shuffler, returning it and its generation if it was present.
+    fn inf_remove_with_generation<Q>(&mut self, item: &Q) -> Option<(Self::Item, Generation)>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized;
+
+    /// Replaces `old` with `new`, giving `new` the generation `old` had. See
+    /// [`AwShuffler::replace`].
+    ///
+    /// Returns true if `old` was present.
+    fn inf_replace(&mut self, old: &Self::Item, new: Self::Item) -> bool;
+
+    /// Removes every item for which `f` returns false. See [`AwShuffler::retain`].
+    fn inf_retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&Self::Item) -> bool;
 
     /// Returns the next item from the shuffler, weighted based on recency and the configured bias.
     ///
     /// Returns `None` when the shuffler is empty.
     fn inf_next(&mut self) -> Option<&Self::Item>;
 
+    /// Returns the next item from the shuffler like [`inf_next`](Self::inf_next), but using
+    /// `bias` in place of the configured bias for this selection only. The configured bias is
+    /// unchanged.
+    ///
+    /// Returns `None` when the shuffler is empty.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    fn inf_next_with_bias(&mut self, bias: f64) -> Option<&Self::Item>;
+
+    /// Returns the next item from the shuffler, only considering items whose generations are at
+    /// least `min_generation_gap` older than the newest generation. See
+    /// [`AwShuffler::next_with_floor`].
+    ///
+    /// Returns `None` when no items are old enough.
+    fn inf_next_with_floor(&mut self, min_generation_gap: u64) -> Option<&Self::Item>;
+
+    /// Returns the next item from the shuffler along with the probability it had of being
+    /// selected. See [`AwShuffler::weighted_next`].
+    ///
+    /// Returns `None` when the shuffler is empty.
+    fn inf_weighted_next(&mut self) -> Option<(&Self::Item, f64)>;
+
     /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
     /// bias. This is not quite equivalent to calling next() `n` times. As `n` grows larger with
     /// respect to the number of items being shuffled, this approaches an unweighted random
@@ -30,6 +88,12 @@ pub trait InfallibleShuffler: AwShuffler {
     /// Returns `None` when the shuffler is empty, even if `n` is 0.
     fn inf_next_n(&mut self, n: usize) -> Option<Vec<&Self::Item>>;
 
+    /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
+    /// bias. This is equivalent to calling [`inf_next`](Self::inf_next) `n` times.
+    ///
+    /// Returns `None` when the shuffler is empty, even if `n` is 0.
+    fn inf_next_n_spread(&mut self, n: usize) -> Option<Vec<&Self::Item>>;
+
     /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
     /// bias. Items are guaranteed to be unique.
     ///
@@ -40,6 +104,12 @@ pub trait InfallibleShuffler: AwShuffler {
     /// request or when the shuffler is empty, even if `n` is 0.
     fn inf_unique_n(&mut self, n: usize) -> Option<Vec<&Self::Item>>;
 
+    /// Returns the `n` items that [`inf_next_n`](Self::inf_next_n) could return without treating
+    /// them as having been selected. The shuffler is left unchanged and, like `inf_next_n`, the
+    /// returned items may contain repeats.
+    ///
+    /// Returns `None` when the shuffler is empty, even if `n` is 0.
+    fn inf_preview_n(&mut self, n: usize) -> Option<Vec<&Self::Item>>;
 
     /// Returns the next `n` unique items, if enough unique items exist, otherwise returns the next
     /// `n` items ignoring uniqueness.
@@ -49,6 +119,10 @@ pub trait InfallibleShuffler: AwShuffler {
     ///
     /// Returns `Ok(None)` when the shuffler is empty.
     fn inf_try_unique_n(&mut self, n: usize) -> Option<Vec<&Self::Item>>;
+
+    /// Compresses the generations of all items onto a dense range starting at 0, preserving their
+    /// order.
+    fn inf_rebase_generations(&mut self);
 }
 
 impl<T: Item, S> InfallibleShuffler for S
@@ -59,23 +133,78 @@ where
         self.add(item).unwrap()
     }
 
-    fn inf_remove(&mut self, item: &Self::Item) -> Option<Self::Item> {
+    fn inf_add_with_generation(&mut self, item: Self::Item, generation: Generation) -> bool {
+        self.add_with_generation(item, generation).unwrap()
+    }
+
+    fn inf_add_or_get_generation(&mut self, item: Self::Item) -> (bool, Generation) {
+        self.add_or_get_generation(item).unwrap()
+    }
+
+    fn inf_remove<Q>(&mut self, item: &Q) -> Option<Self::Item>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
         self.remove(item).unwrap()
     }
 
+    fn inf_remove_with_generation<Q>(&mut self, item: &Q) -> Option<(Self::Item, Generation)>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        self.remove_with_generation(item).unwrap()
+    }
+
+    fn inf_replace(&mut self, old: &Self::Item, new: Self::Item) -> bool {
+        self.replace(old, new).unwrap()
+    }
+
+    fn inf_retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&Self::Item) -> bool,
+    {
+        self.retain(f).unwrap();
+    }
+
     fn inf_next(&mut self) -> Option<&Self::Item> {
         self.next().unwrap()
     }
 
+    fn inf_next_with_bias(&mut self, bias: f64) -> Option<&Self::Item> {
+        self.next_with_bias(bias).unwrap()
+    }
+
+    fn inf_next_with_floor(&mut self, min_generation_gap: u64) -> Option<&Self::Item> {
+        self.next_with_floor(min_generation_gap).unwrap()
+    }
+
+    fn inf_weighted_next(&mut self) -> Option<(&Self::Item, f64)> {
+        self.weighted_next().unwrap()
+    }
+
     fn inf_next_n(&mut self, n: usize) -> Option<Vec<&Self::Item>> {
         self.next_n(n).unwrap()
     }
 
+    fn inf_next_n_spread(&mut self, n: usize) -> Option<Vec<&Self::Item>> {
+        self.next_n_spread(n).unwrap()
+    }
+
     fn inf_unique_n(&mut self, n: usize) -> Option<Vec<&Self::Item>> {
         self.unique_n(n).unwrap()
     }
 
+    fn inf_preview_n(&mut self, n: usize) -> Option<Vec<&Self::Item>> {
+        self.preview_n(n).unwrap()
+    }
+
     fn inf_try_unique_n(&mut self, n: usize) -> Option<Vec<&Self::Item>> {
         self.try_unique_n(n).unwrap()
     }
+
+    fn inf_rebase_generations(&mut self) {
+        self.rebase_generations().unwrap()
+    }
 }
@@ -1,23 +1,51 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(missing_docs)]
 #![warn(unsafe_op_in_unsafe_fn)]
-#![doc = include_str!("../../README.md")]
-use std::convert::Infallible;
-use std::error::Error;
-use std::hash::{Hash, Hasher};
-use std::num::NonZeroU64;
-
-use ahash::AHasher;
+// The README examples need the default shuffler, which is only available with std.
+#![cfg_attr(feature = "std", doc = include_str!("../../README.md"))]
+#![cfg_attr(not(feature = "std"), doc = "Weighted shuffling for `no_std` with `alloc`.")]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("Either the std or libm feature must be enabled.");
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::{BinaryHeap, VecDeque};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::{Ordering, Reverse};
+use core::convert::Infallible;
+use core::error::Error;
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::num::NonZeroU64;
+use core::ptr::NonNull;
+
+use ahash::{AHasher, RandomState};
 use rand::distributions::Uniform;
 use rand::prelude::{Distribution, StdRng};
 use rand::{Rng, SeedableRng};
-use rbtree::{Node, Rbtree};
-
+use rbtree::{Comparator, Node, Rbtree};
+#[cfg(feature = "debug-nodes")]
+pub use rbtree::DebugNode;
+pub use rbtree::IntegrityError;
+
+mod bag;
+mod builder;
+mod generation;
+mod hasher;
 mod infallible;
 #[cfg(feature = "persistent")]
 pub mod persistent;
 mod rbtree;
+mod snapshot;
 
+pub use bag::ShuffleBag;
+pub use builder::ShufflerBuilder;
+pub use generation::Generation;
+pub use hasher::IdentityHasher;
 pub use infallible::*;
+pub use snapshot::SnapshotReader;
 
 #[doc(hidden)]
 // Just for benchmarking
@@ -28,8 +56,20 @@ pub mod _secret_do_not_use {
 /// The minimum set of traits any item needs to implement for use in the shuffler.
 ///
 /// It is a logic error for an item to be mutated in a way that changes its hash or equality.
-pub trait Item: Hash + Eq + Ord {}
-impl<T: Hash + Eq + Ord> Item for T {}
+///
+/// Shufflers created with [`Shuffler::new`] and the other constructors also require [`Ord`], which
+/// must be consistent with [`Eq`]. Items with identical hashes are told apart by [`Ord`] alone, so
+/// an item that compares equal to a different item is silently not added. Debug builds panic when
+/// this happens. Use [`ShufflerBuilder::build_hash_only`] for items that don't implement [`Ord`].
+pub trait Item: Hash + Eq {}
+impl<T: Hash + Eq> Item for T {}
+
+/// The minimum set of traits a borrowed form of an item, such as `str` for `String`, needs to
+/// implement to look the item up.
+///
+/// These must behave identically on the borrowed form and on the item itself.
+pub trait Query: Hash + Eq {}
+impl<Q: Hash + Eq + ?Sized> Query for Q {}
 
 /// The generic trait all shufflers implement.
 ///
@@ -52,19 +92,124 @@ pub trait AwShuffler: private::Sealed {
     /// alternative that does read from the database.
     fn add(&mut self, item: Self::Item) -> Result<bool, Self::Error>;
 
+    /// Adds the item to the shuffler with the given generation, ignoring [`NewItemHandling`]. This
+    /// is useful for importing a known history of selections.
+    ///
+    /// Generations outside of the current range of generations will extend that range. Since
+    /// weights are based on where generations fall within that range this affects the
+    /// probabilities of all items in future selections. A generation far below the rest will make
+    /// the new item very likely to be selected while making the remaining items more uniformly
+    /// weighted, for example.
+    ///
+    /// Returns `true` if the item was not already present. If the item was already present its
+    /// generation is not changed.
+    fn add_with_generation(
+        &mut self,
+        item: Self::Item,
+        generation: Generation,
+    ) -> Result<bool, Self::Error>;
+
+    /// Adds the item to the shuffler like [`add`](Self::add), but also returns its generation.
+    ///
+    /// Returns `true` and the new item's generation if the item was not already present,
+    /// otherwise `false` and the existing item's unchanged generation.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only writes to the
+    /// database when the item is newly inserted.
+    fn add_or_get_generation(
+        &mut self,
+        item: Self::Item,
+    ) -> Result<(bool, Generation), Self::Error>;
+
     /// Removes the item from the shuffler, returning it if it was present.
     ///
     /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this immediately removes the
     /// item from the database. See
     /// [`PersistentShuffler::soft_remove`](persistent::PersistentShuffler::soft_remove) for an
     /// alternative that does retain the item in the database for the future.
-    fn remove(&mut self, item: &Self::Item) -> Result<Option<Self::Item>, Self::Error>;
+    ///
+    /// `item` may be any borrowed form of the item type, like with
+    /// [`HashMap::remove`](std::collections::HashMap::remove), but the [`Query`] traits on the
+    /// borrowed form must match those on the item type.
+    fn remove<Q>(&mut self, item: &Q) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized;
+
+    /// Removes the item from the shuffler, returning it and its generation if it was present.
+    ///
+    /// The generation can be used to preserve how recently the item was selected when moving it
+    /// to another shuffler. Otherwise this behaves exactly like [`remove`](Self::remove).
+    fn remove_with_generation<Q>(
+        &mut self,
+        item: &Q,
+    ) -> Result<Option<(Self::Item, Generation)>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized;
+
+    /// Replaces `old` with `new`, giving `new` the generation `old` had so that it keeps the same
+    /// standing as `old`. This is useful when an item changes in a way that alters its hash or
+    /// ordering but should be treated as the same item, such as a renamed file.
+    ///
+    /// Nothing is added if `old` is not present. If `new` is already present `old` is still
+    /// removed but `new` keeps its existing generation.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s `old` is removed from the
+    /// database and `new` is written in a single atomic batch.
+    ///
+    /// Returns true if `old` was present.
+    fn replace(&mut self, old: &Self::Item, new: Self::Item) -> Result<bool, Self::Error>;
+
+    /// Removes every item for which `f` returns false, like [`Vec::retain`].
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s the predicate only runs
+    /// against items in memory and all removed items are deleted from the database in a single
+    /// batch. Items that are only in the database, like soft-removed items or items kept by
+    /// [`keep_unrecognized`](persistent::Options::keep_unrecognized), are left untouched.
+    fn retain<F>(&mut self, f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&Self::Item) -> bool;
 
     /// Returns the next item from the shuffler, weighted based on recency and the configured bias.
     ///
     /// Returns `Ok(None)` when the shuffler is empty.
     fn next(&mut self) -> Result<Option<&Self::Item>, Self::Error>;
 
+    /// Returns the next item from the shuffler like [`next`](Self::next), but using `bias` in
+    /// place of the configured bias for this selection only. The configured bias is unchanged.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    fn next_with_bias(&mut self, bias: f64) -> Result<Option<&Self::Item>, Self::Error>;
+
+    /// Returns the next item from the shuffler like [`next`](Self::next), but only considers
+    /// items whose generations are at least `min_generation_gap` older than the newest
+    /// generation, so nothing selected within the last `min_generation_gap` generations can be
+    /// repeated. A gap of 0 considers every item.
+    ///
+    /// The cooldown is ignored for this selection if it would exclude every item that is old
+    /// enough.
+    ///
+    /// Returns `Ok(None)` when no items are old enough, including when the shuffler is empty.
+    fn next_with_floor(
+        &mut self,
+        min_generation_gap: u64,
+    ) -> Result<Option<&Self::Item>, Self::Error>;
+
+    /// Returns the next item from the shuffler exactly like [`next`](Self::next), along with the
+    /// probability it had of being selected. This is useful for explaining selections.
+    ///
+    /// The probability is calculated from the state of the shuffler before the selection,
+    /// including its cooldown, the positions of the items and the
+    /// [`WrapStrategy`], so the probabilities of every item always sum to 1. It is in the range
+    /// `(0, 1]`. Unlike [`weights`](Self::weights) this takes `O(n)` time.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty.
+    fn weighted_next(&mut self) -> Result<Option<(&Self::Item, f64)>, Self::Error>;
+
     /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
     /// bias. This is not quite equivalent to calling next() `n` times. As `n` grows larger with
     /// respect to the number of items being shuffled, this approaches an unweighted random
@@ -73,9 +218,26 @@ pub trait AwShuffler: private::Sealed {
     /// All the returned items will be treated as having been selected at the same time for
     /// future calls.
     ///
-    /// Returns `Ok(None)` when the shuffler is empty, even if `n` is 0.
+    /// Returns `Ok(None)` when the shuffler is empty, even if `n` is 0. Otherwise when `n` is 0
+    /// this returns an empty vector and leaves the shuffler unchanged.
+    ///
+    /// Space for all `n` items is reserved before any are selected. If `n` is too large for that
+    /// to succeed this returns `Ok(None)` and leaves the shuffler unchanged, instead of aborting
+    /// the process.
     fn next_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error>;
 
+    /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
+    /// bias. This is equivalent to calling [`next`](Self::next) `n` times.
+    ///
+    /// Unlike [`next_n`](Self::next_n), each selected item is treated as having been selected
+    /// after the previous one, so items selected earlier in the batch are strongly avoided by later
+    /// selections and repeats are much less likely. Unlike [`unique_n`](Self::unique_n) repeats
+    /// are still possible, and the shuffler doesn't need to contain `n` items.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty, even if `n` is 0, or when `n` is too large
+    /// to reserve space for, like [`next_n`](Self::next_n).
+    fn next_n_spread(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error>;
+
     /// Returns the next `n` items from the shuffler, weighted based on recency and the configured
     /// bias. Items are guaranteed to be unique.
     ///
@@ -83,9 +245,55 @@ pub trait AwShuffler: private::Sealed {
     /// calls.
     ///
     /// Returns `Ok(None)` when the shuffler does not contain enough unique items to fulfill the
-    /// request or when the shuffler is empty, even if `n` is 0.
+    /// request or when the shuffler is empty, even if `n` is 0. Otherwise when `n` is 0 this
+    /// returns an empty vector and leaves the shuffler unchanged.
     fn unique_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error>;
 
+    /// Returns the `n` items that [`next_n`](Self::next_n) could return without treating them as
+    /// having been selected. The shuffler is left unchanged and, like `next_n`, the returned items
+    /// may contain repeats.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this never writes to the
+    /// database.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty, even if `n` is 0, or when `n` is too large
+    /// to reserve space for, like [`next_n`](Self::next_n).
+    fn preview_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error>;
+
+    /// Returns an item chosen the same way [`next`](Self::next) would choose one, without
+    /// treating it as having been selected. The shuffler is left unchanged, so repeated calls
+    /// sample independently from the same distribution.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this never writes to the
+    /// database.
+    ///
+    /// Returns `None` when the shuffler is empty.
+    fn sample(&mut self) -> Option<&Self::Item>;
+
+    /// Returns `n` items sampled independently, as if by calling [`sample`](Self::sample) `n`
+    /// times. Unlike [`preview_n`](Self::preview_n) earlier samples have no influence on later
+    /// ones.
+    ///
+    /// Returns an empty vector when the shuffler is empty or when `n` is too large to reserve
+    /// space for, like [`next_n`](Self::next_n).
+    fn sample_n(&mut self, n: usize) -> Vec<&Self::Item>;
+
+    /// Picks up to `k` of the `candidates` without replacement, weighted like
+    /// [`weights`](Self::weights) for candidates already in the shuffler. Candidates that aren't
+    /// in the shuffler are weighted like its least recently selected items.
+    ///
+    /// This uses weighted reservoir sampling, so `candidates` is only iterated once. Only the
+    /// random number generator is advanced: no item is treated as selected and unknown candidates
+    /// are not added. Each repeat of a candidate is sampled separately, so it may be returned more
+    /// than once.
+    ///
+    /// Returns every candidate if there are no more than `k`. The order of the returned items is
+    /// unspecified.
+    fn sample_from<'a, I>(&mut self, candidates: I, k: usize) -> Vec<&'a Self::Item>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a;
+
     /// Returns the next `n` unique items, if enough unique items exist, otherwise returns the next
     /// `n` items ignoring uniqueness.
     ///
@@ -98,9 +306,197 @@ pub trait AwShuffler: private::Sealed {
         if s == 0 || s < n { self.next_n(n) } else { self.unique_n(n) }
     }
 
+    /// Returns a clone of the next item, selected exactly like [`next`](Self::next). Since the
+    /// result doesn't borrow the shuffler it can be held across further calls.
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty.
+    fn next_owned(&mut self) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Clone,
+    {
+        Ok(self.next()?.cloned())
+    }
+
+    /// Returns a clone of the next item like [`next_owned`](Self::next_owned), or if the
+    /// shuffler is empty adds the item returned by `f` to the shuffler and returns that instead.
+    ///
+    /// The fallback item stays in the shuffler and is selected like any other item, so it is
+    /// treated as having just been selected.
+    fn next_or_else<F>(&mut self, f: F) -> Result<Self::Item, Self::Error>
+    where
+        F: FnOnce() -> Self::Item,
+        Self::Item: Clone,
+    {
+        if self.is_empty() {
+            self.add(f())?;
+        }
+
+        Ok(self.next_owned()?.expect("shuffler cannot be empty after adding an item"))
+    }
+
+    /// Returns clones of the next `n` items, selected exactly like [`next_n`](Self::next_n).
+    ///
+    /// Returns `Ok(None)` when the shuffler is empty, even if `n` is 0.
+    fn next_n_owned(&mut self, n: usize) -> Result<Option<Vec<Self::Item>>, Self::Error>
+    where
+        Self::Item: Clone,
+    {
+        Ok(self.next_n(n)?.map(|v| v.into_iter().cloned().collect()))
+    }
+
+    /// Returns clones of the next `n` unique items, selected exactly like
+    /// [`unique_n`](Self::unique_n).
+    ///
+    /// Returns `Ok(None)` when the shuffler does not contain enough unique items to fulfill the
+    /// request or when the shuffler is empty, even if `n` is 0.
+    fn unique_n_owned(&mut self, n: usize) -> Result<Option<Vec<Self::Item>>, Self::Error>
+    where
+        Self::Item: Clone,
+    {
+        Ok(self.unique_n(n)?.map(|v| v.into_iter().cloned().collect()))
+    }
+
+    /// Returns clones of the next `n` unique items, selected exactly like
+    /// [`unique_n`](Self::unique_n), along with the generation they were given. Since the items
+    /// are selected together they all share the same generation.
+    ///
+    /// Returns `Ok(None)` when the shuffler does not contain enough unique items to fulfill the
+    /// request or when the shuffler is empty, even if `n` is 0.
+    #[allow(clippy::type_complexity)]
+    fn unique_n_with_generations(
+        &mut self,
+        n: usize,
+    ) -> Result<Option<Vec<(Self::Item, Generation)>>, Self::Error>
+    where
+        Self::Item: Clone,
+    {
+        let Some(items) = self.unique_n_owned(n)? else {
+            return Ok(None);
+        };
+        let Some(Some(gen)) = self.generations_of(items.first()).pop() else {
+            return Ok(Some(Vec::new()));
+        };
+
+        Ok(Some(items.into_iter().map(|i| (i, gen)).collect()))
+    }
+
+    /// Returns clones of the next `n` items, drawn one at a time like
+    /// [`next_owned`](Self::next_owned), as a sequence suitable for golden tests.
+    ///
+    /// The sequence is only reproducible when the shuffler is fully deterministic: created with
+    /// [`Shuffler::from_seed`] or [`Options::seed`](persistent::Options::seed), with the same
+    /// items and generations and the same prior operations.
+    ///
+    /// Returns an empty vector when the shuffler is empty.
+    fn deterministic_sequence(&mut self, n: usize) -> Result<Vec<Self::Item>, Self::Error>
+    where
+        Self::Item: Clone,
+    {
+        let mut sequence = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.next_owned()? {
+                Some(item) => sequence.push(item),
+                None => break,
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Applies a single [`Op`], discarding anything it returns. This lets a fuzz harness replay
+    /// the same sequence of operations against every kind of shuffler.
+    ///
+    /// Selections are made exactly as if the corresponding method had been called directly.
+    #[doc(hidden)]
+    fn apply_op(&mut self, op: Op<Self::Item>) -> Result<(), Self::Error> {
+        match op {
+            Op::Add(item) => {
+                self.add(item)?;
+            }
+            Op::Remove(item) => {
+                self.remove(&item)?;
+            }
+            Op::Next => {
+                self.next()?;
+            }
+            Op::NextN(n) => {
+                self.next_n(n)?;
+            }
+            Op::UniqueN(n) => {
+                self.unique_n(n)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks every present item in `items` as selected together, giving them all a single new
+    /// generation like [`next_n`](Self::next_n) does. This is useful when items are used together
+    /// outside the shuffler. Items that are not present are ignored.
+    ///
+    /// Touched items are not returned by the shuffler, so they don't enter the cooldown or count
+    /// as selections.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s the new generations are
+    /// written to the database in a single batch.
+    ///
+    /// Returns the number of distinct items that were present and touched.
+    fn touch_all<'a, I>(&mut self, items: I) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a;
+
+    /// Compresses the generations of all items onto a dense range starting at 0, preserving their
+    /// order. Items that were least recently selected remain the least recently selected and items
+    /// that shared a generation continue to share one.
+    ///
+    /// Since weights are based on the distances between generations this changes the
+    /// probabilities of future selections, but it keeps the range of generations small.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this writes every item to the
+    /// database in a single batch.
+    fn rebase_generations(&mut self) -> Result<(), Self::Error>;
+
+    /// Registers a callback that is called with each item returned by [`next`](Self::next),
+    /// [`next_n`](Self::next_n), [`unique_n`](Self::unique_n) and the other selection methods,
+    /// along with the generation it now has. Items returned more than once by a single call are
+    /// passed to the callback once per return. Registering a callback replaces any previous one.
+    ///
+    /// Callbacks are optional. Without one, selecting only costs a branch per returned item. With
+    /// one, every returned item costs a dynamic call, which is noticeable for very large
+    /// [`next_n`](Self::next_n) calls. Methods that don't treat items as selected, like
+    /// [`preview_n`](Self::preview_n), [`sample`](Self::sample) and
+    /// [`touch_all`](Self::touch_all), never call it.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s the callback is called before
+    /// the new generations are written to the database, so it can be called for a selection that
+    /// then returns an error.
+    fn on_select<F>(&mut self, f: F)
+    where
+        F: FnMut(&Self::Item, Generation) + Send + 'static;
+
+    /// Removes the most recently selected items until no more than `max` remain, returning the
+    /// number of items removed. Recently selected items are the least likely to be selected
+    /// again, so this keeps a bounded shuffler's most valuable items.
+    ///
+    /// Items that share a generation are removed in the order of the shuffler's internal tree,
+    /// which is deterministic for a given hasher, such as a shuffler created with a fixed
+    /// [`ShufflerBuilder::hasher`] or [`hash_seed`](persistent::Options::hash_seed).
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s all removed items are deleted
+    /// from the database in a single batch.
+    fn prune_to_size(&mut self, max: usize) -> Result<usize, Self::Error>;
+
     /// Returns the number of items currently in the shuffler.
     fn size(&self) -> usize;
 
+    /// Returns `true` if the shuffler contains no items.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
+    /// currently loaded in memory. See the documentation for persistent shufflers for more
+    /// information.
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
     /// Returns all of the values currently in the shuffler in no specific order.
     ///
     /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
@@ -108,6 +504,43 @@ pub trait AwShuffler: private::Sealed {
     /// information.
     fn values(&self) -> Vec<&Self::Item>;
 
+    /// Returns all of the values currently in the shuffler sorted by their [`Ord`]
+    /// implementation.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
+    /// currently loaded in memory. See the documentation for persistent shufflers for more
+    /// information.
+    fn values_sorted(&self) -> Vec<&Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        let mut values = self.values();
+        values.sort_unstable();
+        values
+    }
+
+    /// Returns every value between `lo` and `hi`, inclusive, sorted by their [`Ord`]
+    /// implementation. Returns nothing if `lo` is greater than `hi`.
+    ///
+    /// The shuffler is ordered by the hashes of its items, not their values, so this scans every
+    /// item. It takes `O(n + k log(k))` time for a shuffler with `n` items, `k` of which are in
+    /// the range.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
+    /// currently loaded in memory.
+    fn items_in_value_range(&self, lo: &Self::Item, hi: &Self::Item) -> Vec<&Self::Item>
+    where
+        Self::Item: Ord,
+    {
+        let mut values: Vec<_> = self
+            .entries()
+            .map(|(item, _)| item)
+            .filter(|item| lo <= *item && *item <= hi)
+            .collect();
+        values.sort_unstable();
+        values
+    }
+
     /// Consumes the shuffler and returns all the items in no specific order.
     ///
     /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
@@ -123,11 +556,157 @@ pub trait AwShuffler: private::Sealed {
     /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
     /// currently loaded in memory. See the documentation for persistent shufflers for more
     /// information.
-    fn dump(&self) -> Vec<(&Self::Item, u64)>;
+    fn dump(&self) -> Vec<(&Self::Item, Generation)>;
+
+    /// Returns an iterator over every item currently in the shuffler along with its generation.
+    ///
+    /// Unlike [`dump`](Self::dump) the entries are produced lazily, borrowing from the shuffler
+    /// without allocating, and this is intended to be relied on for things like building external
+    /// indexes. Every item is yielded exactly once.
+    ///
+    /// Items are yielded in the order of the shuffler's internal tree, which depends on the
+    /// hashes of the items. It is unrelated to generations or to the order items were added, can
+    /// change when items are added or removed, and may change between versions.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only includes the items
+    /// currently loaded in memory.
+    fn entries(&self) -> impl Iterator<Item = (&Self::Item, Generation)> + '_;
+
+    /// Returns all of the values currently in the shuffler and their generations, sorted by the
+    /// items' [`Ord`] implementation.
+    ///
+    /// Unlike [`dump`](Self::dump) the order is deterministic, which makes this suitable for
+    /// logging or comparing the state of two shufflers.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
+    /// currently loaded in memory. See the documentation for persistent shufflers for more
+    /// information.
+    fn dump_sorted(&self) -> Vec<(&Self::Item, Generation)>
+    where
+        Self::Item: Ord,
+    {
+        let mut dump = self.dump();
+        dump.sort_unstable_by_key(|(item, _)| *item);
+        dump
+    }
+
+    /// Returns the generation of each of `items`, in the same order, or `None` for items that are
+    /// not present.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only checks the items
+    /// currently loaded in memory.
+    fn generations_of<'a, I>(&self, items: I) -> Vec<Option<Generation>>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a;
+
+    /// Returns all of the values currently in the shuffler and their weights in no specific
+    /// order, using the same bias and generations as selection.
+    ///
+    /// Weights are raw rather than normalized: each is the probability, between 0 and 1, that the
+    /// item is old enough to be selected by a single call to [`next`](Self::next). The least
+    /// recently selected items always have a weight of 1. The actual probability of an item being
+    /// selected also depends on its position among the other items and on any cooldown, which
+    /// are not reflected in the weights.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only counts the items
+    /// currently loaded in memory.
+    fn weights(&self) -> Vec<(&Self::Item, f64)>;
+
+    /// Returns all of the values currently in the shuffler and how many times each has been
+    /// selected, in no specific order. Requires the `selection-counts` feature.
+    ///
+    /// Counts start at 0 when an item is added and increase each time the item is returned by a
+    /// method that treats it as selected, such as [`next`](Self::next),
+    /// [`next_n`](Self::next_n), or [`unique_n`](Self::unique_n). Previews and samples are not
+    /// counted.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s counts are only kept in memory
+    /// and start at 0 each time an item is loaded.
+    #[cfg(feature = "selection-counts")]
+    fn selection_counts(&self) -> Vec<(&Self::Item, u64)>;
+
+    /// Resets the selection count of every item to 0, leaving the items and their generations
+    /// unchanged. See [`selection_counts`](Self::selection_counts).
+    ///
+    /// This is a no-op unless the `selection-counts` feature is enabled.
+    fn reset_selection_counts(&mut self);
+
+    /// Returns the number of rotations the shuffler's internal red-black tree has performed while
+    /// inserting and removing items since it was created or since
+    /// [`reset_rotation_count`](Self::reset_rotation_count) was last called. Requires the
+    /// `rotation-count` feature.
+    ///
+    /// This is only intended for instrumenting and benchmarking the tree.
+    #[cfg(feature = "rotation-count")]
+    fn rotation_count(&self) -> u64;
+
+    /// Resets the count returned by [`rotation_count`](Self::rotation_count) to 0. Requires the
+    /// `rotation-count` feature.
+    #[cfg(feature = "rotation-count")]
+    fn reset_rotation_count(&mut self);
+
+    /// Returns a snapshot of every node in the shuffler's internal tree, in the order of the tree,
+    /// for debugging and visualization. Requires the `debug-nodes` feature.
+    ///
+    /// The layout of the tree is an implementation detail and may change between versions.
+    #[cfg(feature = "debug-nodes")]
+    fn debug_nodes(&self) -> Vec<DebugNode<'_, Self::Item>>;
+
+    /// Returns whether the most recent call that selected items, such as [`next`](Self::next),
+    /// [`next_n`](Self::next_n), or [`unique_n`](Self::unique_n), ran out of generations and
+    /// reset them.
+    ///
+    /// A reset rebases every item's generation to 0, preserving only which items were most
+    /// recently selected. It happens at most once every `u64::MAX` selections, but for
+    /// [`PersistentShuffler`](persistent::PersistentShuffler)s it rewrites every item in the
+    /// database, so it can be a good time to [`compact`](persistent::PersistentShuffler::compact).
+    fn last_reset_occurred(&self) -> bool;
+
+    /// Checks the internal data structures of the shuffler for consistency.
+    ///
+    /// This should never fail unless there is a bug in the shuffler or an [`Item`] has been
+    /// mutated in a way that changes its ordering. It walks every item, so it is intended for
+    /// defensive validation, such as after repairing a persistent database, rather than for
+    /// regular use.
+    ///
+    /// For [`PersistentShuffler`](persistent::PersistentShuffler)s this only checks the items
+    /// currently loaded in memory.
+    fn verify_integrity(&self) -> Result<(), IntegrityError>;
+}
+
+/// Cleanup methods for code that is generic over both in-memory and persistent shufflers.
+///
+/// For in-memory shufflers these do nothing. For
+/// [`PersistentShuffler`](persistent::PersistentShuffler)s they call
+/// [`PersistentShuffler::compact`](persistent::PersistentShuffler::compact) and
+/// [`PersistentShuffler::close`](persistent::PersistentShuffler::close).
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use aw_shuffle::{AwShuffler, MaybePersistent, NewItemHandling, Shuffler};
+///
+/// fn finish<S: MaybePersistent>(mut shuffler: S) -> Result<(), S::Error> {
+///     shuffler.compact()?;
+///     shuffler.close()
+/// }
+///
+/// let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+/// shuffler.add(1).unwrap();
+/// finish(shuffler).unwrap();
+/// ```
+pub trait MaybePersistent: AwShuffler {
+    /// Compacts the underlying storage, if there is any.
+    fn compact(&mut self) -> Result<(), Self::Error>;
+
+    /// Closes the shuffler, flushing any underlying storage.
+    fn close(self) -> Result<(), Self::Error>
+    where
+        Self: Sized;
 }
 
 mod private {
-    use std::hash::Hasher;
+    use core::hash::Hasher;
 
     use rand::Rng;
 
@@ -138,8 +717,27 @@ mod private {
     impl<T: Item, H: Hasher + Clone, R: Rng> Sealed for ShufflerGeneric<T, H, R> {}
 }
 
+/// What the shuffler does when no item at or after the randomly chosen position is old enough to
+/// be selected. See the [README](crate) for an explanation of how items are selected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WrapStrategy {
+    /// Wrap around to the start of the shuffler and keep searching for an item that is old
+    /// enough. This is the default.
+    #[default]
+    Wrap,
+    /// Relax the required age to that of the oldest item after the chosen position and select the
+    /// first such item, without wrapping.
+    ///
+    /// Since items near the end of the shuffler's internal tree have fewer items after them, they
+    /// are the ones selected when the required age is relaxed. This makes them noticeably more
+    /// likely to be selected than items near the start with the same generation. With
+    /// [`Wrap`](Self::Wrap) there is no such end-of-tree effect, though an item's chance of being
+    /// selected still depends on the generations of the items just before it.
+    Widen,
+}
+
 /// How items should be treated when they're first added to the shuffler.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NewItemHandling {
     /// Treat new items as if they had never been selected, making them very likely to be selected
     /// next. Gives new items the same weight as the least recently selected item.
@@ -152,30 +750,119 @@ pub enum NewItemHandling {
     Random,
 }
 
+/// A single operation for [`AwShuffler::apply_op`], for driving shufflers from fuzz harnesses.
+///
+/// Large values of `n` allocate accordingly, so harnesses should keep them bounded.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<T> {
+    /// Calls [`AwShuffler::add`].
+    Add(T),
+    /// Calls [`AwShuffler::remove`].
+    Remove(T),
+    /// Calls [`AwShuffler::next`].
+    Next,
+    /// Calls [`AwShuffler::next_n`].
+    NextN(usize),
+    /// Calls [`AwShuffler::unique_n`].
+    UniqueN(usize),
+}
+
+/// The error returned when given a bias that is negative or NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BiasError {
+    /// The bias was NaN.
+    NaN,
+    /// The bias was negative.
+    Negative(f64),
+}
+
+impl BiasError {
+    #[cfg(feature = "std")]
+    pub(crate) fn check(bias: f64) -> Result<(), Self> {
+        if bias.is_nan() {
+            Err(Self::NaN)
+        } else if !bias.is_sign_positive() {
+            Err(Self::Negative(bias))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl core::fmt::Display for BiasError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NaN => f.write_str("bias cannot be NaN"),
+            Self::Negative(bias) => write!(f, "bias {bias} cannot be negative"),
+        }
+    }
+}
+
+impl Error for BiasError {}
+
 /// Standard in-memory shuffler with no persistence. All data tracking how recently items were
 /// selected only lives as long as this struct.
 ///
 /// See the documentation for [`AwShuffler`] and [`InfallibleShuffler`] for more information.
-#[derive(Debug)]
 pub struct ShufflerGeneric<T, H, R> {
     pub(crate) tree: Rbtree<T, H>,
     rng: R,
     bias: f64,
     new_items: NewItemHandling,
+    wrap: WrapStrategy,
+    cooldown: usize,
+    // Hashes and tickets of the most recently returned items, oldest first. The ticket tells
+    // items with colliding hashes apart.
+    recent: VecDeque<(u64, u32)>,
+    // The last ticket given to an item entering the cooldown. Tickets wrap around, but only need
+    // to be unique among the items in the cooldown.
+    ticket: u32,
+    last_reset: bool,
+    // The seed used for rng, if it was seeded by this crate, so it can be reported.
+    seed: Option<u64>,
+    on_select: Option<SelectCallback<T>>,
+    // Lets tests exercise generation overflow without u64::MAX selections.
+    #[cfg(test)]
+    max_generation: u64,
 }
 
 
 /// Type alias for [`ShufflerGeneric`] with the default hasher and rng implementations.
 pub type Shuffler<T> = ShufflerGeneric<T, AHasher, StdRng>;
 
+type SelectCallback<T> = Box<dyn FnMut(&T, Generation) + Send>;
+
+// Only summarizes the shuffler, since printing every item would be enormous for large shufflers.
+impl<T: Item, H: Hasher + Clone, R> core::fmt::Debug for ShufflerGeneric<T, H, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ShufflerGeneric")
+            .field("size", &self.tree.size())
+            .field("generations", &self.tree.generations())
+            .field("bias", &self.bias)
+            .field("new_items", &self.new_items)
+            .finish_non_exhaustive()
+    }
+}
+
 
-impl<T: Item> Default for Shuffler<T> {
+#[cfg(feature = "std")]
+impl<T: Item + Ord> Default for Shuffler<T> {
     fn default() -> Self {
         Self {
             tree: Rbtree::default(),
             rng: StdRng::from_entropy(),
             bias: 2.0,
             new_items: NewItemHandling::NeverSelected,
+            wrap: WrapStrategy::Wrap,
+            cooldown: 0,
+            recent: VecDeque::new(),
+            ticket: 0,
+            last_reset: false,
+            seed: None,
+            on_select: None,
+            #[cfg(test)]
+            max_generation: u64::MAX,
         }
     }
 }
@@ -191,8 +878,12 @@ impl<T> Shuffler<T> {
     ///
     /// # Panics
     /// Panics if given a negative or NaN bias.
+    #[cfg(feature = "std")]
     #[must_use]
-    pub fn new(bias: f64, new_item_handling: NewItemHandling) -> Self {
+    pub fn new(bias: f64, new_item_handling: NewItemHandling) -> Self
+    where
+        T: Ord,
+    {
         assert!(!bias.is_nan(), "bias {bias} cannot be NaN.");
         assert!(bias.is_sign_positive(), "bias {bias} cannot be negative.");
 
@@ -201,7 +892,76 @@ impl<T> Shuffler<T> {
             rng: StdRng::from_entropy(),
             bias,
             new_items: new_item_handling,
+            wrap: WrapStrategy::Wrap,
+            cooldown: 0,
+            recent: VecDeque::new(),
+            ticket: 0,
+            last_reset: false,
+            seed: None,
+            on_select: None,
+            #[cfg(test)]
+            max_generation: u64::MAX,
+        }
+    }
+
+    /// Creates a new Shuffler like [`new`](Self::new), but returns an error instead of panicking
+    /// if given a negative or NaN bias.
+    #[cfg(feature = "std")]
+    pub fn try_new(bias: f64, new_item_handling: NewItemHandling) -> Result<Self, BiasError>
+    where
+        T: Ord,
+    {
+        BiasError::check(bias)?;
+        Ok(Self::new(bias, new_item_handling))
+    }
+
+    /// Creates a new Shuffler that is deterministically seeded from `seed`.
+    ///
+    /// Both the random number generator and the hasher are derived from `seed`, so two shufflers
+    /// created with the same seed and given the same sequence of operations will produce the same
+    /// results. See [`new`](Self::new) for `bias` and `new_item_handling`.
+    ///
+    /// Unlike `new` this is available without the `std` feature.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    #[must_use]
+    pub fn from_seed(bias: f64, new_item_handling: NewItemHandling, seed: u64) -> Self
+    where
+        T: Item + Ord,
+    {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let hasher = RandomState::with_seeds(rng.gen(), rng.gen(), rng.gen(), rng.gen());
+
+        let tree = Rbtree::new(hasher.build_hasher());
+        let mut shuffler = Self::new_custom(bias, new_item_handling, tree, rng);
+        shuffler.seed = Some(seed);
+        shuffler
+    }
+
+    /// Creates a new Shuffler seeded like [`from_seed`](Self::from_seed) containing every item
+    /// from `dump` with its generation, as returned by [`dump`](AwShuffler::dump).
+    ///
+    /// Rebuilding from the same dump with the same seed always produces a shuffler that makes the
+    /// same selections, which is useful for reproducing behaviour seen in a live shuffler.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN bias.
+    #[must_use]
+    pub fn from_dump(
+        dump: Vec<(T, Generation)>,
+        bias: f64,
+        new_item_handling: NewItemHandling,
+        seed: u64,
+    ) -> Self
+    where
+        T: Item + Ord,
+    {
+        let mut shuffler = Self::from_seed(bias, new_item_handling, seed);
+        for (item, gen) in dump {
+            shuffler.tree.insert(item, gen.get());
         }
+        shuffler
     }
 }
 
@@ -211,8 +971,8 @@ where
     H: Hasher + Clone,
     R: Rng,
 {
-    /// Creates a new Shuffler with a given bias and handling behaviour for new items, with a
-    /// custom hasher and Rng implementation.
+    /// Creates a new Shuffler with a given bias and handling behaviour for new items, with an
+    /// empty tree using a custom hasher and a custom Rng implementation.
     ///
     /// `bias` controls how strongly the shuffler biases itself towards less recently selected
     /// items, with larger values more strongly. `bias` must be non-negative and not a NaN value. A
@@ -223,357 +983,2669 @@ where
     /// # Panics
     /// Panics if given a negative or NaN bias.
     #[must_use]
-    #[allow(dead_code)]
-    fn new_custom(bias: f64, new_item_handling: NewItemHandling, hasher: H, rng: R) -> Self {
+    fn new_custom(
+        bias: f64,
+        new_item_handling: NewItemHandling,
+        tree: Rbtree<T, H>,
+        rng: R,
+    ) -> Self {
         assert!(!bias.is_nan(), "bias {bias} cannot be NaN.");
         assert!(bias.is_sign_positive(), "bias {bias} cannot be negative.");
 
         Self {
-            tree: Rbtree::new(hasher),
+            tree,
             rng,
             bias,
             new_items: new_item_handling,
+            wrap: WrapStrategy::Wrap,
+            cooldown: 0,
+            recent: VecDeque::new(),
+            ticket: 0,
+            last_reset: false,
+            seed: None,
+            on_select: None,
+            #[cfg(test)]
+            max_generation: u64::MAX,
         }
     }
 
-    fn add_generation(&mut self) -> u64 {
-        let (min_gen, max_gen) = self.tree.generations();
+    /// Sets the number of most recently returned items that are excluded from selection by
+    /// [`next`](AwShuffler::next), [`next_n`](AwShuffler::next_n),
+    /// [`unique_n`](AwShuffler::unique_n), and [`preview_n`](AwShuffler::preview_n). Items
+    /// returned more than once only occupy one place. The default is 0, which disables the
+    /// cooldown.
+    ///
+    /// Items are never excluded when doing so would leave too few items to satisfy a request. If
+    /// the cooldown is at least as large as the number of items, the least recently returned
+    /// items in the cooldown become eligible again, so a shuffler with `n` items and a cooldown of
+    /// `n - 1` or more will cycle through every item before repeating any.
+    ///
+    /// Items returned multiple times by a single call to `next_n` are not excluded from that call.
+    pub fn set_cooldown(&mut self, k: usize) {
+        self.cooldown = k;
+        while self.recent.len() > k {
+            self.recent.pop_front();
+        }
+    }
 
-        match self.new_items {
-            NewItemHandling::NeverSelected => min_gen,
-            NewItemHandling::RecentlySelected => max_gen,
-            // TODO -- there is an opportunity to cache this range as a Uniform for multiple uses
-            // when inserting many values at once.
-            NewItemHandling::Random => self.rng.gen_range(min_gen..=max_gen),
+    /// Returns the seed the shuffler was created with, from [`Shuffler::from_seed`] or
+    /// [`ShufflerBuilder::seed`], so it can be logged and used to reproduce its behaviour.
+    ///
+    /// Returns `None` for shufflers seeded from the operating system or given a custom random
+    /// number generator.
+    pub const fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns an immutable copy of every item and its generation that can be shared between
+    /// threads for read-only queries. Changes made to the shuffler afterwards are not reflected
+    /// in the snapshot. See [`SnapshotReader`].
+    ///
+    /// This clones every item, so it is as expensive as [`dump`](AwShuffler::dump) and cloning
+    /// the results.
+    pub fn snapshot_reader(&self) -> SnapshotReader<T>
+    where
+        T: Clone,
+    {
+        let items = self.tree.dump().into_iter().map(|(item, gen)| (item.clone(), gen)).collect();
+        SnapshotReader::new(items, self.bias, self.tree.generations())
+    }
+
+    /// Adds the item to the shuffler using a precomputed hash, avoiding hashing the item again.
+    /// Otherwise this behaves exactly like [`add`](AwShuffler::add).
+    ///
+    /// `hash` must be exactly the value the shuffler's hasher would produce for `item`: the
+    /// result of [`Hash::hash`] followed by [`Hasher::finish`] on a clone of the configured
+    /// hasher. If it isn't, the item will be stored in the wrong place and later lookups for it,
+    /// including [`add`](AwShuffler::add) and [`remove`](AwShuffler::remove), will silently fail
+    /// to find it.
+    ///
+    /// Returns `true` if the item was not already present.
+    pub fn add_prehashed(&mut self, item: T, hash: u64) -> Result<bool, Infallible> {
+        let gen = self.add_generation();
+        Ok(self.tree.reinsert(item, hash, gen))
+    }
+
+    /// Returns an iterator over the next `n` items from the shuffler, like
+    /// [`next_n`](AwShuffler::next_n), but selecting each item lazily as the iterator advances
+    /// instead of collecting every selection up front.
+    ///
+    /// The iterator yields clones of the items. References can't be handed out while later
+    /// selections are still updating the shuffler, and precomputing the selections would need an
+    /// allocation as large as the one `next_n` makes. Given the same random state the iterator
+    /// yields the same items `next_n` would return.
+    ///
+    /// Items are only treated as selected once they have been yielded, so an iterator dropped
+    /// early leaves the remaining selections unmade. Any [`on_select`](AwShuffler::on_select)
+    /// callback is called as each item is yielded. The iterator is empty if the shuffler is empty.
+    pub fn next_n_iter(&mut self, n: usize) -> impl Iterator<Item = T> + '_
+    where
+        T: Clone,
+    {
+        let size = self.tree.size();
+        if size == 0 {
+            return NextNIter {
+                shuffler: self,
+                remaining: 0,
+                next_gen: NonZeroU64::MIN,
+                index_range: Uniform::new(0, 1),
+                suspended: Vec::new(),
+            };
         }
+
+        let index_range = Uniform::new(0, size);
+        let (next_gen, _) = self.next_generation();
+        // The iterator only suspends these while making each selection.
+        let suspended = self.suspend_cooldown(1);
+        Self::resume_cooldown(suspended.clone());
+
+        NextNIter { shuffler: self, remaining: n, next_gen, index_range, suspended }
     }
 
-    fn next_generation(&mut self) -> (NonZeroU64, bool) {
-        let (_, max_gen) = self.tree.generations();
-        unsafe {
-            if max_gen != u64::MAX {
-                // trivially safe
-                (NonZeroU64::new_unchecked(max_gen + 1), false)
-            } else {
-                // This branch will almost never be taken
-                self.tree.reset();
-                (NonZeroU64::new_unchecked(1), true)
+    // Suspends the most recently returned items while leaving at least `eligible` items
+    // available.
+    fn suspend_cooldown(&mut self, eligible: usize) -> Vec<NonNull<Node<T>>> {
+        let limit = self.tree.size().saturating_sub(eligible.max(1));
+        let mut suspended = Vec::new();
+
+        for &(hash, ticket) in self.recent.iter().rev() {
+            if suspended.len() >= limit {
+                break;
+            }
+
+            if let Some(node) = self.tree.find_ticket(hash, ticket) {
+                Node::set_suspended(node, true);
+                suspended.push(node);
             }
         }
-    }
 
-    fn random_generation(&mut self) -> u64 {
-        let (min_gen, max_gen) = self.tree.generations();
-        self.random_generation_internal(min_gen, max_gen)
+        suspended
     }
 
-    fn random_generation_below(&mut self, limit: NonZeroU64) -> u64 {
-        let (min_gen, mut max_gen) = self.tree.generations();
-        if max_gen == limit.get() {
-            max_gen = limit.get() - 1;
-            assert!(max_gen >= min_gen);
+    fn resume_cooldown(suspended: Vec<NonNull<Node<T>>>) {
+        for node in suspended {
+            Node::set_suspended(node, false);
         }
-        self.random_generation_internal(min_gen, max_gen)
     }
 
-    fn random_generation_internal(&mut self, min_gen: u64, max_gen: u64) -> u64 {
-        if min_gen == max_gen {
-            return max_gen;
+    fn cool_down(&mut self, node: NonNull<Node<T>>) {
+        if self.cooldown == 0 {
+            return;
         }
 
-        let span = max_gen - min_gen;
-        // Generates in the range [0, 1)
-        let biased = self.rng.gen::<f64>().powf(self.bias);
-        let mut offset = (span.saturating_add(1) as f64 * biased).floor() as u64;
+        let entry = unsafe { (node.as_ref().hash(), node.as_ref().ticket()) };
+        self.recent.retain(|e| *e != entry);
 
-        if offset > span {
-            // Should never happen
-            offset = span;
+        // Zero is left for nodes that have never been in the cooldown.
+        self.ticket = self.ticket.checked_add(1).unwrap_or(1);
+        Node::set_ticket(node, self.ticket);
+        self.recent.push_back((entry.0, self.ticket));
+        if self.recent.len() > self.cooldown {
+            self.recent.pop_front();
         }
+    }
 
-        min_gen + offset
+    pub(crate) fn set_wrap_strategy(&mut self, wrap: WrapStrategy) {
+        self.wrap = wrap;
     }
-}
 
-impl<T, H, R> AwShuffler for ShufflerGeneric<T, H, R>
-where
-    T: Item,
-    H: Hasher + Clone,
-    R: Rng,
-{
-    type Error = Infallible;
-    type Item = T;
+    pub(crate) fn set_comparator(&mut self, cmp: Comparator<T>) {
+        self.tree.set_comparator(cmp);
+    }
 
-    fn add(&mut self, item: Self::Item) -> Result<bool, Self::Error> {
-        let gen = self.add_generation();
+    pub(crate) fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    fn find_next(&self, index: usize, gen: u64) -> NonNull<Node<T>> {
+        match self.wrap {
+            WrapStrategy::Wrap => self.tree.find_next(index, gen),
+            WrapStrategy::Widen => self.tree.find_next_widening(index, gen),
+        }
+    }
+
+    // Selecting the only item again can't change how it is weighted, so its generation is left
+    // alone to avoid a write per selection for persistent shufflers. An item that has never been
+    // selected still gets a new generation the first time.
+    pub(crate) fn reselects_only_item(&self) -> bool {
+        self.tree.size() == 1 && self.tree.generations().1 != 0
+    }
+
+    // Selects the only item n times without changing its generation, for next_n and unique_n.
+    fn reselect_only_item(&mut self, n: usize, selected: &mut Vec<NonNull<Node<T>>>) {
+        let node = self.find_next(0, u64::MAX);
+        let gen = unsafe { node.as_ref() }.generation();
+        for _ in 0..n {
+            Node::select(node, gen);
+            selected.push(node);
+        }
+
+        self.last_reset = false;
+        self.cool_down(node);
+        self.notify_selected(selected);
+    }
+
+    // Selects and updates a single node, returning it and whether the generations were reset.
+    fn select_next(&mut self) -> (NonNull<Node<T>>, bool) {
+        self.select_next_at_most(u64::MAX, None)
+    }
+
+    // Like select_next, but only selects items with generations no greater than ceiling. At least
+    // one item must be eligible. If given, probability is set to the probability the selected
+    // node had of being selected, which is left alone when there was only one choice.
+    fn select_next_at_most(
+        &mut self,
+        ceiling: u64,
+        probability: Option<&mut f64>,
+    ) -> (NonNull<Node<T>>, bool) {
+        if self.reselects_only_item() {
+            let node = self.find_next(0, u64::MAX);
+            Node::select(node, unsafe { node.as_ref() }.generation());
+            self.last_reset = false;
+            self.notify_selected(&[node]);
+            return (node, false);
+        }
+
+        let size = self.tree.size();
+        let mut suspended = self.suspend_cooldown(1);
+        if self.tree.generations().0 > ceiling {
+            // The cooldown excluded every eligible item, so ignore it for this selection.
+            Self::resume_cooldown(suspended);
+            suspended = Vec::new();
+        }
+
+        let (min_gen, max_gen) = self.tree.generations();
+        let random_gen = self.random_generation_internal(min_gen, max_gen.min(ceiling));
+        let index = self.rng.gen_range(0..size);
+
+        let node = self.find_next(index, random_gen);
+        if let Some(probability) = probability {
+            *probability = self.selection_probability(node, (min_gen, max_gen.min(ceiling)));
+        }
+        Self::resume_cooldown(suspended);
+
+        let (next_gen, reset) = self.next_generation();
+
+        Node::select(node, next_gen.get());
+        self.cool_down(node);
+        self.notify_selected(&[node]);
+
+        (node, reset)
+    }
+
+    // The probability that a selection from the tree as it is now, with a random generation
+    // between min_gen and max_gen, would have found node. This follows find_next: a selection
+    // starting at an index finds node when every unsuspended item from the index up to node is
+    // too new. Takes O(n) time.
+    fn selection_probability(
+        &self,
+        node: NonNull<Node<T>>,
+        (min_gen, max_gen): (u64, u64),
+    ) -> f64 {
+        let (gens, pos) = self.tree.selectable_generations(node);
+        // The probability that the random generation is at least gen, see weight. Suspended items
+        // are never eligible.
+        let at_least = |gen: Option<u64>| match gen {
+            Some(gen) if gen <= min_gen => 1.0,
+            Some(gen) if gen <= max_gen => weight(self.bias, min_gen, max_gen, gen),
+            _ => 0.0,
+        };
+        let oldest = |a: Option<u64>, b: Option<u64>| a.into_iter().chain(b).min();
+
+        let Some(gen) = gens[pos] else {
+            return 0.0;
+        };
+        let eligible = at_least(Some(gen));
+        let mut total = 0.0;
+        // The oldest generation between the starting index and node.
+        let mut between = None;
+
+        match self.wrap {
+            WrapStrategy::Wrap => {
+                for i in (0..gens.len()).map(|i| (pos + gens.len() - i) % gens.len()) {
+                    between = if i == pos { None } else { oldest(between, gens[i]) };
+                    if between.is_some_and(|b| b <= gen) {
+                        break;
+                    }
+                    total += eligible - at_least(between);
+                }
+            }
+            WrapStrategy::Widen => {
+                // When nothing from the index onwards is eligible, the first of the oldest items
+                // after the index is selected instead.
+                let after = gens[pos + 1..].iter().flatten().min().copied();
+                let widened = if after.is_some_and(|a| a < gen) { 0.0 } else { 1.0 - eligible };
+
+                for i in (0..=pos).rev() {
+                    between = if i == pos { None } else { oldest(between, gens[i]) };
+                    if between.is_some_and(|b| b <= gen) {
+                        break;
+                    }
+                    total += eligible - at_least(between) + widened;
+                }
+
+                // Starting after every unsuspended item wraps around without widening.
+                let before = gens[..pos].iter().flatten().min().copied();
+                if before.is_none_or(|b| b > gen) {
+                    let wrapped = gens[pos + 1..].iter().rev().take_while(|g| g.is_none()).count();
+                    total += wrapped as f64 * (eligible - at_least(before));
+                }
+            }
+        }
+
+        (total / gens.len() as f64).min(1.0)
+    }
+
+    fn notify_selected(&mut self, selected: &[NonNull<Node<T>>]) {
+        if let Some(f) = &mut self.on_select {
+            for node in selected {
+                let node = unsafe { node.as_ref() };
+                f(node.get(), Generation::new(node.generation()));
+            }
+        }
+    }
+
+    // The newest generation old enough to be selected by next_with_floor, or None if no items are
+    // old enough.
+    pub(crate) fn floor_ceiling(&self, min_generation_gap: u64) -> Option<u64> {
+        if self.tree.size() == 0 {
+            return None;
+        }
+
+        let (min_gen, max_gen) = self.tree.generations();
+        max_gen.checked_sub(min_generation_gap).filter(|ceiling| *ceiling >= min_gen)
+    }
+
+    // Removes the newest items until no more than max remain, returning them.
+    pub(crate) fn prune_items(&mut self, max: usize) -> Vec<T> {
+        let mut removed = Vec::with_capacity(self.tree.size().saturating_sub(max));
+        while self.tree.size() > max {
+            removed.extend(self.tree.pop_newest().map(|(item, _)| item));
+        }
+        removed
+    }
+
+    // Returns the selected nodes and whether the generations were reset at any point.
+    pub(crate) fn next_n_spread_nodes(
+        &mut self,
+        n: usize,
+    ) -> Option<(Vec<NonNull<Node<T>>>, bool)> {
+        if self.tree.size() == 0 {
+            return None;
+        }
+
+        let mut selected = try_with_capacity(n)?;
+        let mut any_reset = false;
+
+        for _ in 0..n {
+            let (node, reset) = self.select_next();
+            selected.push(node);
+            any_reset |= reset;
+        }
+
+        self.last_reset = any_reset;
+        Some((selected, any_reset))
+    }
+
+    // Gives every present item one new generation, returning the distinct touched nodes and
+    // whether the generations were reset. Returns None if no items were present.
+    pub(crate) fn touch_nodes<'a, I>(&mut self, items: I) -> Option<(Vec<NonNull<Node<T>>>, bool)>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let mut touched: Vec<_> =
+            items.into_iter().filter_map(|i| self.tree.find_node(i)).collect();
+        if touched.is_empty() {
+            return None;
+        }
+        touched.sort_unstable();
+        touched.dedup();
+
+        let (next_gen, reset) = self.next_generation();
+        for node in &touched {
+            Node::set_generation(*node, next_gen.get());
+        }
+
+        Some((touched, reset))
+    }
+
+    fn add_generation(&mut self) -> u64 {
+        let (min_gen, max_gen) = self.tree.generations();
+
+        match self.new_items {
+            NewItemHandling::NeverSelected => min_gen,
+            NewItemHandling::RecentlySelected => max_gen,
+            NewItemHandling::Random => self.rng.gen_range(min_gen..=max_gen),
+        }
+    }
+
+    fn next_generation(&mut self) -> (NonZeroU64, bool) {
+        let (_, max_gen) = self.tree.generations();
+
+        #[cfg(not(test))]
+        let limit = u64::MAX;
+        #[cfg(test)]
+        let limit = self.max_generation;
+
+        self.last_reset = max_gen >= limit;
+        unsafe {
+            if max_gen < limit {
+                // trivially safe
+                (NonZeroU64::new_unchecked(max_gen + 1), false)
+            } else {
+                // This branch will almost never be taken
+                self.tree.reset();
+                (NonZeroU64::new_unchecked(1), true)
+            }
+        }
+    }
+
+    fn random_generation(&mut self) -> u64 {
+        let (min_gen, max_gen) = self.tree.generations();
+        self.random_generation_internal(min_gen, max_gen)
+    }
+
+    // The probability that an item with this generation is old enough to be selected, see
+    // weights.
+    fn weight(&self, gen: u64) -> f64 {
+        let (min_gen, max_gen) = self.tree.generations();
+        weight(self.bias, min_gen, max_gen, gen)
+    }
+
+    fn random_generation_below(&mut self, limit: NonZeroU64) -> u64 {
+        let (min_gen, mut max_gen) = self.tree.generations();
+        if max_gen == limit.get() {
+            max_gen = limit.get() - 1;
+            assert!(max_gen >= min_gen);
+        }
+        self.random_generation_internal(min_gen, max_gen)
+    }
+
+    fn random_generation_internal(&mut self, min_gen: u64, max_gen: u64) -> u64 {
+        random_generation(&mut self.rng, self.bias, min_gen, max_gen)
+    }
+}
+
+// Picks the generation an item must be at least as old as to be selected, between min_gen and
+// max_gen and biased towards min_gen.
+fn random_generation<R: Rng + ?Sized>(rng: &mut R, bias: f64, min_gen: u64, max_gen: u64) -> u64 {
+    if min_gen == max_gen {
+        return max_gen;
+    }
+
+    let span = max_gen - min_gen;
+    // Generates in the range [0, 1)
+    let biased = powf(rng.gen::<f64>(), bias);
+    // Truncation floors the non-negative product without needing std.
+    let mut offset = (span.saturating_add(1) as f64 * biased) as u64;
+
+    if offset > span {
+        // Should never happen
+        offset = span;
+    }
+
+    min_gen + offset
+}
+
+// The probability that an item with generation gen, between min_gen and max_gen, is old enough
+// to be selected. An item is eligible when the random generation is at least its own, see
+// random_generation. The oldest items are always eligible.
+fn weight(bias: f64, min_gen: u64, max_gen: u64, gen: u64) -> f64 {
+    let span = (max_gen - min_gen).saturating_add(1) as f64;
+
+    if gen == min_gen {
+        1.0
+    } else {
+        1.0 - powf((gen - min_gen) as f64 / span, 1.0 / bias)
+    }
+}
+
+// f64::powf needs std, libm provides the same function for no_std builds.
+#[cfg(feature = "std")]
+fn powf(x: f64, y: f64) -> f64 {
+    x.powf(y)
+}
+
+#[cfg(not(feature = "std"))]
+fn powf(x: f64, y: f64) -> f64 {
+    libm::pow(x, y)
+}
+
+// Reserves space for n results up front so absurdly large requests fail cleanly, before anything
+// has been changed, instead of aborting the process when the allocation fails.
+fn try_with_capacity<V>(n: usize) -> Option<Vec<V>> {
+    let mut v = Vec::new();
+    v.try_reserve_exact(n).ok()?;
+    Some(v)
+}
+
+// A candidate in sample_from's reservoir, ordered only by its key.
+struct ReservoirKey<'a, T>(f64, &'a T);
+
+impl<T> PartialEq for ReservoirKey<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for ReservoirKey<'_, T> {}
+
+impl<T> PartialOrd for ReservoirKey<'_, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for ReservoirKey<'_, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Makes the same selections as next_n one at a time. The items suspended by the cooldown are only
+// suspended during each call to next, so nothing is left suspended if the iterator is leaked.
+struct NextNIter<'a, T, H, R>
+where
+    T: Item,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    shuffler: &'a mut ShufflerGeneric<T, H, R>,
+    remaining: usize,
+    next_gen: NonZeroU64,
+    index_range: Uniform<usize>,
+    suspended: Vec<NonNull<Node<T>>>,
+}
+
+impl<T, H, R> Iterator for NextNIter<'_, T, H, R>
+where
+    T: Item + Clone,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let shuffler = &mut *self.shuffler;
+        for node in &self.suspended {
+            Node::set_suspended(*node, true);
+        }
+
+        let random_gen = shuffler.random_generation();
+        let index = self.index_range.sample(&mut shuffler.rng);
+
+        let node = shuffler.find_next(index, random_gen);
+        Node::select(node, self.next_gen.get());
+        for node in &self.suspended {
+            Node::set_suspended(*node, false);
+        }
+        // Only affects future calls, the suspended nodes were already chosen.
+        shuffler.cool_down(node);
+        shuffler.notify_selected(&[node]);
+
+        Some(unsafe { node.as_ref() }.get().clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// Adds every item as if by calling [`add`](AwShuffler::add) on each of them.
+impl<T, H, R> Extend<T> for ShufflerGeneric<T, H, R>
+where
+    T: Item,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        // New items are always given generations within the current range, so the range can be
+        // computed once for the whole batch.
+        let (min_gen, max_gen) = self.tree.generations();
+        let range = Uniform::new_inclusive(min_gen, max_gen);
+
+        for item in iter {
+            let gen = match self.new_items {
+                NewItemHandling::NeverSelected => min_gen,
+                NewItemHandling::RecentlySelected => max_gen,
+                NewItemHandling::Random => range.sample(&mut self.rng),
+            };
+
+            self.tree.insert(item, gen);
+        }
+    }
+}
+
+impl<T, H, R> MaybePersistent for ShufflerGeneric<T, H, R>
+where
+    T: Item,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    fn compact(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn close(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Creates a [`Shuffler`] with the default settings containing every item. Duplicate items are
+/// only added once.
+#[cfg(feature = "std")]
+impl<T: Item + Ord> FromIterator<T> for Shuffler<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut shuffler = Self::default();
+        shuffler.extend(iter);
+        shuffler
+    }
+}
+
+impl<T, H, R> AwShuffler for ShufflerGeneric<T, H, R>
+where
+    T: Item,
+    H: Hasher + Clone,
+    R: Rng,
+{
+    type Error = Infallible;
+    type Item = T;
+
+    fn add(&mut self, item: Self::Item) -> Result<bool, Self::Error> {
+        let gen = self.add_generation();
         Ok(self.tree.insert(item, gen))
     }
 
-    fn remove(&mut self, item: &Self::Item) -> Result<Option<Self::Item>, Self::Error> {
-        let removed = self.tree.delete(item).map(|(removed, _)| removed);
-        Ok(removed)
+    fn add_with_generation(
+        &mut self,
+        item: Self::Item,
+        generation: Generation,
+    ) -> Result<bool, Self::Error> {
+        Ok(self.tree.insert(item, generation.get()))
+    }
+
+    fn add_or_get_generation(
+        &mut self,
+        item: Self::Item,
+    ) -> Result<(bool, Generation), Self::Error> {
+        if let Some(node) = self.tree.find_node(&item) {
+            return Ok((false, Generation::new(unsafe { node.as_ref().generation() })));
+        }
+
+        let gen = self.add_generation();
+        self.tree.insert(item, gen);
+        Ok((true, Generation::new(gen)))
+    }
+
+    fn remove<Q>(&mut self, item: &Q) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        let removed = self.tree.delete(item).map(|(removed, _)| removed);
+        Ok(removed)
+    }
+
+    fn remove_with_generation<Q>(
+        &mut self,
+        item: &Q,
+    ) -> Result<Option<(Self::Item, Generation)>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        Ok(self.tree.delete(item).map(|(item, gen)| (item, Generation::new(gen))))
+    }
+
+    fn replace(&mut self, old: &Self::Item, new: Self::Item) -> Result<bool, Self::Error> {
+        let Some((_, gen)) = self.tree.delete(old) else {
+            return Ok(false);
+        };
+
+        self.tree.insert(new, gen);
+        Ok(true)
+    }
+
+    fn retain<F>(&mut self, f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&Self::Item) -> bool,
+    {
+        self.tree.retain(f);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<&Self::Item>, Self::Error> {
+        let size = self.tree.size();
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let (node, _) = self.select_next();
+
+        unsafe { Ok(Some(node.as_ref().get())) }
+    }
+
+    fn next_with_bias(&mut self, bias: f64) -> Result<Option<&Self::Item>, Self::Error> {
+        assert!(!bias.is_nan(), "bias {bias} cannot be NaN.");
+        assert!(bias.is_sign_positive(), "bias {bias} cannot be negative.");
+
+        let size = self.tree.size();
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let configured = core::mem::replace(&mut self.bias, bias);
+        let (node, _) = self.select_next();
+        self.bias = configured;
+
+        unsafe { Ok(Some(node.as_ref().get())) }
+    }
+
+    fn next_with_floor(
+        &mut self,
+        min_generation_gap: u64,
+    ) -> Result<Option<&Self::Item>, Self::Error> {
+        let Some(ceiling) = self.floor_ceiling(min_generation_gap) else {
+            return Ok(None);
+        };
+
+        let (node, _) = self.select_next_at_most(ceiling, None);
+
+        unsafe { Ok(Some(node.as_ref().get())) }
+    }
+
+    fn weighted_next(&mut self) -> Result<Option<(&Self::Item, f64)>, Self::Error> {
+        if self.tree.size() == 0 {
+            return Ok(None);
+        }
+
+        let mut probability = 1.0;
+        let (node, _) = self.select_next_at_most(u64::MAX, Some(&mut probability));
+
+        unsafe { Ok(Some((node.as_ref().get(), probability))) }
+    }
+
+    fn next_n_spread(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        let Some((selected, _)) = self.next_n_spread_nodes(n) else {
+            return Ok(None);
+        };
+
+        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+
+        Ok(Some(output))
+    }
+
+    fn next_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        let size = self.tree.size();
+        if size == 0 {
+            return Ok(None);
+        }
+        if n == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let Some(mut selected) = try_with_capacity(n) else {
+            return Ok(None);
+        };
+
+        let index_range = Uniform::new(0, size);
+
+        if self.reselects_only_item() {
+            self.reselect_only_item(n, &mut selected);
+            return Ok(Some(selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect()));
+        }
+
+        let (next_gen, _) = self.next_generation();
+        // It's possible to have reset the tree here but it's not worth optimizing for.
+
+        let suspended = self.suspend_cooldown(1);
+
+        for _ in 0..n {
+            let random_gen = self.random_generation();
+            let index = index_range.sample(&mut self.rng);
+
+            let node = self.find_next(index, random_gen);
+
+            // Set the generation here to try to prioritize other items.
+            Node::select(node, next_gen.get());
+
+            selected.push(node)
+        }
+
+        Self::resume_cooldown(suspended);
+        for node in &selected {
+            self.cool_down(*node);
+        }
+        self.notify_selected(&selected);
+
+        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+
+        Ok(Some(output))
+    }
+
+    fn unique_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        let size = self.tree.size();
+        if size == 0 || size < n {
+            return Ok(None);
+        }
+        if n == 0 {
+            return Ok(Some(Vec::new()));
+        }
+
+        let index_range = Uniform::new(0, size);
+        let mut selected = Vec::with_capacity(n);
+
+        if self.reselects_only_item() {
+            self.reselect_only_item(n, &mut selected);
+            return Ok(Some(selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect()));
+        }
+
+        let (next_gen, _) = self.next_generation();
+        // It's possible to have reset the tree here but it's not worth optimizing for.
+
+        let suspended = self.suspend_cooldown(n);
+
+        for _ in 0..n {
+            let random_gen = self.random_generation_below(next_gen);
+            let index = index_range.sample(&mut self.rng);
+
+            let node = self.find_next(index, random_gen);
+
+            // Suspend selected items so they can't be selected again. The cooldown leaves at least
+            // n items unsuspended so there's always another item to select.
+            Node::select(node, next_gen.get());
+            Node::set_suspended(node, true);
+
+            selected.push(node)
+        }
+
+        Self::resume_cooldown(suspended);
+        for node in &selected {
+            Node::set_suspended(*node, false);
+            self.cool_down(*node);
+        }
+        self.notify_selected(&selected);
+
+        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+
+        Ok(Some(output))
+    }
+
+    fn preview_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        let size = self.tree.size();
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let Some(mut selected) = try_with_capacity(n) else {
+            return Ok(None);
+        };
+        let Some(mut original) = try_with_capacity(n) else {
+            return Ok(None);
+        };
+
+        let index_range = Uniform::new(0, size);
+
+        // Don't call next_generation() since it could reset the tree.
+        let (_, max_gen) = self.tree.generations();
+        let next_gen = max_gen.saturating_add(1);
+
+        let suspended = self.suspend_cooldown(1);
+
+        for _ in 0..n {
+            let random_gen = self.random_generation();
+            let index = index_range.sample(&mut self.rng);
+
+            let node = self.find_next(index, random_gen);
+
+            // Temporarily set the generation so this behaves the same as next_n().
+            original.push((node, unsafe { node.as_ref().generation() }));
+            Node::set_generation(node, next_gen);
+
+            selected.push(node)
+        }
+
+        // Restore in reverse order so items selected more than once end up with their original
+        // generation.
+        for (node, gen) in original.into_iter().rev() {
+            Node::set_generation(node, gen);
+        }
+        Self::resume_cooldown(suspended);
+
+        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+
+        Ok(Some(output))
+    }
+
+    fn sample(&mut self) -> Option<&Self::Item> {
+        let size = self.tree.size();
+        if size == 0 {
+            return None;
+        }
+
+        let suspended = self.suspend_cooldown(1);
+
+        let random_gen = self.random_generation();
+        let index = self.rng.gen_range(0..size);
+        let node = self.find_next(index, random_gen);
+
+        Self::resume_cooldown(suspended);
+
+        unsafe { Some(node.as_ref().get()) }
+    }
+
+    fn sample_n(&mut self, n: usize) -> Vec<&Self::Item> {
+        let size = self.tree.size();
+        if size == 0 {
+            return Vec::new();
+        }
+
+        let Some(mut selected) = try_with_capacity(n) else {
+            return Vec::new();
+        };
+
+        let index_range = Uniform::new(0, size);
+
+        let suspended = self.suspend_cooldown(1);
+
+        for _ in 0..n {
+            let random_gen = self.random_generation();
+            let index = index_range.sample(&mut self.rng);
+
+            selected.push(self.find_next(index, random_gen));
+        }
+
+        Self::resume_cooldown(suspended);
+
+        selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect()
+    }
+
+    fn touch_all<'a, I>(&mut self, items: I) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        Ok(self.touch_nodes(items).map_or(0, |(touched, ..)| touched.len()))
+    }
+
+    fn rebase_generations(&mut self) -> Result<(), Self::Error> {
+        self.tree.rebase();
+        Ok(())
+    }
+
+    fn prune_to_size(&mut self, max: usize) -> Result<usize, Self::Error> {
+        Ok(self.prune_items(max).len())
+    }
+
+    fn on_select<F>(&mut self, f: F)
+    where
+        F: FnMut(&Self::Item, Generation) + Send + 'static,
+    {
+        self.on_select = Some(Box::new(f));
+    }
+
+    fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    fn values(&self) -> Vec<&Self::Item> {
+        self.tree.values()
+    }
+
+    fn into_values(self) -> Vec<Self::Item> {
+        self.tree.into_values()
+    }
+
+    fn dump(&self) -> Vec<(&Self::Item, Generation)> {
+        self.entries().collect()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&Self::Item, Generation)> + '_ {
+        self.tree.entries().map(|(item, gen)| (item, Generation::new(gen)))
+    }
+
+    fn generations_of<'a, I>(&self, items: I) -> Vec<Option<Generation>>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        items
+            .into_iter()
+            .map(|item| self.tree.find_node(item).map(|n| unsafe { n.as_ref().generation() }))
+            .map(|gen| gen.map(Generation::new))
+            .collect()
+    }
+
+    fn sample_from<'a, I>(&mut self, candidates: I, k: usize) -> Vec<&'a Self::Item>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        // Efraimidis and Spirakis' A-Res: keep the k candidates with the largest u^(1/weight).
+        let mut reservoir = BinaryHeap::with_capacity(k);
+        for item in candidates {
+            let weight = match self.tree.find_node(item) {
+                Some(node) => self.weight(unsafe { node.as_ref().generation() }),
+                None => 1.0,
+            };
+            let key = Reverse(ReservoirKey(powf(self.rng.gen::<f64>(), 1.0 / weight), item));
+
+            if reservoir.len() < k {
+                reservoir.push(key);
+            } else if reservoir.peek().is_some_and(|min| key < *min) {
+                reservoir.pop();
+                reservoir.push(key);
+            }
+        }
+
+        reservoir.into_iter().map(|Reverse(ReservoirKey(_, item))| item).collect()
+    }
+
+    fn weights(&self) -> Vec<(&Self::Item, f64)> {
+        self.tree.dump().into_iter().map(|(item, gen)| (item, self.weight(gen))).collect()
+    }
+
+    #[cfg(feature = "selection-counts")]
+    fn selection_counts(&self) -> Vec<(&Self::Item, u64)> {
+        self.tree.selection_counts()
+    }
+
+    fn reset_selection_counts(&mut self) {
+        self.tree.reset_selection_counts();
+    }
+
+    #[cfg(feature = "rotation-count")]
+    fn rotation_count(&self) -> u64 {
+        self.tree.rotation_count()
+    }
+
+    #[cfg(feature = "rotation-count")]
+    fn reset_rotation_count(&mut self) {
+        self.tree.reset_rotation_count();
+    }
+
+    #[cfg(feature = "debug-nodes")]
+    fn debug_nodes(&self) -> Vec<DebugNode<'_, Self::Item>> {
+        self.tree.debug_nodes()
+    }
+
+    fn last_reset_occurred(&self) -> bool {
+        self.last_reset
+    }
+
+    fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        self.tree.verify_integrity()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod tests {
+    use std::collections::{HashSet, VecDeque};
+    use std::hash::BuildHasher;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+
+    use crate::rbtree::tests::DummyHasher;
+    use crate::rbtree::Rbtree;
+    use crate::{
+        AwShuffler, BiasError, Generation, InfallibleShuffler, IntegrityError, NewItemHandling, Op,
+        Shuffler, ShufflerBuilder, ShufflerGeneric, WrapStrategy,
+    };
+
+
+    #[derive(Default)]
+    struct DummyRandom {
+        vals: Vec<u64>,
+        index: usize,
+    }
+
+    impl RngCore for DummyRandom {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            if self.vals.is_empty() {
+                return 0;
+            }
+            let v = self.vals[self.index];
+            self.index = (self.index + 1) % self.vals.len();
+            v
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn new_default_leftmost_oldest() -> ShufflerGeneric<&'static str, DummyHasher, DummyRandom> {
+        ShufflerGeneric {
+            tree: Rbtree::new_dummy(&[]),
+            rng: DummyRandom::default(),
+            bias: f64::INFINITY,
+            new_items: NewItemHandling::NeverSelected,
+            wrap: WrapStrategy::Wrap,
+            cooldown: 0,
+            recent: VecDeque::new(),
+            ticket: 0,
+            last_reset: false,
+            seed: None,
+            on_select: None,
+            max_generation: u64::MAX,
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut shuffler: Shuffler<i32> = ShufflerGeneric::default();
+
+        assert_eq!(shuffler.size(), 0);
+        assert!(shuffler.is_empty());
+        assert!(shuffler.values().is_empty());
+        assert!(shuffler.next().unwrap().is_none());
+        assert!(shuffler.next_n(0).unwrap().is_none());
+        assert!(shuffler.next_n(10).unwrap().is_none());
+        assert!(shuffler.unique_n(0).unwrap().is_none());
+        assert!(shuffler.unique_n(10).unwrap().is_none());
+        assert!(shuffler.remove(&0).unwrap().is_none());
+
+        assert!(shuffler.inf_next().is_none());
+        assert!(shuffler.inf_next_n(0).is_none());
+        assert!(shuffler.inf_next_n(10).is_none());
+        assert!(shuffler.inf_unique_n(0).is_none());
+        assert!(shuffler.inf_unique_n(10).is_none());
+        assert!(shuffler.inf_remove(&0).is_none());
+        assert_eq!(shuffler.tree.generations().1, 0);
+    }
+
+    #[test]
+    fn one_item_fal() {
+        let mut shuffler = ShufflerGeneric::default();
+
+        assert!(shuffler.add(0).unwrap());
+        assert!(!shuffler.add(0).unwrap());
+
+        assert_eq!(shuffler.size(), 1);
+        assert!(!shuffler.is_empty());
+        assert_eq!(shuffler.values()[0], &0);
+        assert_eq!(shuffler.tree.generations(), (0, 0));
+        assert_eq!(shuffler.next().unwrap().unwrap(), &0);
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+        assert!(shuffler.next_n(0).unwrap().unwrap().is_empty());
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        let n = shuffler.next_n(1).unwrap().unwrap();
+        assert_eq!(n.len(), 1);
+        assert_eq!(n[0], &0);
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        let n = shuffler.next_n(2).unwrap().unwrap();
+        assert_eq!(n.len(), 2);
+        assert_eq!((n[0], n[1]), (&0, &0));
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        assert!(shuffler.unique_n(0).unwrap().unwrap().is_empty());
+
+        let n = shuffler.unique_n(1).unwrap().unwrap();
+        assert_eq!(n.len(), 1);
+        assert_eq!(n[0], &0);
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+        assert!(shuffler.unique_n(2).unwrap().is_none());
+
+        assert_eq!(shuffler.remove(&0).unwrap().unwrap(), 0);
+        assert_eq!(shuffler.tree.generations(), (0, 0));
+
+        assert!(shuffler.remove(&0).unwrap().is_none());
+    }
+
+    #[test]
+    fn one_item_inf() {
+        let mut shuffler = ShufflerGeneric::default();
+
+        assert!(shuffler.add(0).unwrap());
+
+        assert_eq!(shuffler.inf_next().unwrap(), &0);
+        assert!(shuffler.inf_next_n(0).unwrap().is_empty());
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        let n = shuffler.inf_next_n(1).unwrap();
+        assert_eq!(n.len(), 1);
+        assert_eq!(n[0], &0);
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        let n = shuffler.inf_next_n(2).unwrap();
+        assert_eq!(n.len(), 2);
+        assert_eq!((n[0], n[1]), (&0, &0));
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+
+        assert!(shuffler.inf_unique_n(0).unwrap().is_empty());
+
+        let n = shuffler.inf_unique_n(1).unwrap();
+        assert_eq!(n.len(), 1);
+        assert_eq!(n[0], &0);
+        assert_eq!(shuffler.tree.generations(), (1, 1));
+        assert!(shuffler.inf_unique_n(2).is_none());
+
+        assert_eq!(shuffler.inf_remove(&0).unwrap(), 0);
+        assert_eq!(shuffler.tree.generations(), (0, 0));
+
+        assert!(shuffler.inf_remove(&0).is_none());
+    }
+
+    #[test]
+    fn remove_with_generation() {
+        let mut shuffler = new_default_leftmost_oldest();
+
+        assert!(shuffler.add("a").unwrap());
+        assert!(shuffler.add("b").unwrap());
+        assert!(shuffler.add("c").unwrap());
+
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"a");
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"b");
+
+        let removed = shuffler.remove_with_generation(&"a").unwrap();
+        assert_eq!(removed, Some(("a", Generation::new(1))));
+        assert_eq!(shuffler.inf_remove_with_generation(&"b"), Some(("b", Generation::new(2))));
+        let removed = shuffler.remove_with_generation(&"c").unwrap();
+        assert_eq!(removed, Some(("c", Generation::new(0))));
+        assert_eq!(shuffler.remove_with_generation(&"c").unwrap(), None);
+        assert_eq!(shuffler.size(), 0);
+    }
+
+    #[test]
+    fn leftmost_oldest_fal() {
+        let mut shuffler = new_default_leftmost_oldest();
+
+        assert!(shuffler.add("b").is_ok());
+        assert!(shuffler.add("c").is_ok());
+        assert!(shuffler.add("d").is_ok());
+        assert!(shuffler.add("e").is_ok());
+
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"b");
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"c");
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"d");
+
+        assert!(shuffler.add("a").is_ok());
+
+        assert_eq!(shuffler.next().unwrap().unwrap(), &"a");
+
+        let v = shuffler.next_n(3).unwrap().unwrap();
+        let expected = ["e", "b", "c"];
+        v.into_iter().zip(expected.iter()).for_each(|(a, b)| assert_eq!(a, b));
+
+        let v = shuffler.unique_n(5).unwrap().unwrap();
+        // b, c, and e all have the same generation
+        let expected = ["d", "a", "b", "c", "e"];
+        v.into_iter().zip(expected.iter()).for_each(|(a, b)| assert_eq!(a, b));
+    }
+
+    #[test]
+    fn preview_n() {
+        let mut shuffler = ShufflerGeneric::default();
+        assert!(shuffler.preview_n(0).unwrap().is_none());
+        assert!(shuffler.preview_n(5).unwrap().is_none());
+
+        for i in 0..10 {
+            assert!(shuffler.add(i).unwrap());
+        }
+        shuffler.next_n(4).unwrap();
+        shuffler.next().unwrap();
+
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        let generations = shuffler.tree.generations();
+
+        assert!(shuffler.preview_n(0).unwrap().unwrap().is_empty());
+        assert_eq!(shuffler.inf_preview_n(25).unwrap().len(), 25);
+
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(before, after);
+        assert_eq!(generations, shuffler.tree.generations());
+    }
+
+    #[test]
+    fn preview_n_leftmost_oldest() {
+        let mut shuffler = new_default_leftmost_oldest();
+
+        assert!(shuffler.add("b").is_ok());
+        assert!(shuffler.add("c").is_ok());
+        assert!(shuffler.add("d").is_ok());
+
+        let v = shuffler.preview_n(4).unwrap().unwrap();
+        assert_eq!(v, [&"b", &"c", &"d", &"b"]);
+
+        let v = shuffler.next_n(4).unwrap().unwrap();
+        assert_eq!(v, [&"b", &"c", &"d", &"b"]);
+    }
+
+    #[test]
+    fn dump_sorted() {
+        let mut shuffler = ShufflerGeneric::default();
+
+        for i in [5, 3, 8, 1, 9, 2] {
+            assert!(shuffler.add(i).unwrap());
+        }
+        shuffler.next_n(4).unwrap();
+
+        let sorted = shuffler.dump_sorted();
+        let items: Vec<_> = sorted.iter().map(|(i, _)| **i).collect();
+        assert_eq!(items, [1, 2, 3, 5, 8, 9]);
+
+        let mut dump = shuffler.dump();
+        dump.sort_unstable();
+        assert_eq!(sorted, dump);
+        assert_eq!(sorted, shuffler.dump_sorted());
+    }
+
+    #[test]
+    fn values_sorted() {
+        let mut a = ShufflerGeneric::default();
+        let mut b = ShufflerGeneric::default();
+
+        for i in [5, 3, 8, 1, 9, 2] {
+            assert!(a.add(i).unwrap());
+        }
+        for i in [9, 8, 5, 3, 2, 1] {
+            assert!(b.add(i).unwrap());
+        }
+
+        assert_eq!(a.values_sorted(), [&1, &2, &3, &5, &8, &9]);
+        assert_eq!(a.values_sorted(), b.values_sorted());
+    }
+
+    #[test]
+    fn from_seed() {
+        let build = || {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::Random, 7);
+            for i in 0..100 {
+                shuffler.add(i).unwrap();
+            }
+            shuffler
+        };
+
+        let mut a = build();
+        let mut b = build();
+
+        for _ in 0..10 {
+            assert_eq!(a.next_n(10).unwrap(), b.next_n(10).unwrap());
+        }
+        assert_eq!(a.dump_sorted(), b.dump_sorted());
+    }
+
+    #[test]
+    fn generation_overflow() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.max_generation = 3;
+
+        for s in ["a", "b", "c", "d"] {
+            shuffler.add(s).unwrap();
+        }
+
+        assert_eq!(shuffler.next_n(2).unwrap(), Some(vec![&"a", &"b"]));
+        assert_eq!(shuffler.next().unwrap(), Some(&"c"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"d"));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 1), (&"b", 1), (&"c", 2), (&"d", 3)]));
+
+        // Every item is rebased to 0 before the selected item is given generation 1
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.tree.generations(), (0, 1));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 1), (&"b", 0), (&"c", 0), (&"d", 0)]));
+
+        assert_eq!(shuffler.next_n(3).unwrap(), Some(vec![&"b", &"c", &"d"]));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 3), (&"b", 2), (&"c", 2), (&"d", 2)]));
+
+        // next_n resets before selecting anything, so the selections ignore the old generations
+        assert_eq!(shuffler.next_n(2).unwrap(), Some(vec![&"a", &"b"]));
+        assert_eq!(shuffler.tree.generations(), (0, 1));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 1), (&"b", 1), (&"c", 0), (&"d", 0)]));
+    }
+
+    #[test]
+    fn rebase_generations() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.rebase_generations().unwrap();
+
+        for (s, g) in [("a", 7), ("b", 1000), ("c", 7), ("d", 3), ("e", 1 << 40)] {
+            shuffler.tree.insert(s, g);
+        }
+
+        shuffler.rebase_generations().unwrap();
+        assert_eq!(shuffler.tree.generations(), (0, 3));
+        assert_eq!(
+            shuffler.dump_sorted(),
+            as_gens([(&"a", 1), (&"b", 2), (&"c", 1), (&"d", 0), (&"e", 3)])
+        );
+
+        assert_eq!(shuffler.next().unwrap(), Some(&"d"));
+        assert_eq!(shuffler.tree.generations(), (1, 4));
+        shuffler.rebase_generations().unwrap();
+        assert_eq!(shuffler.tree.generations(), (0, 3));
+        assert_eq!(
+            shuffler.dump_sorted(),
+            as_gens([(&"a", 0), (&"b", 1), (&"c", 0), (&"d", 3), (&"e", 2)])
+        );
+    }
+
+    #[test]
+    fn add_with_generation() {
+        let mut shuffler = new_default_leftmost_oldest();
+
+        assert!(shuffler.add_with_generation("a", Generation::new(5)).unwrap());
+        assert!(shuffler.add_with_generation("b", Generation::new(10)).unwrap());
+        assert_eq!(shuffler.tree.generations(), (5, 10));
+
+        // Below the minimum
+        assert!(shuffler.add_with_generation("c", Generation::new(2)).unwrap());
+        assert_eq!(shuffler.tree.generations(), (2, 10));
+
+        // Above the maximum
+        assert!(shuffler.add_with_generation("d", Generation::new(20)).unwrap());
+        assert_eq!(shuffler.tree.generations(), (2, 20));
+
+        // Equal to an existing generation
+        assert!(shuffler.add_with_generation("e", Generation::new(10)).unwrap());
+        assert_eq!(shuffler.tree.generations(), (2, 20));
+
+        // Existing items are unchanged
+        assert!(!shuffler.add_with_generation("a", Generation::new(0)).unwrap());
+        assert!(!shuffler.add("b").unwrap());
+
+        assert_eq!(
+            shuffler.dump_sorted(),
+            as_gens([(&"a", 5), (&"b", 10), (&"c", 2), (&"d", 20), (&"e", 10)])
+        );
+
+        assert_eq!(shuffler.next().unwrap(), Some(&"c"));
+        assert_eq!(shuffler.dump_sorted()[2], (&"c", Generation::new(21)));
+    }
+
+    #[test]
+    fn add_or_get_generation() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.add_with_generation("a", Generation::new(5)).unwrap();
+        shuffler.add_with_generation("b", Generation::new(10)).unwrap();
+
+        // New items are never selected, so they get the minimum generation
+        assert_eq!(shuffler.add_or_get_generation("c").unwrap(), (true, Generation::new(5)));
+        assert_eq!(shuffler.inf_add_or_get_generation("b"), (false, Generation::new(10)));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 5), (&"b", 10), (&"c", 5)]));
+
+        shuffler.next_n(2).unwrap();
+        assert_eq!(shuffler.add_or_get_generation("a").unwrap(), (false, Generation::new(11)));
+    }
+
+    fn as_gens<T, const N: usize>(dump: [(T, u64); N]) -> [(T, Generation); N] {
+        dump.map(|(item, gen)| (item, Generation::new(gen)))
+    }
+
+    // With a bias of 0 and no randomness the leftmost eligible item is always selected.
+    fn new_default_leftmost() -> ShufflerGeneric<&'static str, DummyHasher, DummyRandom> {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.tree = Rbtree::new_dummy(&[("a", 1), ("b", 2), ("c", 3), ("d", 4)]);
+        shuffler.bias = 0.0;
+        for s in ["a", "b", "c", "d"] {
+            shuffler.add(s).unwrap();
+        }
+        shuffler
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut shuffler = new_default_leftmost();
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+
+        shuffler.set_cooldown(2);
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"b"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"c"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+
+        // Items repeated within one call still only occupy one place in the cooldown
+        assert_eq!(shuffler.next_n(3).unwrap(), Some(vec![&"b", &"b", &"b"]));
+        assert_eq!(shuffler.unique_n(2).unwrap(), Some(vec![&"c", &"d"]));
+        assert_eq!(shuffler.preview_n(2).unwrap(), Some(vec![&"a", &"a"]));
+        // Only d can be excluded while leaving three items available
+        assert_eq!(shuffler.unique_n(3).unwrap(), Some(vec![&"a", &"b", &"c"]));
+
+        // Removed items don't exclude anything
+        assert_eq!(shuffler.remove(&"b").unwrap(), Some("b"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"d"));
+
+        shuffler.set_cooldown(0);
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+    }
+
+    #[test]
+    fn cooldown_colliding_hashes() {
+        // Every item hashes to 0, so only the item itself can tell them apart.
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.bias = 0.0;
+        for s in ["a", "b", "c"] {
+            shuffler.add(s).unwrap();
+        }
+
+        shuffler.set_cooldown(1);
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"b"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+
+        shuffler.set_cooldown(2);
+        assert_eq!(shuffler.next().unwrap(), Some(&"b"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"c"));
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+
+        // A removed item's place in the cooldown doesn't pass to an item that replaces it.
+        assert_eq!(shuffler.remove(&"a").unwrap(), Some("a"));
+        shuffler.add("a").unwrap();
+        assert_eq!(shuffler.next().unwrap(), Some(&"a"));
+    }
+
+    #[test]
+    fn cooldown_exceeds_size() {
+        let mut shuffler = new_default_leftmost();
+        shuffler.set_cooldown(10);
+
+        for _ in 0..3 {
+            for s in ["a", "b", "c", "d"] {
+                assert_eq!(shuffler.next().unwrap(), Some(&s));
+            }
+        }
+
+        // unique_n can always be satisfied, the least recently returned items are eligible
+        assert_eq!(shuffler.unique_n(2).unwrap(), Some(vec![&"a", &"b"]));
+        assert_eq!(shuffler.unique_n(4).unwrap(), Some(vec![&"a", &"b", &"c", &"d"]));
+        assert_eq!(shuffler.next_n(2).unwrap(), Some(vec![&"a", &"a"]));
+        assert!(shuffler.unique_n(5).unwrap().is_none());
+    }
+
+    #[test]
+    fn extend() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.extend(["a", "b", "a", "c"]);
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 0), (&"b", 0), (&"c", 0)]));
+
+        shuffler.next_n(2).unwrap();
+        shuffler.new_items = NewItemHandling::RecentlySelected;
+        shuffler.extend(vec!["d", "a", "d"]);
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 1), (&"b", 1), (&"c", 0), (&"d", 1)]));
+
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::Random);
+        shuffler.extend(0..10);
+        shuffler.next_n(5).unwrap();
+        shuffler.next_n(5).unwrap();
+        let generations = shuffler.tree.generations();
+        shuffler.extend(5..100);
+        assert_eq!(shuffler.size(), 100);
+        assert_eq!(shuffler.tree.generations(), generations);
+    }
+
+    #[test]
+    fn from_iter() {
+        let shuffler: Shuffler<_> = [3, 1, 2, 1, 3].into_iter().collect();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        assert_eq!(shuffler.bias, 2.0);
+
+        let shuffler: Shuffler<u32> = std::iter::empty().collect();
+        assert_eq!(shuffler.size(), 0);
+    }
+
+    #[test]
+    fn next_n_spread() {
+        let mut shuffler = new_default_leftmost_oldest();
+        assert!(shuffler.next_n_spread(0).unwrap().is_none());
+        assert!(shuffler.next_n_spread(2).unwrap().is_none());
+
+        shuffler.extend(["a", "b", "c"]);
+        assert_eq!(shuffler.next_n_spread(0).unwrap(), Some(vec![]));
+        assert_eq!(shuffler.next_n_spread(5).unwrap(), Some(vec![&"a", &"b", &"c", &"a", &"b"]));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 4), (&"b", 5), (&"c", 3)]));
+    }
+
+    #[test]
+    fn next_n_spread_repeats() {
+        let repeats = |spread: bool| {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 3);
+            shuffler.extend(0..10);
+
+            let mut repeats = 0;
+            for _ in 0..1000 {
+                let mut batch = if spread {
+                    shuffler.next_n_spread(5).unwrap().unwrap()
+                } else {
+                    shuffler.next_n(5).unwrap().unwrap()
+                };
+                batch.sort_unstable();
+                batch.dedup();
+                repeats += 5 - batch.len();
+            }
+            repeats
+        };
+
+        let spread = repeats(true);
+        let batched = repeats(false);
+        assert!(spread * 3 < batched * 2, "{spread} {batched}");
+    }
+
+    #[test]
+    fn add_prehashed() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.tree = Rbtree::new_dummy(&[("a", 1), ("b", 2), ("c", 3)]);
+
+        assert!(shuffler.add("b").unwrap());
+        assert!(shuffler.add_prehashed("a", 1).unwrap());
+        assert!(shuffler.add_prehashed("c", 3).unwrap());
+        assert!(!shuffler.add_prehashed("a", 1).unwrap());
+        assert!(!shuffler.add("c").unwrap());
+
+        for s in ["a", "b", "c"] {
+            let node = shuffler.tree.find_node(&s).unwrap();
+            assert_eq!(unsafe { node.as_ref().get() }, &s);
+        }
+
+        assert_eq!(shuffler.next_n(3).unwrap(), Some(vec![&"a", &"b", &"c"]));
+        assert_eq!(shuffler.remove(&"a").unwrap(), Some("a"));
+        assert_eq!(shuffler.values_sorted(), [&"b", &"c"]);
+    }
+
+    #[test]
+    fn verify_integrity() {
+        let mut shuffler = new_default_leftmost();
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+
+        shuffler.next_n(3).unwrap();
+        shuffler.remove(&"b").unwrap();
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+
+        shuffler.tree.corrupt_children();
+        assert_eq!(shuffler.verify_integrity(), Err(IntegrityError::Children));
+    }
+
+    #[test]
+    fn try_new() {
+        let err = Shuffler::<u32>::try_new(f64::NAN, NewItemHandling::NeverSelected).unwrap_err();
+        assert_eq!(err, BiasError::NaN);
+        assert_eq!(err.to_string(), "bias cannot be NaN");
+
+        let err = Shuffler::<u32>::try_new(-1.0, NewItemHandling::NeverSelected).unwrap_err();
+        assert_eq!(err, BiasError::Negative(-1.0));
+        assert_eq!(err.to_string(), "bias -1 cannot be negative");
+
+        let shuffler = Shuffler::<u32>::try_new(0.0, NewItemHandling::NeverSelected).unwrap();
+        assert_eq!(shuffler.bias, 0.0);
+
+        let shuffler = Shuffler::<u32>::try_new(f64::INFINITY, NewItemHandling::Random).unwrap();
+        assert_eq!(shuffler.bias, f64::INFINITY);
+    }
+
+    #[test]
+    fn next_with_bias() {
+        let mut shuffler = Shuffler::new(0.0, NewItemHandling::NeverSelected);
+        assert!(shuffler.next_with_bias(f64::INFINITY).unwrap().is_none());
+
+        for i in 0..10 {
+            assert!(shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap());
+        }
+
+        for i in 0..10 {
+            assert_eq!(shuffler.next_with_bias(f64::INFINITY).unwrap(), Some(&i));
+            assert_eq!(shuffler.bias, 0.0);
+        }
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            shuffler.inf_next_with_bias(f64::NAN);
+        }));
+        assert!(result.is_err());
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            shuffler.inf_next_with_bias(-1.0);
+        }));
+        assert!(result.is_err());
+        assert_eq!(shuffler.bias, 0.0);
+    }
+
+    #[test]
+    fn next_n_iter() {
+        let mut shuffler = Shuffler::<u32>::new(2.0, NewItemHandling::NeverSelected);
+        assert_eq!(shuffler.next_n_iter(5).count(), 0);
+
+        let build = || {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 5);
+            shuffler.extend(0..20_u32);
+            shuffler.set_cooldown(3);
+            shuffler
+        };
+        let mut batched = build();
+        let mut streamed = build();
+
+        for n in [0, 1, 5, 20, 50] {
+            let expected: Vec<_> = batched.next_n(n).unwrap().unwrap().into_iter().collect();
+            let iter = streamed.next_n_iter(n);
+            assert_eq!(iter.size_hint(), (n, Some(n)));
+            assert_eq!(iter.collect::<Vec<_>>().iter().collect::<Vec<_>>(), expected);
+
+            assert_eq!(batched.dump_sorted(), streamed.dump_sorted());
+            assert_eq!(batched.recent, streamed.recent);
+        }
+
+        // Dropping the iterator early leaves no items suspended by the cooldown.
+        let (_, max_gen) = streamed.tree.generations();
+        let mut iter = streamed.next_n_iter(10);
+        let first = iter.next().unwrap();
+        drop(iter);
+        assert!(streamed.dump().contains(&(&first, Generation::new(max_gen + 1))));
+        assert_eq!(streamed.unique_n(20).unwrap().unwrap().len(), 20);
+
+        // Neither does leaking it.
+        let mut iter = streamed.next_n_iter(10);
+        iter.next().unwrap();
+        std::mem::forget(iter);
+        assert_eq!(streamed.unique_n(20).unwrap().unwrap().len(), 20);
+        assert_eq!(streamed.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn remove_borrowed() {
+        let mut shuffler = Shuffler::<String>::new(2.0, NewItemHandling::NeverSelected);
+        shuffler.extend(["a", "b", "c"].map(String::from));
+        shuffler.next_n(2).unwrap();
+
+        let gen = shuffler.dump().into_iter().find(|(s, _)| *s == "b").unwrap().1;
+
+        assert_eq!(shuffler.remove("a").unwrap().as_deref(), Some("a"));
+        assert_eq!(shuffler.remove("a").unwrap(), None);
+        assert_eq!(shuffler.inf_remove_with_generation("b"), Some(("b".to_string(), gen)));
+        assert_eq!(shuffler.values_sorted(), [&"c".to_string()]);
+    }
+
+    #[test]
+    fn replace() {
+        let mut shuffler = Shuffler::new(f64::INFINITY, NewItemHandling::NeverSelected);
+        for i in 0..5 {
+            assert!(shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap());
+        }
+
+        assert!(shuffler.replace(&0, 10).unwrap());
+        assert!(shuffler.inf_replace(&3, 20));
+        assert!(!shuffler.replace(&0, 30).unwrap());
+        assert!(shuffler.replace(&4, 1).unwrap());
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&1, 1), (&2, 2), (&10, 0), (&20, 3)]));
+
+        let order: Vec<_> = (0..4).map(|_| *shuffler.next().unwrap().unwrap()).collect();
+        assert_eq!(order, [10, 1, 2, 20]);
+    }
+
+    #[test]
+    fn retain() {
+        let mut shuffler = Shuffler::new(f64::INFINITY, NewItemHandling::NeverSelected);
+        for i in 0..10 {
+            assert!(shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap());
+        }
+
+        shuffler.retain(|i| i % 3 != 0).unwrap();
+        shuffler.inf_retain(|i| *i != 5);
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&1, 1), (&2, 2), (&4, 4), (&7, 7), (&8, 8)]));
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn seed() {
+        let seeded = Shuffler::<u32>::from_seed(2.0, NewItemHandling::NeverSelected, 7);
+        assert_eq!(seeded.seed(), Some(7));
+        let dumped = Shuffler::<u32>::from_dump(Vec::new(), 2.0, NewItemHandling::Random, 3);
+        assert_eq!(dumped.seed(), Some(3));
+        assert_eq!(Shuffler::<u32>::new(2.0, NewItemHandling::NeverSelected).seed(), None);
+        assert_eq!(Shuffler::<u32>::default().seed(), None);
+    }
+
+    // Every item's probability of being selected next, calculated from the current state.
+    fn probabilities(shuffler: &mut Shuffler<u32>) -> Vec<f64> {
+        let suspended = shuffler.suspend_cooldown(1);
+        let range = shuffler.tree.generations();
+        let probabilities = (0..shuffler.size() as u32)
+            .map(|i| shuffler.selection_probability(shuffler.tree.find_node(&i).unwrap(), range))
+            .collect();
+        Shuffler::resume_cooldown(suspended);
+        probabilities
+    }
+
+    #[test]
+    fn selection_probability() {
+        for wrap in [WrapStrategy::Wrap, WrapStrategy::Widen] {
+            for cooldown in [0, 3] {
+                let mut shuffler = ShufflerBuilder::new().wrap_strategy(wrap).seed(9).build();
+                shuffler.set_cooldown(cooldown);
+                for i in 0..20_u32 {
+                    let gen = u64::from(i * 7 % 20);
+                    shuffler.add_with_generation(i, Generation::new(gen)).unwrap();
+                }
+                shuffler.next_n_spread(3).unwrap();
+
+                let expected = probabilities(&mut shuffler);
+                assert!((expected.iter().sum::<f64>() - 1.0).abs() < 1e-9, "{expected:?}");
+
+                let mut counts = [0; 20];
+                for _ in 0..100_000 {
+                    counts[*shuffler.sample().unwrap() as usize] += 1;
+                }
+                for (p, count) in expected.iter().zip(counts) {
+                    let actual = f64::from(count) / 100_000.0;
+                    assert!((p - actual).abs() < 0.01, "{wrap:?} {expected:?} {counts:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_next() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 3);
+        assert_eq!(shuffler.weighted_next().unwrap(), None);
+
+        shuffler.add(0).unwrap();
+        assert_eq!(shuffler.weighted_next().unwrap(), Some((&0, 1.0)));
+        assert_eq!(shuffler.inf_weighted_next(), Some((&0, 1.0)));
+
+        for i in 1..10_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i) + 10)).unwrap();
+        }
+
+        for _ in 0..50 {
+            let expected = probabilities(&mut shuffler);
+            assert!(expected.iter().all(|p| *p > 0.0 && *p <= 1.0), "{expected:?}");
+
+            let (item, probability) = shuffler.weighted_next().unwrap().unwrap();
+            let item = *item;
+            assert_eq!(probability, expected[item as usize]);
+            assert!(probability > 0.0 && probability <= 1.0);
+
+            let (_, max_gen) = shuffler.tree.generations();
+            assert_eq!(shuffler.generations_of(&[item]), [Some(Generation::new(max_gen))]);
+        }
+
+        // Starting from the same state, with the same hasher, the reported probabilities match how
+        // often each item is actually selected.
+        let hasher = ahash::RandomState::with_seeds(1, 2, 3, 4).build_hasher();
+        let mut counts = [0; 10];
+        let mut reported = [None; 10];
+        for seed in 0..50_000 {
+            let mut shuffler = ShufflerBuilder::new()
+                .hasher(hasher.clone())
+                .rng(StdRng::seed_from_u64(seed))
+                .build();
+            for i in 0..10_u32 {
+                shuffler.add_with_generation(i, Generation::new(u64::from(i * 3 % 10))).unwrap();
+            }
+
+            let (item, probability) = shuffler.weighted_next().unwrap().unwrap();
+            let item = *item as usize;
+            counts[item] += 1;
+            assert_eq!(*reported[item].get_or_insert(probability), probability);
+        }
+        for (p, count) in reported.iter().zip(counts) {
+            let actual = f64::from(count) / 50_000.0;
+            assert!((p.unwrap_or_default() - actual).abs() < 0.01, "{reported:?} {counts:?}");
+        }
+    }
+
+    #[test]
+    fn next_with_floor() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 6);
+        assert_eq!(shuffler.next_with_floor(0).unwrap(), None);
+
+        for i in 0..10 {
+            shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap();
+        }
+        assert_eq!(shuffler.next_with_floor(10).unwrap(), None);
+        assert_eq!(shuffler.inf_next_with_floor(9), Some(&0));
+        assert_eq!(shuffler.generations_of(&[0]), [Some(Generation::new(10))]);
+
+        for _ in 0..100 {
+            let (_, max_gen) = shuffler.tree.generations();
+            let dump: Vec<_> = shuffler.dump().into_iter().map(|(i, g)| (*i, g)).collect();
+            let item = *shuffler.next_with_floor(4).unwrap().unwrap();
+            let (_, gen) = dump.into_iter().find(|(i, _)| *i == item).unwrap();
+            assert!(gen.get() + 4 <= max_gen, "{item} with generation {gen} selected at {max_gen}");
+        }
+
+        // The cooldown yields when it would exclude every old enough item.
+        shuffler.set_cooldown(9);
+        for _ in 0..20 {
+            assert!(shuffler.next_with_floor(8).unwrap().is_some());
+        }
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+    }
+
+    // Replays random operations the way a fuzz harness would, checking the shuffler against a
+    // simple model of its contents after each one.
+    pub(crate) fn replay_random_ops<S: AwShuffler<Item = u8>>(shuffler: &mut S, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut model = HashSet::new();
+
+        for _ in 0..2000 {
+            let op = match rng.gen_range(0..5) {
+                0 => Op::Add(rng.gen()),
+                1 => Op::Remove(rng.gen()),
+                2 => Op::Next,
+                3 => Op::NextN(rng.gen_range(0..10)),
+                _ => Op::UniqueN(rng.gen_range(0..10)),
+            };
+            match &op {
+                Op::Add(item) => {
+                    model.insert(*item);
+                }
+                Op::Remove(item) => {
+                    model.remove(item);
+                }
+                _ => {}
+            }
+
+            shuffler.apply_op(op.clone()).unwrap();
+            assert_eq!(shuffler.size(), model.len(), "after {op:?}");
+            assert_eq!(shuffler.verify_integrity(), Ok(()), "after {op:?}");
+        }
+    }
+
+    #[test]
+    fn huge_n() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 4);
+        shuffler.extend(0..10);
+        shuffler.next_n(3).unwrap();
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+
+        for n in [usize::MAX, usize::MAX / 4, isize::MAX as usize] {
+            assert_eq!(shuffler.next_n(n), Ok(None));
+            assert_eq!(shuffler.next_n_spread(n), Ok(None));
+            assert_eq!(shuffler.unique_n(n), Ok(None));
+            assert_eq!(shuffler.preview_n(n), Ok(None));
+            assert!(shuffler.sample_n(n).is_empty());
+        }
+
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(before, after);
+        assert_eq!(shuffler.next_n(20).unwrap().unwrap().len(), 20);
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn new_item_handling_traits() {
+        use std::collections::HashMap;
+
+        let handling = NewItemHandling::Random;
+        let copied = handling;
+        assert_eq!(handling, copied);
+        assert_ne!(NewItemHandling::NeverSelected, NewItemHandling::RecentlySelected);
+
+        let mut counts = HashMap::new();
+        for h in [handling, NewItemHandling::NeverSelected, copied] {
+            *counts.entry(h).or_insert(0) += 1;
+        }
+        assert_eq!(counts[&NewItemHandling::Random], 2);
+        assert_eq!(counts.get(&NewItemHandling::RecentlySelected), None);
+
+        let mut shuffler = Shuffler::<u32>::new(2.0, handling);
+        assert_eq!(shuffler.new_items, NewItemHandling::Random);
+        shuffler.new_items = NewItemHandling::NeverSelected;
+        assert_ne!(shuffler.new_items, handling);
     }
 
-    fn next(&mut self) -> Result<Option<&Self::Item>, Self::Error> {
-        let size = self.tree.size();
-        if size == 0 {
-            return Ok(None);
+    #[test]
+    fn no_end_of_tree_bias() {
+        // With the identity hasher the tree is ordered by value. Every quarter of the tree holds
+        // the same pattern of generations, so any difference between quarters comes from where
+        // they are in the tree. The neighbours of each item are the same in every quarter, so
+        // this says nothing about their effect, see neighbours_affect_selection.
+        let mut shuffler = ShufflerBuilder::new()
+            .bias(2.0)
+            .hasher(crate::IdentityHasher::default())
+            .rng(StdRng::seed_from_u64(5))
+            .build();
+        for i in 0..400_u64 {
+            shuffler.add_with_generation(i, Generation::new((i % 100) * 37 % 100)).unwrap();
         }
 
-        let random_gen = self.random_generation();
-        let index = self.rng.gen_range(0..size);
+        let assert_uniform = |counts: &[u32], total: u32| {
+            let expected = total / counts.len() as u32;
+            for (part, count) in counts.iter().enumerate() {
+                assert!(
+                    count.abs_diff(expected) < expected / 20,
+                    "part {part} selected {count} times, expected {expected}: {counts:?}"
+                );
+            }
+        };
 
-        let node = self.tree.find_next(index, random_gen);
-        let (next_gen, _) = self.next_generation();
+        let mut quarters = [0; 4];
+        for _ in 0..40000 {
+            quarters[*shuffler.sample().unwrap() as usize / 100] += 1;
+        }
+        assert_uniform(&quarters, 40000);
 
-        Node::set_generation(node, next_gen.get());
+        let mut quarters = [0; 4];
+        for _ in 0..40000 {
+            quarters[*shuffler.next().unwrap().unwrap() as usize / 100] += 1;
+        }
+        assert_uniform(&quarters, 40000);
 
-        unsafe { Ok(Some(node.as_ref().get())) }
+        // The ends of the tree are no more likely than the middle.
+        let mut tenths = [0; 10];
+        for _ in 0..40000 {
+            tenths[*shuffler.next().unwrap().unwrap() as usize / 40] += 1;
+        }
+        assert_uniform(&tenths, 40000);
     }
 
-    fn next_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
-        let size = self.tree.size();
-        if size == 0 {
-            return Ok(None);
+    #[test]
+    fn neighbours_affect_selection() {
+        // Selection probability isn't a function of generation alone. Items 4 to 7 share the
+        // oldest generation, but item 4 is also found by searches starting in the recent run
+        // before it.
+        let mut shuffler = ShufflerBuilder::new()
+            .bias(2.0)
+            .hasher(crate::IdentityHasher::default())
+            .rng(StdRng::seed_from_u64(5))
+            .build();
+        for i in 0..8_u64 {
+            shuffler.add_with_generation(i, Generation::new(if i < 4 { 9 } else { 0 })).unwrap();
         }
 
-        let index_range = Uniform::new(0, size);
-        let mut selected = Vec::with_capacity(n);
+        let mut counts = [0; 8];
+        for _ in 0..10000 {
+            counts[*shuffler.sample().unwrap() as usize] += 1;
+        }
+        for i in 5..8 {
+            assert!(counts[4] > counts[i] * 3, "{counts:?}");
+        }
+    }
 
-        let (next_gen, _) = self.next_generation();
-        // It's possible to have reset the tree here but it's not worth optimizing for.
+    #[test]
+    fn items_in_value_range() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 3);
+        let names = ["a/1", "a/2", "b/1", "b/2", "b/3", "c/1", "ba", "bz", "c"];
+        shuffler.extend(names.map(str::to_string));
+
+        let range = |lo: &str, hi: &str| -> Vec<_> {
+            shuffler
+                .items_in_value_range(&lo.to_string(), &hi.to_string())
+                .into_iter()
+                .map(String::as_str)
+                .collect()
+        };
+        assert_eq!(range("b/", "b/~"), ["b/1", "b/2", "b/3"]);
+        assert_eq!(range("a/2", "ba"), ["a/2", "b/1", "b/2", "b/3", "ba"]);
+        assert_eq!(range("c", "c"), ["c"]);
+        assert!(range("d", "z").is_empty());
+        assert!(range("c", "a").is_empty());
+
+        let all: Vec<_> = (0..1000).collect();
+        let mut shuffler: Shuffler<u32> = all.iter().copied().collect();
+        shuffler.next_n(300).unwrap();
+        let expected: Vec<_> = all[250..750].iter().collect();
+        assert_eq!(shuffler.items_in_value_range(&250, &749), expected);
+    }
 
-        for _ in 0..n {
-            let random_gen = self.random_generation();
-            let index = index_range.sample(&mut self.rng);
+    #[test]
+    fn apply_op() {
+        for seed in 0..5 {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::Random, seed);
+            replay_random_ops(&mut shuffler, seed);
+        }
 
-            let node = self.tree.find_next(index, random_gen);
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 1);
+        shuffler.apply_op(Op::Add(1)).unwrap();
+        shuffler.apply_op(Op::Add(2)).unwrap();
+        shuffler.apply_op(Op::UniqueN(2)).unwrap();
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&1, 1), (&2, 1)]));
 
-            // Set the generation here to try to prioritize other items.
-            Node::set_generation(node, next_gen.get());
+        shuffler.apply_op(Op::Remove(1)).unwrap();
+        shuffler.apply_op(Op::NextN(3)).unwrap();
+        assert_eq!(shuffler.dump(), as_gens([(&2, 1)]));
+    }
 
-            selected.push(node)
+    #[test]
+    fn next_or_else() {
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        assert_eq!(shuffler.next_or_else(|| 5).unwrap(), 5);
+        assert_eq!(shuffler.dump(), [(&5, Generation::new(1))]);
+
+        shuffler.add(6).unwrap();
+        shuffler.add(7).unwrap();
+        for _ in 0..10 {
+            let next = shuffler.next_or_else(|| panic!("fallback called")).unwrap();
+            assert!((5..=7).contains(&next));
         }
+        assert_eq!(shuffler.size(), 3);
+    }
 
+    #[test]
+    fn prune_to_size() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 9);
+        for i in 0..100 {
+            shuffler.add_with_generation(i, Generation::new(100 - i as u64)).unwrap();
+        }
 
-        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
-
-        Ok(Some(output))
+        assert_eq!(shuffler.prune_to_size(100).unwrap(), 0);
+        assert_eq!(shuffler.prune_to_size(30).unwrap(), 70);
+        assert!(shuffler.values_sorted().into_iter().copied().eq(70..100));
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+        assert_eq!(shuffler.prune_to_size(0).unwrap(), 30);
+        assert!(shuffler.is_empty());
+
+        // Ties are broken by the order of the tree, which follows the items with this hasher.
+        let mut shuffler = ShufflerBuilder::new().hasher(crate::IdentityHasher::default()).build();
+        for i in 0..50 {
+            shuffler.add_with_generation(i, Generation::new(i / 10)).unwrap();
+        }
+        assert_eq!(shuffler.prune_to_size(25).unwrap(), 25);
+        assert!(shuffler.values_sorted().into_iter().copied().eq(0..25_u64));
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
     }
 
-    fn unique_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
-        let size = self.tree.size();
-        if size == 0 || size < n {
-            return Ok(None);
+    #[test]
+    fn entries() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 8);
+        assert_eq!(shuffler.entries().count(), 0);
+
+        for i in 0..200 {
+            shuffler.add_with_generation(i, Generation::new(i as u64 % 7)).unwrap();
+        }
+        shuffler.next_n(50).unwrap().unwrap();
+        for i in (0..200).step_by(3) {
+            shuffler.remove(&i).unwrap();
         }
 
-        let index_range = Uniform::new(0, size);
-        let mut selected = Vec::with_capacity(n);
+        let mut entries: Vec<_> = shuffler.entries().collect();
+        assert_eq!(entries, shuffler.dump());
+        let size = shuffler.size();
+        assert_eq!(shuffler.entries().size_hint(), (size, Some(size)));
 
-        let (next_gen, _) = self.next_generation();
-        // It's possible to have reset the tree here but it's not worth optimizing for.
+        entries.sort_unstable();
+        let items: Vec<_> = entries.iter().map(|(i, _)| **i).collect();
+        assert_eq!(items, (0..200).filter(|i| i % 3 != 0).collect::<Vec<_>>());
+        let gens = shuffler.generations_of(items.iter());
+        assert!(entries.iter().zip(gens).all(|((_, gen), g)| g == Some(*gen)));
+    }
 
-        for _ in 0..n {
-            let random_gen = self.random_generation_below(next_gen);
-            let index = index_range.sample(&mut self.rng);
+    #[test]
+    fn unique_n_with_generations() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 6);
+        for i in 0..20 {
+            shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap();
+        }
 
-            let node = self.tree.find_next(index, random_gen);
+        let selected = shuffler.unique_n_with_generations(10).unwrap().unwrap();
+        assert_eq!(selected.len(), 10);
+        assert!(selected.iter().all(|(_, gen)| *gen == Generation::new(20)));
 
-            // Set the generation here to try to prioritize other items.
-            Node::set_generation(node, next_gen.get());
+        let items: HashSet<_> = selected.iter().map(|(i, _)| *i).collect();
+        assert_eq!(items.len(), 10);
+        let gens = shuffler.generations_of(&items.into_iter().collect::<Vec<_>>());
+        assert!(gens.into_iter().all(|g| g == Some(Generation::new(20))));
 
-            selected.push(node)
+        assert_eq!(shuffler.unique_n_with_generations(0).unwrap(), Some(Vec::new()));
+        assert_eq!(shuffler.unique_n_with_generations(21).unwrap(), None);
+        assert_eq!(Shuffler::<u8>::default().unique_n_with_generations(0).unwrap(), None);
+    }
+
+    #[test]
+    fn on_select() {
+        use std::sync::{Arc, Mutex};
+
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 5);
+        for i in 0..10 {
+            shuffler.add(i).unwrap();
         }
 
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        shuffler.on_select(move |item, gen| recorded.lock().unwrap().push((*item, gen)));
 
-        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+        let take =
+            |calls: &Mutex<Vec<(i32, Generation)>>| std::mem::take(&mut *calls.lock().unwrap());
+        let with_generations = |shuffler: &Shuffler<i32>, items: Vec<i32>| -> Vec<_> {
+            let gens = shuffler.generations_of(&items);
+            items.into_iter().zip(gens.into_iter().map(Option::unwrap)).collect()
+        };
 
-        Ok(Some(output))
-    }
+        let item = *shuffler.next().unwrap().unwrap();
+        assert_eq!(take(&calls), with_generations(&shuffler, vec![item]));
 
-    fn size(&self) -> usize {
-        self.tree.size()
+        let items = shuffler.next_n_owned(15).unwrap().unwrap();
+        assert_eq!(take(&calls), with_generations(&shuffler, items));
+
+        let items = shuffler.unique_n_owned(10).unwrap().unwrap();
+        assert_eq!(take(&calls), with_generations(&shuffler, items));
+
+        let items: Vec<_> = shuffler.next_n_iter(5).collect();
+        assert_eq!(take(&calls), with_generations(&shuffler, items));
+
+        shuffler.preview_n(5).unwrap().unwrap();
+        shuffler.sample().unwrap();
+        shuffler.touch_all(&[1, 2]).unwrap();
+        assert!(take(&calls).is_empty());
     }
 
-    fn values(&self) -> Vec<&Self::Item> {
-        self.tree.values()
+    #[test]
+    fn touch_all() {
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        for i in 0..5 {
+            shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap();
+        }
+
+        assert_eq!(shuffler.touch_all(&[1, 3, 3, 7]).unwrap(), 2);
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 0), (&1, 5), (&2, 2), (&3, 5), (&4, 4)]));
+        assert_eq!(shuffler.touch_all(&[]).unwrap(), 0);
+        assert_eq!(shuffler.touch_all(&[8, 9]).unwrap(), 0);
+        assert_eq!(shuffler.tree.generations(), (0, 5));
+
+        shuffler.max_generation = 5;
+        assert_eq!(shuffler.touch_all(&[0, 4]).unwrap(), 2);
+        assert!(shuffler.last_reset_occurred());
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 1), (&1, 0), (&2, 0), (&3, 0), (&4, 1)]));
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
     }
 
-    fn into_values(self) -> Vec<Self::Item> {
-        self.tree.into_values()
+    #[test]
+    fn next_single_item() {
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        shuffler.add(1).unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(shuffler.next().unwrap(), Some(&1));
+            assert_eq!(shuffler.dump(), [(&1, Generation::new(1))]);
+        }
+        assert_eq!(shuffler.next_n(3).unwrap(), Some(vec![&1, &1, &1]));
+        assert_eq!(shuffler.unique_n(1).unwrap(), Some(vec![&1]));
+        assert_eq!(shuffler.unique_n(2).unwrap(), None);
+        assert_eq!(shuffler.dump(), [(&1, Generation::new(1))]);
+
+        shuffler.add(2).unwrap();
+        assert_eq!(shuffler.dump_sorted(), [(&1, Generation::new(1)), (&2, Generation::new(1))]);
+        let first = *shuffler.next().unwrap().unwrap();
+        assert!(shuffler.dump_sorted().contains(&(&first, Generation::new(2))));
     }
 
-    fn dump(&self) -> Vec<(&Self::Item, u64)> {
-        self.tree.dump()
+    #[test]
+    fn sample() {
+        let mut shuffler = Shuffler::from_seed(0.0, NewItemHandling::NeverSelected, 3);
+        assert!(shuffler.sample().is_none());
+        assert!(shuffler.sample_n(5).is_empty());
+
+        for i in 0..4 {
+            assert!(shuffler.add_with_generation(i, Generation::new(i as u64)).unwrap());
+        }
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+
+        fn count(samples: impl Iterator<Item = usize>) -> [usize; 4] {
+            let mut counts = [0; 4];
+            samples.for_each(|i| counts[i] += 1);
+            counts
+        }
+
+        // With no bias every item is equally likely.
+        let counts = count(shuffler.sample_n(40_000).into_iter().copied());
+        assert!(counts.iter().all(|c| (9_000..11_000).contains(c)), "{counts:?}");
+
+        // Older items are more likely, and sampling doesn't make them any less likely.
+        shuffler.bias = 2.0;
+        let counts = count(shuffler.sample_n(40_000).into_iter().copied());
+        assert!(counts.windows(2).all(|w| w[0] > w[1]), "{counts:?}");
+
+        let counts = count((0..40_000).map(|_| *shuffler.sample().unwrap()));
+        assert!(counts.windows(2).all(|w| w[0] > w[1]), "{counts:?}");
+
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(before, after);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use rand::RngCore;
+    #[test]
+    fn from_dump() {
+        let mut original = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        for i in 0..50 {
+            original.add(i).unwrap();
+        }
+        original.next_n(80).unwrap();
 
-    use crate::rbtree::tests::DummyHasher;
-    use crate::rbtree::Rbtree;
-    use crate::{AwShuffler, InfallibleShuffler, NewItemHandling, ShufflerGeneric};
+        let dump: Vec<_> = original.dump().into_iter().map(|(i, g)| (*i, g)).collect();
+        let rebuild = || Shuffler::from_dump(dump.clone(), 2.0, NewItemHandling::NeverSelected, 9);
 
+        let mut a = rebuild();
+        let mut b = rebuild();
+        a.verify_integrity().unwrap();
+        assert_eq!(a.dump_sorted(), original.dump_sorted());
+        assert_eq!(a.tree.generations(), original.tree.generations());
 
-    #[derive(Default)]
-    struct DummyRandom {
-        vals: Vec<u64>,
-        index: usize,
+        for _ in 0..100 {
+            assert_eq!(a.next().unwrap(), b.next().unwrap());
+        }
+        assert_eq!(a.dump_sorted(), b.dump_sorted());
     }
 
-    impl RngCore for DummyRandom {
-        fn next_u32(&mut self) -> u32 {
-            self.next_u64() as u32
+    #[test]
+    fn debug() {
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        for i in 0..1000 {
+            shuffler.add_with_generation(format!("item-{i}"), Generation::new(i + 5)).unwrap();
         }
 
-        fn next_u64(&mut self) -> u64 {
-            if self.vals.is_empty() {
-                return 0;
+        let debug = format!("{shuffler:?}");
+        assert_eq!(
+            debug,
+            "ShufflerGeneric { size: 1000, generations: (5, 1004), bias: 2.0, new_items: \
+             NeverSelected, .. }"
+        );
+        assert!(!debug.contains("item-"));
+    }
+
+    #[test]
+    fn unique_n_equal_generations() {
+        let strategies = [WrapStrategy::Wrap, WrapStrategy::Widen];
+        for (seed, wrap) in (0..20).flat_map(|s| strategies.map(|w| (s, w))) {
+            for n in [2, 10, 29, 30] {
+                let mut shuffler = ShufflerBuilder::new().seed(seed).wrap_strategy(wrap).build();
+                shuffler.set_cooldown(5);
+                for i in 0..30 {
+                    shuffler.add_with_generation(i, Generation::new(7)).unwrap();
+                }
+
+                for _ in 0..20 {
+                    let mut selected = shuffler.unique_n(n).unwrap().unwrap();
+                    selected.sort_unstable();
+                    selected.dedup();
+                    assert_eq!(selected.len(), n);
+                }
+                shuffler.verify_integrity().unwrap();
             }
-            let v = self.vals[self.index];
-            self.index = (self.index + 1) % self.vals.len();
-            v
         }
+    }
 
-        fn fill_bytes(&mut self, _dest: &mut [u8]) {
-            unimplemented!()
+    #[test]
+    fn equal_generations_uniform() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 11);
+        for i in 0..10 {
+            shuffler.add_with_generation(i, Generation::new(3)).unwrap();
         }
 
-        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand::Error> {
-            unimplemented!()
-        }
+        let mut counts = [0; 10];
+        shuffler.sample_n(100_000).into_iter().for_each(|i| counts[*i] += 1);
+        assert!(counts.iter().all(|c| (9_500..10_500).contains(c)), "{counts:?}");
     }
 
-    fn new_default_leftmost_oldest() -> ShufflerGeneric<&'static str, DummyHasher, DummyRandom> {
-        ShufflerGeneric {
-            tree: Rbtree::new_dummy(&[]),
-            rng: DummyRandom::default(),
-            bias: f64::INFINITY,
-            new_items: NewItemHandling::NeverSelected,
+    #[test]
+    fn weights() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 4);
+        assert!(shuffler.weights().is_empty());
+
+        for i in 0..10_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i) * 3 + 2)).unwrap();
         }
+
+        let mut weights = shuffler.weights();
+        weights.sort_unstable_by_key(|(i, _)| **i);
+        assert_eq!(weights[0], (&0, 1.0));
+        assert!(weights.iter().all(|(_, w)| *w > 0.0 && *w <= 1.0), "{weights:?}");
+        assert!(weights.windows(2).all(|w| w[0].1 > w[1].1), "{weights:?}");
+
+        shuffler.bias = 0.0;
+        assert!(shuffler.weights().iter().all(|(_, w)| *w == 1.0));
+
+        shuffler.bias = f64::INFINITY;
+        let weights = shuffler.weights();
+        assert!(weights.iter().all(|(i, w)| *w == if **i == 0 { 1.0 } else { 0.0 }));
     }
 
     #[test]
-    fn empty() {
-        let mut shuffler = ShufflerGeneric::default();
+    fn zero_n() {
+        let mut shuffler = new_default_leftmost_oldest();
+        for (s, gen) in [("a", 1), ("b", 2), ("c", 3)] {
+            shuffler.add_with_generation(s, Generation::new(gen)).unwrap();
+        }
+        // Any selection would reset every generation.
+        shuffler.max_generation = 3;
 
-        assert_eq!(shuffler.size(), 0);
-        assert!(shuffler.values().is_empty());
-        assert!(shuffler.next().unwrap().is_none());
-        assert!(shuffler.next_n(0).unwrap().is_none());
-        assert!(shuffler.next_n(10).unwrap().is_none());
-        assert!(shuffler.unique_n(0).unwrap().is_none());
-        assert!(shuffler.unique_n(10).unwrap().is_none());
-        assert!(shuffler.remove(&0).unwrap().is_none());
+        assert_eq!(shuffler.next_n(0).unwrap(), Some(Vec::new()));
+        assert_eq!(shuffler.unique_n(0).unwrap(), Some(Vec::new()));
+        assert_eq!(shuffler.try_unique_n(0).unwrap(), Some(Vec::new()));
+        assert_eq!(shuffler.tree.generations(), (1, 3));
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&"a", 1), (&"b", 2), (&"c", 3)]));
 
-        assert!(shuffler.inf_next().is_none());
-        assert!(shuffler.inf_next_n(0).is_none());
-        assert!(shuffler.inf_next_n(10).is_none());
-        assert!(shuffler.inf_unique_n(0).is_none());
-        assert!(shuffler.inf_unique_n(10).is_none());
-        assert!(shuffler.inf_remove(&0).is_none());
-        assert_eq!(shuffler.tree.generations().1, 0);
+        shuffler.next_n(1).unwrap();
+        assert_eq!(shuffler.tree.generations(), (0, 1));
     }
 
     #[test]
-    fn one_item_fal() {
-        let mut shuffler = ShufflerGeneric::default();
+    fn last_reset_occurred() {
+        let mut shuffler = new_default_leftmost_oldest();
+        shuffler.max_generation = 3;
+        for s in ["a", "b", "c", "d"] {
+            shuffler.add(s).unwrap();
+        }
+        assert!(!shuffler.last_reset_occurred());
+
+        shuffler.next_n(2).unwrap();
+        shuffler.next().unwrap();
+        shuffler.next().unwrap();
+        assert!(!shuffler.last_reset_occurred());
+
+        shuffler.next().unwrap();
+        assert!(shuffler.last_reset_occurred());
+        shuffler.next().unwrap();
+        assert!(!shuffler.last_reset_occurred());
+
+        // Only the first of the spread selections resets, but the flag covers the whole call.
+        shuffler.next().unwrap();
+        shuffler.next_n_spread(2).unwrap();
+        assert_eq!(shuffler.tree.generations(), (0, 2));
+        assert!(shuffler.last_reset_occurred());
+
+        shuffler.unique_n(2).unwrap();
+        assert!(!shuffler.last_reset_occurred());
+        shuffler.unique_n(2).unwrap();
+        assert!(shuffler.last_reset_occurred());
+    }
 
-        assert!(shuffler.add(0).unwrap());
-        assert!(!shuffler.add(0).unwrap());
+    #[test]
+    fn generations_of() {
+        let mut shuffler = new_default_leftmost_oldest();
+        for (s, gen) in [("a", 3), ("b", 1), ("c", 7)] {
+            shuffler.add_with_generation(s, Generation::new(gen)).unwrap();
+        }
 
-        assert_eq!(shuffler.size(), 1);
-        assert_eq!(shuffler.values()[0], &0);
-        assert_eq!(shuffler.tree.generations(), (0, 0));
-        assert_eq!(shuffler.next().unwrap().unwrap(), &0);
-        assert_eq!(shuffler.tree.generations(), (1, 1));
-        assert!(shuffler.next_n(0).unwrap().unwrap().is_empty());
-        assert_eq!(shuffler.tree.generations(), (1, 1));
+        let gens = shuffler.generations_of(&["c", "x", "a", "a", "b", "y"]);
+        let gens: Vec<_> = gens.into_iter().map(|g| g.map(Generation::get)).collect();
+        assert_eq!(gens, [Some(7), None, Some(3), Some(3), Some(1), None]);
+        assert!(shuffler.generations_of([]).is_empty());
 
-        let n = shuffler.next_n(1).unwrap().unwrap();
-        assert_eq!(n.len(), 1);
-        assert_eq!(n[0], &0);
-        assert_eq!(shuffler.tree.generations(), (2, 2));
+        shuffler.next().unwrap();
+        assert_eq!(shuffler.generations_of(&["b"]), [Some(Generation::new(8))]);
+    }
 
-        let n = shuffler.next_n(2).unwrap().unwrap();
-        assert_eq!(n.len(), 2);
-        assert_eq!((n[0], n[1]), (&0, &0));
-        assert_eq!(shuffler.tree.generations(), (3, 3));
+    #[test]
+    fn owned() {
+        let new = || {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 7);
+            for i in 0..20 {
+                shuffler.add(i).unwrap();
+            }
+            shuffler
+        };
+
+        let mut borrowed = new();
+        let mut owned = new();
+        for _ in 0..10 {
+            assert_eq!(borrowed.next().unwrap().copied(), owned.next_owned().unwrap());
+            let expected = borrowed.next_n(3).unwrap().map(|v| v.into_iter().copied().collect());
+            assert_eq!(expected, owned.next_n_owned(3).unwrap());
+            let expected = borrowed.unique_n(5).unwrap().map(|v| v.into_iter().copied().collect());
+            assert_eq!(expected, owned.unique_n_owned(5).unwrap());
+        }
+        assert_eq!(borrowed.dump_sorted(), owned.dump_sorted());
 
-        assert!(shuffler.unique_n(0).unwrap().unwrap().is_empty());
+        // Owned items can be held while the shuffler is used again.
+        let first = owned.next_owned().unwrap().unwrap();
+        owned.remove(&first).unwrap();
+        assert_eq!(owned.unique_n_owned(20).unwrap(), None);
+        assert_eq!(owned.unique_n_owned(19).unwrap().unwrap().len(), 19);
 
-        let n = shuffler.unique_n(1).unwrap().unwrap();
-        assert_eq!(n.len(), 1);
-        assert_eq!(n[0], &0);
-        assert_eq!(shuffler.tree.generations(), (4, 4));
-        assert!(shuffler.unique_n(2).unwrap().is_none());
+        let mut empty: Shuffler<i32> = Shuffler::default();
+        assert_eq!(empty.next_owned().unwrap(), None);
+        assert_eq!(empty.next_n_owned(0).unwrap(), None);
+    }
 
-        assert_eq!(shuffler.remove(&0).unwrap().unwrap(), 0);
-        assert_eq!(shuffler.tree.generations(), (0, 0));
+    #[test]
+    fn sample_from() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 5);
+        shuffler.add_with_generation("old", Generation::new(0)).unwrap();
+        shuffler.add_with_generation("middle", Generation::new(50)).unwrap();
+        shuffler.add_with_generation("new", Generation::new(100)).unwrap();
+        let before = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect::<Vec<_>>();
+
+        let candidates = ["old", "middle", "new", "unknown"];
+        let mut counts = [0; 4];
+        for _ in 0..2000 {
+            let picked = shuffler.sample_from(&candidates, 1);
+            assert_eq!(picked.len(), 1);
+            counts[candidates.iter().position(|c| c == picked[0]).unwrap()] += 1;
+        }
+        let [old, middle, new, unknown] = counts;
+        assert!(old > 500 && unknown > 500, "{counts:?}");
+        assert!(middle > new * 3 && old > middle && unknown > middle, "{counts:?}");
+        assert!(new < 100, "{counts:?}");
+
+        let mut all = shuffler.sample_from(&candidates, 10);
+        all.sort_unstable();
+        assert_eq!(all, [&"middle", &"new", &"old", &"unknown"]);
+        assert_eq!(shuffler.sample_from(&candidates, 3).len(), 3);
+        assert!(shuffler.sample_from(&candidates, 0).is_empty());
+        assert!(shuffler.sample_from([], 3).is_empty());
+
+        let after = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
 
-        assert!(shuffler.remove(&0).unwrap().is_none());
+    #[test]
+    fn hash_only() {
+        // Deliberately not Ord.
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct Unordered(u32);
+
+        let mut shuffler = ShufflerBuilder::new().build_hash_only();
+        for i in 0..100 {
+            assert!(shuffler.add(Unordered(i)).unwrap());
+        }
+        assert!(!shuffler.add(Unordered(5)).unwrap());
+
+        let unique = shuffler.unique_n(100).unwrap().unwrap();
+        assert!((0..100).all(|i| unique.contains(&&Unordered(i))));
+        assert_eq!(shuffler.remove(&Unordered(5)).unwrap(), Some(Unordered(5)));
+        assert_eq!(shuffler.remove(&Unordered(5)).unwrap(), None);
+        assert_eq!(shuffler.size(), 99);
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
     }
 
     #[test]
-    fn one_item_inf() {
-        let mut shuffler = ShufflerGeneric::default();
+    fn deterministic_sequence() {
+        let new = || {
+            let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 11);
+            for i in 0..50 {
+                shuffler.add(i).unwrap();
+            }
+            shuffler
+        };
+
+        let mut a = new();
+        let mut b = new();
+        let sequence = a.deterministic_sequence(200).unwrap();
+        assert_eq!(sequence.len(), 200);
+        assert_eq!(sequence, b.deterministic_sequence(200).unwrap());
+        assert_eq!(a.dump_sorted(), b.dump_sorted());
+
+        let mut c = new();
+        let expected: Vec<_> = (0..200).map(|_| c.next_owned().unwrap().unwrap()).collect();
+        assert_eq!(sequence, expected);
+
+        let mut empty: Shuffler<i32> = Shuffler::default();
+        assert!(empty.deterministic_sequence(5).unwrap().is_empty());
+    }
 
-        assert!(shuffler.add(0).unwrap());
+    #[cfg(feature = "selection-counts")]
+    #[test]
+    fn selection_counts() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 5);
+        for i in 0..10 {
+            shuffler.add(i).unwrap();
+        }
+        assert!(shuffler.selection_counts().iter().all(|(_, c)| *c == 0));
 
-        assert_eq!(shuffler.inf_next().unwrap(), &0);
-        assert!(shuffler.inf_next_n(0).unwrap().is_empty());
-        assert_eq!(shuffler.tree.generations(), (1, 1));
+        shuffler.preview_n(5).unwrap();
+        shuffler.sample_n(5);
+        assert!(shuffler.selection_counts().iter().all(|(_, c)| *c == 0));
 
-        let n = shuffler.inf_next_n(1).unwrap();
-        assert_eq!(n.len(), 1);
-        assert_eq!(n[0], &0);
-        assert_eq!(shuffler.tree.generations(), (2, 2));
+        for _ in 0..5000 {
+            shuffler.next().unwrap();
+        }
+        for _ in 0..500 {
+            shuffler.next_n(5).unwrap();
+            shuffler.unique_n(5).unwrap();
+        }
 
-        let n = shuffler.inf_next_n(2).unwrap();
-        assert_eq!(n.len(), 2);
-        assert_eq!((n[0], n[1]), (&0, &0));
-        assert_eq!(shuffler.tree.generations(), (3, 3));
+        let counts = shuffler.selection_counts();
+        assert_eq!(counts.iter().map(|(_, c)| c).sum::<u64>(), 10000);
+        for (i, c) in &counts {
+            assert!((900..=1100).contains(c), "{i} selected {c} times");
+        }
 
-        assert!(shuffler.inf_unique_n(0).unwrap().is_empty());
+        // Removing items restructures the tree, but counts stay with their items.
+        let mut before: Vec<_> = counts.into_iter().map(|(i, c)| (*i, c)).collect();
+        for i in [4, 0, 7] {
+            shuffler.remove(&i).unwrap();
+            before.retain(|(item, _)| *item != i);
+            let mut after: Vec<_> =
+                shuffler.selection_counts().into_iter().map(|(i, c)| (*i, c)).collect();
+            after.sort_unstable();
+            before.sort_unstable();
+            assert_eq!(after, before);
+        }
+    }
 
-        let n = shuffler.inf_unique_n(1).unwrap();
-        assert_eq!(n.len(), 1);
-        assert_eq!(n[0], &0);
-        assert_eq!(shuffler.tree.generations(), (4, 4));
-        assert!(shuffler.inf_unique_n(2).is_none());
+    #[cfg(feature = "selection-counts")]
+    #[test]
+    fn reset_selection_counts() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 6);
+        for i in 0..10 {
+            shuffler.add(i).unwrap();
+        }
+        shuffler.next_n(20).unwrap();
 
-        assert_eq!(shuffler.inf_remove(&0).unwrap(), 0);
-        assert_eq!(shuffler.tree.generations(), (0, 0));
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        shuffler.reset_selection_counts();
+        assert!(shuffler.selection_counts().iter().all(|(_, c)| *c == 0));
+        assert!(shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).eq(before));
 
-        assert!(shuffler.inf_remove(&0).is_none());
+        let selected: Vec<_> = shuffler.inf_unique_n(3).unwrap().into_iter().copied().collect();
+        shuffler.next().unwrap();
+
+        let counts = shuffler.selection_counts();
+        assert_eq!(counts.iter().map(|(_, c)| c).sum::<u64>(), 4);
+        for i in selected {
+            assert!(counts.iter().any(|(item, c)| **item == i && *c >= 1));
+        }
     }
 
     #[test]
-    fn leftmost_oldest_fal() {
-        let mut shuffler = new_default_leftmost_oldest();
-
-        assert!(shuffler.add("b").is_ok());
-        assert!(shuffler.add("c").is_ok());
-        assert!(shuffler.add("d").is_ok());
-        assert!(shuffler.add("e").is_ok());
+    fn empty_item() {
+        let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+        assert_eq!(shuffler.generations_of(&[String::new()]), [None]);
+
+        assert!(shuffler.add(String::new()).unwrap());
+        assert!(!shuffler.add(String::new()).unwrap());
+        assert!(shuffler.add("a".to_string()).unwrap());
+        let gens = shuffler.generations_of(&[String::new(), "b".to_string()]);
+        assert_eq!(gens, [Some(Generation::new(0)), None]);
+        assert_eq!(shuffler.values_sorted(), [&String::new(), &"a".to_string()]);
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+
+        assert_eq!(shuffler.remove(""), Ok(Some(String::new())));
+        assert_eq!(shuffler.remove(""), Ok(None));
+        assert_eq!(shuffler.generations_of(&[String::new()]), [None]);
+        assert_eq!(shuffler.next(), Ok(Some(&"a".to_string())));
+    }
+}
 
-        assert_eq!(shuffler.next().unwrap().unwrap(), &"b");
-        assert_eq!(shuffler.next().unwrap().unwrap(), &"c");
-        assert_eq!(shuffler.next().unwrap().unwrap(), &"d");
+#[cfg(all(test, not(feature = "std")))]
+mod no_std_tests {
+    use crate::{AwShuffler, Generation, InfallibleShuffler, NewItemHandling, Shuffler};
 
-        assert!(shuffler.add("a").is_ok());
+    #[test]
+    fn add_next() {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 1);
+        assert!(shuffler.inf_next().is_none());
 
-        assert_eq!(shuffler.next().unwrap().unwrap(), &"a");
+        for i in 0..10 {
+            assert!(shuffler.add(i).unwrap());
+        }
+        assert!(!shuffler.inf_add(3));
 
-        let v = shuffler.next_n(3).unwrap().unwrap();
-        let expected = ["e", "b", "c"];
-        v.into_iter().zip(expected.iter()).for_each(|(a, b)| assert_eq!(a, b));
+        let first = *shuffler.inf_next().unwrap();
+        assert_eq!(shuffler.dump().iter().filter(|(_, g)| *g == Generation::new(1)).count(), 1);
+        assert!(shuffler.dump().contains(&(&first, Generation::new(1))));
 
-        let v = shuffler.unique_n(5).unwrap().unwrap();
-        // b, c, and e all have the same generation
-        let expected = ["d", "a", "b", "c", "e"];
-        v.into_iter().zip(expected.iter()).for_each(|(a, b)| assert_eq!(a, b));
+        assert_eq!(shuffler.inf_next_n(5).unwrap().len(), 5);
+        assert_eq!(shuffler.inf_unique_n(10).unwrap().len(), 10);
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
     }
 }
@@ -0,0 +1,247 @@
+//! Module containing an async wrapper around the RocksDB [`Shuffler`].
+
+use std::thread;
+
+use tokio::sync::{mpsc, oneshot};
+
+use super::rocksdb::{Error, Shuffler};
+use super::{Item, PersistentShuffler};
+use crate::AwShuffler;
+
+// Large enough that callers rarely wait on a busy worker, small enough that a stalled worker
+// pushes back on callers quickly.
+const CHANNEL_CAPACITY: usize = 64;
+
+type Reply<V> = oneshot::Sender<Result<V, Error>>;
+
+enum Command<T> {
+    Next(Reply<Option<T>>),
+    NextN(usize, Reply<Option<Vec<T>>>),
+    UniqueN(usize, Reply<Option<Vec<T>>>),
+    Add(T, Reply<bool>),
+    Load(T, Reply<bool>),
+    Remove(T, Reply<Option<T>>),
+    SoftRemove(T, Reply<Option<T>>),
+    Values(Reply<Vec<T>>),
+    Size(Reply<usize>),
+    Close(Reply<()>),
+}
+
+/// An async handle to a RocksDB [`Shuffler`] that runs on a dedicated OS thread.
+///
+/// Every database operation is blocking, so rather than running them on the async runtime the
+/// shuffler is moved to its own thread and each method sends a command to it over a channel.
+/// Items are returned as owned clones since references cannot outlive the command.
+///
+/// # Ordering
+///
+/// Commands are executed one at a time in the order they are sent. Two calls awaited in
+/// sequence on one task always observe each other's effects. Calls made concurrently from
+/// different tasks are executed in the order they enter the channel.
+///
+/// # Backpressure
+///
+/// The channel is bounded. Once it holds 64 pending commands, further calls wait until the
+/// worker catches up before their command is queued.
+///
+/// # Closing
+///
+/// [`close`](Self::close) flushes pending writes and waits for the thread to finish. If the handle
+/// is dropped instead, the worker finishes any queued commands and then drops the shuffler, which
+/// flushes but discards any errors. Dropping does not wait for the thread.
+#[must_use = "async shufflers should be closed with `close` to flush pending writes"]
+pub struct AsyncShuffler<T> {
+    tx: mpsc::Sender<Command<T>>,
+    // Completed when the worker finishes, or dropped without a value if it panics.
+    done: oneshot::Receiver<()>,
+}
+
+impl<T> std::fmt::Debug for AsyncShuffler<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncShuffler").finish_non_exhaustive()
+    }
+}
+
+impl<T> AsyncShuffler<T>
+where
+    T: Item + Clone + Send + 'static,
+{
+    /// Moves `shuffler` onto a new thread and returns a handle to it.
+    ///
+    /// # Panics
+    /// Panics if the thread cannot be spawned.
+    pub fn new(shuffler: Shuffler<T>) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (done_tx, done) = oneshot::channel();
+        thread::Builder::new()
+            .name("aw-shuffle".to_string())
+            .spawn(move || {
+                run(shuffler, rx);
+                let _ = done_tx.send(());
+            })
+            .expect("Failed to spawn shuffler thread");
+
+        Self { tx, done }
+    }
+
+    async fn call<V>(&self, command: impl FnOnce(Reply<V>) -> Command<T>) -> Result<V, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(command(tx)).await.map_err(|_| Error::Disconnected)?;
+        rx.await.map_err(|_| Error::Disconnected)?
+    }
+
+    /// See [`AwShuffler::next`].
+    pub async fn next(&self) -> Result<Option<T>, Error> {
+        self.call(Command::Next).await
+    }
+
+    /// See [`AwShuffler::next_n`].
+    pub async fn next_n(&self, n: usize) -> Result<Option<Vec<T>>, Error> {
+        self.call(|tx| Command::NextN(n, tx)).await
+    }
+
+    /// See [`AwShuffler::unique_n`].
+    pub async fn unique_n(&self, n: usize) -> Result<Option<Vec<T>>, Error> {
+        self.call(|tx| Command::UniqueN(n, tx)).await
+    }
+
+    /// See [`AwShuffler::add`].
+    pub async fn add(&self, item: T) -> Result<bool, Error> {
+        self.call(|tx| Command::Add(item, tx)).await
+    }
+
+    /// See [`PersistentShuffler::load`].
+    pub async fn load(&self, item: T) -> Result<bool, Error> {
+        self.call(|tx| Command::Load(item, tx)).await
+    }
+
+    /// See [`AwShuffler::remove`].
+    pub async fn remove(&self, item: T) -> Result<Option<T>, Error> {
+        self.call(|tx| Command::Remove(item, tx)).await
+    }
+
+    /// See [`PersistentShuffler::soft_remove`].
+    pub async fn soft_remove(&self, item: T) -> Result<Option<T>, Error> {
+        self.call(|tx| Command::SoftRemove(item, tx)).await
+    }
+
+    /// See [`AwShuffler::values`].
+    pub async fn values(&self) -> Result<Vec<T>, Error> {
+        self.call(Command::Values).await
+    }
+
+    /// See [`AwShuffler::size`].
+    pub async fn size(&self) -> Result<usize, Error> {
+        self.call(Command::Size).await
+    }
+
+    /// Closes the shuffler, flushing pending writes, and waits for its thread to finish without
+    /// blocking the runtime.
+    ///
+    /// Commands sent before this call are executed first. Returns [`Error::Disconnected`] if the
+    /// worker thread panicked.
+    pub async fn close(self) -> Result<(), Error> {
+        let result = self.call(Command::Close).await;
+        drop(self.tx);
+        // The worker finishes as soon as it replies or sees the closed channel.
+        self.done.await.map_err(|_| Error::Disconnected)?;
+        result
+    }
+}
+
+fn run<T: Item + Clone>(mut shuffler: Shuffler<T>, mut rx: mpsc::Receiver<Command<T>>) {
+    // Send errors only mean the caller stopped waiting, which isn't a problem here.
+    while let Some(command) = rx.blocking_recv() {
+        match command {
+            Command::Next(tx) => drop(tx.send(shuffler.next_owned())),
+            Command::NextN(n, tx) => drop(tx.send(shuffler.next_n_owned(n))),
+            Command::UniqueN(n, tx) => drop(tx.send(shuffler.unique_n_owned(n))),
+            Command::Add(item, tx) => drop(tx.send(shuffler.add(item))),
+            Command::Load(item, tx) => drop(tx.send(shuffler.load(item))),
+            Command::Remove(item, tx) => drop(tx.send(shuffler.remove(&item))),
+            Command::SoftRemove(item, tx) => drop(tx.send(shuffler.soft_remove(&item))),
+            Command::Values(tx) => {
+                drop(tx.send(Ok(shuffler.values().into_iter().cloned().collect())));
+            }
+            Command::Size(tx) => drop(tx.send(Ok(shuffler.size()))),
+            Command::Close(tx) => {
+                drop(tx.send(shuffler.close()));
+                return;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn async_shuffler() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 3])).unwrap();
+        let shuffler = AsyncShuffler::new(shuffler);
+
+        assert!(shuffler.add(4).await.unwrap());
+        assert!(!shuffler.add(4).await.unwrap());
+        assert_eq!(shuffler.remove(1).await.unwrap(), Some(1));
+        assert_eq!(shuffler.size().await.unwrap(), 3);
+
+        let mut unique = shuffler.unique_n(3).await.unwrap().unwrap();
+        unique.sort_unstable();
+        assert_eq!(unique, [2, 3, 4]);
+        assert_eq!(shuffler.next_n(5).await.unwrap().unwrap().len(), 5);
+        assert!(shuffler.next().await.unwrap().is_some());
+
+        let mut values = shuffler.values().await.unwrap();
+        values.sort_unstable();
+        assert_eq!(values, [2, 3, 4]);
+
+        shuffler.close().await.unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        let mut values = shuffler.close_into_values().unwrap();
+        values.sort_unstable();
+        assert_eq!(values, [2, 3, 4]);
+    }
+
+    #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    struct PanicOnClone(u32);
+
+    impl Clone for PanicOnClone {
+        fn clone(&self) -> Self {
+            panic!("cloned {}", self.0)
+        }
+    }
+
+    impl Serialize for PanicOnClone {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.0.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for PanicOnClone {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            u32::deserialize(deserializer).map(Self)
+        }
+    }
+
+    #[tokio::test]
+    async fn worker_panic() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let shuffler = Shuffler::new_default(&path, Some(vec![PanicOnClone(1)])).unwrap();
+        let shuffler = AsyncShuffler::new(shuffler);
+
+        assert!(matches!(shuffler.next().await, Err(Error::Disconnected)));
+        assert!(matches!(shuffler.size().await, Err(Error::Disconnected)));
+        assert!(matches!(shuffler.close().await, Err(Error::Disconnected)));
+    }
+}
@@ -1,10 +1,15 @@
 //! Module containing shufflers that are backed by a persistent database.
 
+use std::borrow::Borrow;
+use std::io::Write;
+
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::{AwShuffler, NewItemHandling};
+use crate::{AwShuffler, BiasError, MaybePersistent, NewItemHandling, Query};
 
+#[cfg(feature = "tokio")]
+pub mod async_rocksdb;
 #[cfg(feature = "rocks")]
 pub mod rocksdb;
 
@@ -25,7 +30,11 @@ pub mod rocksdb;
 ///
 /// # Limitations
 /// The backing database may impose a limit on the serialized size of each item. For
-/// [`rocksdb::Shuffler`] the limit is 8MB, using MessagePack.
+/// [`rocksdb::Shuffler`] the limit is 8MB by default, using MessagePack, and can be raised with
+/// [`Options::max_item_size`].
+///
+/// Empty items, such as an empty string or vector, are supported. MessagePack always writes a
+/// type marker, so no item serializes to an empty key.
 pub trait Item: super::Item + Serialize + DeserializeOwned {}
 impl<I: super::Item + Serialize + DeserializeOwned> Item for I {}
 
@@ -75,6 +84,14 @@ where
     /// Returns `true` if the item was not present in memory.
     fn load(&mut self, item: Self::Item) -> Result<bool, Self::Error>;
 
+    /// Equivalent to calling [`load`](Self::load) for every item, but reads the items from the
+    /// database in a single batch.
+    ///
+    /// Returns the number of items that were not already present in memory.
+    fn load_all_items<I>(&mut self, items: I) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = Self::Item>;
+
     /// Removes the item from the shuffler, returning it if it was present in memory. Does not
     /// remove the item from the underlying database, leaving it available for future runs or
     /// future [`load`](Self::load) calls.
@@ -83,7 +100,20 @@ where
     /// using [`remove`](AwShuffler::remove) alone, it will need to be added then removed, or
     /// cleared by a future shuffler initialized with [`Options::keep_unrecognized`] set to
     /// `true`.
-    fn soft_remove(&mut self, item: &Self::Item) -> Result<Option<Self::Item>, Self::Error>;
+    fn soft_remove<Q>(&mut self, item: &Q) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized;
+
+    /// Writes every item in the database and its generation to `w`, streaming them directly from
+    /// the database instead of collecting them in memory first. Unlike
+    /// [`dump`](AwShuffler::dump) this includes items that are only in the database, such as
+    /// those removed with [`soft_remove`](Self::soft_remove).
+    ///
+    /// Each entry is written as a big-endian `u32` length, the MessagePack encoding of the item
+    /// with that length, and the generation as a big-endian `u64`. Entries are written in no
+    /// specific order and the stream ends when `w` does.
+    fn dump_to<W: Write>(&self, w: &mut W) -> Result<(), Self::Error>;
 
 
     /// Flushes any pending changes to disk and runs any garbage collection or compaction routines
@@ -98,28 +128,74 @@ where
     /// If this is not called it will be called on drop, but any errors will be lost.
     fn close(self) -> Result<(), Self::Error>;
 
+    /// Cleanly shut down the persistent shuffler like [`close`](Self::close), but return the
+    /// shuffler along with the error if the data could not be flushed to disk.
+    ///
+    /// The returned shuffler is unchanged and can be used normally, closed again, or leaked with
+    /// [`close_no_dealloc`](Self::close_no_dealloc).
+    fn try_close(self) -> Result<(), (Self, Self::Error)>
+    where
+        Self: Sized;
+
     /// Cleanly shut down the persistent shuffler and ensure all data is flushed to disk, but
     /// also return all values in no specific order.
     ///
     /// If this is not called it will be called on drop, but any errors will be lost.
     fn close_into_values(self) -> Result<Vec<Self::Item>, Self::Error>;
 
-    /// Cleanly shut down the database connection but leak the in-memory shuffler.
+    /// Cleanly shut down the persistent shuffler like [`close`](Self::close), flushing all data
+    /// to disk, but never free the memory used by the in-memory shuffler.
     ///
-    /// This can be used to defer cleanup until the process is terminated. It's only useful when
-    /// leaking memory is no longer a concern.
-    ///
-    /// Hidden in docs because this is generally a bad idea.
-    #[doc(hidden)]
-    fn close_leak(self) -> Result<(), Self::Error>;
+    /// Freeing every item of a large shuffler takes noticeable time, which is wasted when the
+    /// process is about to exit and the operating system will reclaim the memory anyway. This is
+    /// appropriate right before process exit, such as at the end of a short-lived command line
+    /// program. Anywhere else the memory is leaked for the rest of the process.
+    fn close_no_dealloc(self) -> Result<(), Self::Error>;
+
+    /// Renamed to [`close_no_dealloc`](Self::close_no_dealloc).
+    #[deprecated(note = "renamed to close_no_dealloc")]
+    fn close_leak(self) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        self.close_no_dealloc()
+    }
 }
 
+impl<P> MaybePersistent for P
+where
+    P: PersistentShuffler,
+    P::Item: Item,
+{
+    fn compact(&mut self) -> Result<(), Self::Error> {
+        PersistentShuffler::compact(self)
+    }
+
+    fn close(self) -> Result<(), Self::Error> {
+        PersistentShuffler::close(self)
+    }
+}
+
+/// A function converting a key written in an old serialized format into the current one. See
+/// [`Options::migrate`].
+pub type Migration = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
 /// Options for initializing a [`PersistentShuffler`].
 pub struct Options {
     bias: f64,
     new_item_handling: NewItemHandling,
     remove_on_deserialization_error: bool,
     keep_unrecognized: bool,
+    error_on_duplicate_items: bool,
+    seed: Option<u64>,
+    hash_seed: Option<u64>,
+    column_family: Option<String>,
+    max_open_files: i32,
+    keep_log_file_num: usize,
+    compaction_readahead_size: usize,
+    expected_items: Option<usize>,
+    max_item_size: u32,
+    migrate: Option<Migration>,
 }
 
 impl Default for Options {
@@ -129,6 +205,17 @@ impl Default for Options {
             new_item_handling: NewItemHandling::NeverSelected,
             remove_on_deserialization_error: false,
             keep_unrecognized: false,
+            error_on_duplicate_items: false,
+            seed: None,
+            hash_seed: None,
+            column_family: None,
+            max_open_files: 100,
+            keep_log_file_num: 10,
+            // Much more efficient on slower storage, probably minimal impact on fast storage.
+            compaction_readahead_size: 2 * 1024 * 1024,
+            expected_items: None,
+            max_item_size: 8 * 1024 * 1024,
+            migrate: None,
         }
     }
 }
@@ -147,6 +234,15 @@ impl Options {
         self
     }
 
+    /// Controls how strongly the shuffler is biased towards older items, like
+    /// [`bias`](Self::bias), but returns an error instead of panicking if `bias` is negative or
+    /// NaN.
+    pub fn try_bias(mut self, bias: f64) -> Result<Self, BiasError> {
+        BiasError::check(bias)?;
+        self.bias = bias;
+        Ok(self)
+    }
+
     /// See [`Shuffler::new`](crate::Shuffler::new)
     #[must_use]
     pub const fn new_item_handling(mut self, new_item_handling: NewItemHandling) -> Self {
@@ -161,7 +257,8 @@ impl Options {
     /// Setting this to `true` will cause any keys that can't be deserialized to be removed from the
     /// database silently without exposing an error. The intended use case is for when the
     /// structure or serialized format is expected to change in a partially backwards-incompatible
-    /// way.
+    /// way. If [`keep_unrecognized`](Self::keep_unrecognized) is also set the keys are skipped but
+    /// left in the database.
     #[must_use]
     pub const fn remove_on_deserialization_error(
         mut self,
@@ -184,4 +281,127 @@ impl Options {
         self.keep_unrecognized = keep_unrecognized;
         self
     }
+
+    /// Controls whether repeated items in the [`items`](rocksdb::Shuffler::new) vector are an
+    /// error.
+    ///
+    /// The default value is `false`, which silently ignores repeats.
+    ///
+    /// Setting this to `true` will cause opening the shuffler to fail with
+    /// [`Error::DuplicateItem`](rocksdb::Error::DuplicateItem) if any item is provided more than
+    /// once.
+    #[must_use]
+    pub const fn error_on_duplicate_items(mut self, error_on_duplicate_items: bool) -> Self {
+        self.error_on_duplicate_items = error_on_duplicate_items;
+        self
+    }
+
+    /// Deterministically seeds the shuffler's random number generator and hasher. See
+    /// [`Shuffler::from_seed`](crate::Shuffler::from_seed).
+    ///
+    /// Given the same seed, the same database contents, and the same sequence of operations the
+    /// shuffler will make the same selections. By default the shuffler is seeded randomly.
+    #[must_use]
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Seeds only the shuffler's hasher, overriding the hasher derived from
+    /// [`seed`](Self::seed) if both are set. The random number generator is unaffected.
+    ///
+    /// Items are ordered in memory by their hashes, which are never stored in the database, so by
+    /// default the order changes every time the database is opened. With a fixed hash seed the
+    /// order is the same each time, which keeps anything based on that order, like
+    /// [`values`](AwShuffler::values), stable across restarts. A hash seed of `n` produces the
+    /// same order as a [`seed`](Self::seed) of `n`.
+    ///
+    /// The hash seed can be changed or removed at any time without affecting the database. Since a
+    /// fixed seed makes hashes predictable, avoid it when items may be chosen by an adversary.
+    #[must_use]
+    pub const fn hash_seed(mut self, hash_seed: u64) -> Self {
+        self.hash_seed = Some(hash_seed);
+        self
+    }
+
+    /// Stores the shuffler's items in the named column family instead of the default one, so that
+    /// several independent shufflers can share a single database. Shufflers using different
+    /// column families never see each other's items.
+    ///
+    /// A shuffler opened from a path holds the database's lock. To have several open at once, open
+    /// the database with [`open_shared`](rocksdb::open_shared) and create each shuffler with
+    /// [`Shuffler::new_shared`](rocksdb::Shuffler::new_shared).
+    #[must_use]
+    pub fn column_family(mut self, column_family: String) -> Self {
+        self.column_family = Some(column_family);
+        self
+    }
+
+    /// Sets the maximum number of files RocksDB keeps open. A value of -1 keeps every file open.
+    ///
+    /// The default value is 100. Large databases may benefit from a higher limit.
+    #[must_use]
+    pub const fn max_open_files(mut self, max_open_files: i32) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    /// Sets the number of RocksDB info log files to keep.
+    ///
+    /// The default value is 10.
+    #[must_use]
+    pub const fn keep_log_file_num(mut self, keep_log_file_num: usize) -> Self {
+        self.keep_log_file_num = keep_log_file_num;
+        self
+    }
+
+    /// Sets the size in bytes of RocksDB's readahead during compaction. A value of 0 disables it.
+    ///
+    /// The default value is 2MB, which helps on slower storage. Small databases can use less.
+    #[must_use]
+    pub const fn compaction_readahead_size(mut self, compaction_readahead_size: usize) -> Self {
+        self.compaction_readahead_size = compaction_readahead_size;
+        self
+    }
+
+    /// Hints at the number of items the database is expected to hold, so RocksDB's write buffer
+    /// and level sizes can be chosen to suit it when the database is opened.
+    ///
+    /// This only affects performance. The hint doesn't need to be exact and the database can
+    /// grow past it. By default RocksDB's own defaults are used, which suit databases of up to a
+    /// few hundred thousand items.
+    #[must_use]
+    pub const fn expected_items(mut self, expected_items: usize) -> Self {
+        self.expected_items = Some(expected_items);
+        self
+    }
+
+    /// Sets the largest serialized size, in bytes, of an item that can be written to the
+    /// database. Writing a larger item fails with
+    /// [`Error::ItemTooLarge`](rocksdb::Error::ItemTooLarge) before anything is changed.
+    ///
+    /// The default is 8MB. Raising it allows larger items to be stored, but every write of an
+    /// item copies all of it and RocksDB performs poorly with very large keys. Items already in
+    /// the database are loaded regardless of their size.
+    #[must_use]
+    pub const fn max_item_size(mut self, max_item_size: u32) -> Self {
+        self.max_item_size = max_item_size;
+        self
+    }
+
+    /// Sets a function to migrate keys written in an old serialized format. When a key can't be
+    /// deserialized it is passed to `migrate`, and if the returned bytes deserialize into an item
+    /// the item is loaded and rewritten under its current key, keeping its generation.
+    ///
+    /// If `migrate` returns `None`, or its output can't be deserialized either, the key is handled
+    /// according to [`remove_on_deserialization_error`](Self::remove_on_deserialization_error).
+    /// By default no migration is attempted.
+    ///
+    /// If the migrated item is already stored under another key, the old key is deleted and the
+    /// item keeps whichever of the two generations is newer.
+    #[must_use]
+    pub fn migrate(mut self, migrate: Migration) -> Self {
+        self.migrate = Some(migrate);
+        self
+    }
 }
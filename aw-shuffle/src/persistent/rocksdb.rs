@@ -1,21 +1,35 @@
 //! Module containing the [`PersistentShuffler`] backed by RocksDB.
 
+use std::borrow::Borrow;
+use std::cell::Cell;
 use std::fmt::Display;
-use std::hash::Hasher;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::Write;
 use std::mem::ManuallyDrop;
+use std::ops::Deref;
 use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
 
-use ahash::{AHashSet, AHasher};
+use ahash::{AHashSet, AHasher, RandomState};
 use rand::prelude::StdRng;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use rmp_serde::{decode, encode, Deserializer};
 use rocksdb::IteratorMode::Start;
-use rocksdb::{WriteBatch, DB};
-use serde::Deserialize;
+use rocksdb::{ColumnFamily, WriteBatch, DB, DEFAULT_COLUMN_FAMILY_NAME};
+use serde::{Deserialize, Serialize};
 
-use super::{Item, Options, PersistentShuffler};
-use crate::{AwShuffler, InfallibleShuffler, ShufflerGeneric as BaseShuffler};
+use super::{Item, Migration, Options, PersistentShuffler};
+use crate::rbtree::{Node, Rbtree};
+use crate::{
+    AwShuffler, Generation, InfallibleShuffler, IntegrityError, Query,
+    ShufflerGeneric as BaseShuffler, SnapshotReader,
+};
 
+// A rough estimate of the size of one item in the database, including RocksDB's overhead.
+const ESTIMATED_ITEM_SIZE: usize = 64;
+const MIN_WRITE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+const MAX_WRITE_BUFFER_SIZE: usize = 256 * 1024 * 1024;
 
 /// A simple wrapper around the different sources of errors that can happen.
 ///
@@ -27,11 +41,120 @@ pub enum Error {
     Serialization(encode::Error),
     /// An error during deserialization.
     ///
-    /// When [`Options::remove_on_deserialization_error`] is set to true this will never be
-    /// constructed.
+    /// Errors while reading an item or generation from the database are reported as
+    /// [`Error::DeserializationAt`] instead, which includes the key that failed.
     Deserialization(decode::Error),
+    /// An error during deserialization of an item or its generation read from the database.
+    ///
+    /// When [`Options::remove_on_deserialization_error`] is set to true this will never be
+    /// constructed while loading the database.
+    DeserializationAt {
+        /// The raw bytes of the key whose item or generation could not be deserialized.
+        bytes: Vec<u8>,
+        /// Whether the generation stored in the value failed to deserialize, rather than the key.
+        value: bool,
+        /// The underlying deserialization error.
+        source: decode::Error,
+    },
     /// An error from a database operation.
     DB(rocksdb::Error),
+    /// The database could not be opened because its lock is held, either by another process or by
+    /// another open shuffler in this process.
+    Locked(rocksdb::Error),
+    /// The shuffler was opened with [`Shuffler::open_secondary`] or [`Shuffler::open_read_only`]
+    /// and cannot write to the database.
+    ReadOnly,
+    /// An error writing to the output of [`PersistentShuffler::dump_to`].
+    Io(std::io::Error),
+    /// The items provided when opening the shuffler contained duplicates and
+    /// [`Options::error_on_duplicate_items`] is set.
+    DuplicateItem {
+        /// The serialized bytes of the first repeated item.
+        bytes: Vec<u8>,
+    },
+    /// The background thread of an
+    /// [`AsyncShuffler`](super::async_rocksdb::AsyncShuffler) exited before replying.
+    Disconnected,
+    /// An item could not be written because its serialized size is over
+    /// [`Options::max_item_size`].
+    ItemTooLarge {
+        /// The serialized size of the item in bytes.
+        size: usize,
+        /// The configured limit in bytes.
+        limit: u32,
+    },
+    /// The column family set by [`Options::column_family`] was not opened in the database passed
+    /// to [`Shuffler::new_shared`].
+    MissingColumnFamily(String),
+    /// [`ShufflerGeneric::into_db`] was called while the database handle was still shared with
+    /// other shufflers created with [`Shuffler::new_shared`] or with the caller.
+    SharedDatabase,
+}
+
+/// The category of an [`Error`], for matching on errors without inspecting their sources.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// See [`Error::Serialization`].
+    Serialization,
+    /// See [`Error::Deserialization`] and [`Error::DeserializationAt`].
+    Deserialization,
+    /// See [`Error::DB`].
+    Db,
+    /// See [`Error::Locked`].
+    Locked,
+    /// See [`Error::ReadOnly`].
+    ReadOnly,
+    /// See [`Error::Io`].
+    Io,
+    /// See [`Error::DuplicateItem`].
+    DuplicateItem,
+    /// See [`Error::Disconnected`].
+    Disconnected,
+    /// See [`Error::ItemTooLarge`].
+    ItemTooLarge,
+    /// See [`Error::MissingColumnFamily`].
+    MissingColumnFamily,
+    /// See [`Error::SharedDatabase`].
+    SharedDatabase,
+}
+
+impl Error {
+    /// Returns the category of this error.
+    #[must_use]
+    pub const fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Serialization(_) => ErrorKind::Serialization,
+            Self::Deserialization(_) | Self::DeserializationAt { .. } => {
+                ErrorKind::Deserialization
+            }
+            Self::DB(_) => ErrorKind::Db,
+            Self::Locked(_) => ErrorKind::Locked,
+            Self::ReadOnly => ErrorKind::ReadOnly,
+            Self::Io(_) => ErrorKind::Io,
+            Self::DuplicateItem { .. } => ErrorKind::DuplicateItem,
+            Self::Disconnected => ErrorKind::Disconnected,
+            Self::ItemTooLarge { .. } => ErrorKind::ItemTooLarge,
+            Self::MissingColumnFamily(_) => ErrorKind::MissingColumnFamily,
+            Self::SharedDatabase => ErrorKind::SharedDatabase,
+        }
+    }
+
+    /// Converts an error from opening a RocksDB database, returning [`Error::Locked`] if the
+    /// database's lock is held and [`Error::DB`] otherwise.
+    ///
+    /// This is useful when opening a shuffler's database directly with RocksDB.
+    // RocksDB doesn't expose a distinct error kind for lock failures, only IO errors with
+    // different messages depending on whether the lock is held by this process or another.
+    #[must_use]
+    pub fn from_open(e: rocksdb::Error) -> Self {
+        let msg: &str = e.as_ref();
+        let locked = e.kind() == rocksdb::ErrorKind::IOError
+            && msg.contains("/LOCK: ")
+            && (msg.contains("While lock file") || msg.contains("lock hold by current process"));
+
+        if locked { Self::Locked(e) } else { Self::DB(e) }
+    }
 }
 
 impl From<encode::Error> for Error {
@@ -52,40 +175,165 @@ impl From<rocksdb::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Serialization(e) => e.fmt(f),
             Self::Deserialization(e) => e.fmt(f),
+            Self::DeserializationAt { bytes, value, source } => {
+                let part = if *value { "generation" } else { "item" };
+                write!(f, "failed to deserialize {part} for key {bytes:?}: {source}")
+            }
             Self::DB(e) => e.fmt(f),
+            Self::Locked(e) => e.fmt(f),
+            Self::ReadOnly => f.write_str("cannot modify a read-only shuffler"),
+            Self::Io(e) => e.fmt(f),
+            Self::DuplicateItem { bytes } => write!(f, "duplicate item with key {bytes:?}"),
+            Self::Disconnected => f.write_str("shuffler thread exited"),
+            Self::ItemTooLarge { size, limit } => {
+                write!(f, "item of {size} bytes is larger than the limit of {limit} bytes")
+            }
+            Self::MissingColumnFamily(cf) => write!(f, "column family {cf:?} was not opened"),
+            Self::SharedDatabase => f.write_str("the database is still shared with other users"),
         }
     }
 }
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(match self {
-            Self::Serialization(e) => e,
-            Self::Deserialization(e) => e,
-            Self::DB(e) => e,
-        })
+        match self {
+            Self::Serialization(e) => Some(e),
+            Self::Deserialization(e) | Self::DeserializationAt { source: e, .. } => Some(e),
+            Self::DB(e) | Self::Locked(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::ReadOnly
+            | Self::DuplicateItem { .. }
+            | Self::Disconnected
+            | Self::ItemTooLarge { .. }
+            | Self::MissingColumnFamily(_)
+            | Self::SharedDatabase => None,
+        }
     }
 }
 
+/// A summary of the changes made while loading a database, returned by
+/// [`Shuffler::new_with_report`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OpenReport {
+    /// The number of items loaded from the database.
+    pub loaded: usize,
+    /// The number of keys that could not be deserialized and were dropped. This is always 0
+    /// unless [`Options::remove_on_deserialization_error`] is set, and always 0 if
+    /// [`Options::keep_unrecognized`] is set since the keys are left in the database.
+    pub dropped_bad_keys: usize,
+    /// The number of items removed from the database because they were not in the provided
+    /// items. This is always 0 if no items were provided or if [`Options::keep_unrecognized`] is
+    /// set.
+    pub removed_unrecognized: usize,
+    /// The number of keys that were rewritten in the current format by [`Options::migrate`].
+    pub migrated: usize,
+    /// The number of keys migrated by [`Options::migrate`] into an item that was already stored
+    /// under another key. The old key is deleted and the item keeps the newer of the two
+    /// generations. These are not counted in `loaded` or `migrated`.
+    pub migration_conflicts: usize,
+}
+
 /// A shuffler backed by RocksDB, where all database operations are completed synchronously.
 ///
 /// See [`PersistentShuffler`] for more documentation.
-#[derive(Debug)]
+///
+/// Dropping the shuffler flushes any pending writes but ignores errors. With the `log` feature
+/// enabled, debug builds log a warning when a shuffler with pending writes is dropped without
+/// being closed.
+#[must_use = "persistent shufflers should be closed with `close` to flush pending writes"]
 pub struct ShufflerGeneric<T, H, R> {
     internal: ManuallyDrop<BaseShuffler<T, H, R>>,
-    db: DB,
+    db: Database,
     closed: bool,
     leak: bool,
+    read_only: bool,
+    last_reset: bool,
 }
 
 /// Type alias for [`ShufflerGeneric`] with the default hasher and rng implementations.
 pub type Shuffler<T> = ShufflerGeneric<T, AHasher, StdRng>;
 
+impl<T: Item, H: Hasher + Clone, R> std::fmt::Debug for ShufflerGeneric<T, H, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShufflerGeneric")
+            .field("size", &self.internal.tree.size())
+            .field("generations", &self.internal.tree.generations())
+            .field("bias", &self.internal.bias)
+            .field("new_items", &self.internal.new_items)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The database along with the column family holding the shuffler's items.
+#[derive(Debug)]
+struct Database {
+    db: Arc<DB>,
+    cf: String,
+    // Whether anything has been written since the last flush.
+    pending: Cell<bool>,
+    max_item_size: u32,
+}
+
+impl Database {
+    fn new(db: Arc<DB>, cf: String, max_item_size: u32) -> Self {
+        Self { db, cf, pending: Cell::new(false), max_item_size }
+    }
+
+    // Serializes an item that is about to be written, enforcing the size limit.
+    fn encode_key<T: Serialize + ?Sized>(&self, item: &T) -> Result<Vec<u8>, Error> {
+        let key = encode::to_vec(item)?;
+        if key.len() > self.max_item_size as usize {
+            return Err(Error::ItemTooLarge { size: key.len(), limit: self.max_item_size });
+        }
+        Ok(key)
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        // The column family is always opened along with the database.
+        self.db.cf_handle(&self.cf).expect("column family was not opened")
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), rocksdb::Error> {
+        self.pending.set(true);
+        self.db.write(batch)
+    }
+
+    fn flush(&self) -> Result<(), rocksdb::Error> {
+        self.db.flush_cf(self.cf())?;
+        self.pending.set(false);
+        Ok(())
+    }
+
+    // Other shufflers may still be using a shared database, so only the last one stops RocksDB's
+    // background work.
+    fn cancel_all_background_work(&self, wait: bool) {
+        if Arc::strong_count(&self.db) == 1 {
+            self.db.cancel_all_background_work(wait);
+        }
+    }
+}
+
+impl Deref for Database {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        &self.db
+    }
+}
+
 
 impl<T, H, R> PersistentShuffler for ShufflerGeneric<T, H, R>
 where
@@ -94,6 +342,7 @@ where
     R: Rng,
 {
     fn load(&mut self, item: Self::Item) -> Result<bool, Self::Error> {
+        self.check_writable()?;
         if self.internal.tree.find_node(&item).is_some() {
             return Ok(false);
         }
@@ -104,30 +353,101 @@ where
         }
     }
 
-    fn soft_remove(&mut self, item: &Self::Item) -> Result<Option<Self::Item>, Self::Error> {
+    fn load_all_items<I>(&mut self, items: I) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = Self::Item>,
+    {
+        self.check_writable()?;
+        let items: Vec<_> = items
+            .into_iter()
+            .filter(|item| self.internal.tree.find_node(item).is_none())
+            .collect();
+        let keys = items.iter().map(encode::to_vec).collect::<Result<Vec<_>, _>>()?;
+        let cf = self.db.cf();
+        let values = self.db.multi_get_cf(keys.iter().map(|key| (cf, key)));
+
+        let mut loaded = 0;
+        for ((item, key), value) in items.into_iter().zip(keys).zip(values) {
+            // The same item may be present more than once.
+            if self.internal.tree.find_node(&item).is_some() {
+                continue;
+            }
+
+            match value? {
+                Some(value) => {
+                    let gen = Self::decode_generation(key, &value)?;
+                    self.internal.tree.insert(item, gen);
+                }
+                None => {
+                    self.add(item)?;
+                }
+            }
+            loaded += 1;
+        }
+
+        Ok(loaded)
+    }
+
+    fn soft_remove<Q>(&mut self, item: &Q) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        self.check_writable()?;
         Ok(self.internal.inf_remove(item))
     }
 
+    fn dump_to<W: Write>(&self, w: &mut W) -> Result<(), Self::Error> {
+        for r in self.db.iterator_cf(self.db.cf(), Start) {
+            let (key, value) = r?;
+            let gen = Self::decode_generation(key.to_vec(), &value)?;
+
+            // Items are limited by Options::max_item_size so the length always fits.
+            let len = u32::try_from(key.len()).expect("Item too large");
+            w.write_all(&len.to_be_bytes())?;
+            w.write_all(&key)?;
+            w.write_all(&gen.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+
     fn compact(&mut self) -> Result<(), Self::Error> {
-        self.db.compact_range::<&[u8], &[u8]>(None, None);
+        self.check_writable()?;
+        self.db.compact_range_cf::<&[u8], &[u8]>(self.db.cf(), None, None);
         self.db.flush().map_err(Into::into)
     }
 
     fn close(mut self) -> Result<(), Self::Error> {
         self.closed = true;
-        self.db.flush()?;
+        if !self.read_only {
+            self.db.flush()?;
+        }
+        self.db.cancel_all_background_work(true);
+        Ok(())
+    }
+
+    fn try_close(mut self) -> Result<(), (Self, Self::Error)> {
+        if !self.read_only {
+            if let Err(e) = self.db.flush() {
+                return Err((self, e.into()));
+            }
+        }
+        self.closed = true;
         self.db.cancel_all_background_work(true);
         Ok(())
     }
 
     fn close_into_values(mut self) -> Result<Vec<Self::Item>, Self::Error> {
         self.closed = true;
-        self.db.flush()?;
+        if !self.read_only {
+            self.db.flush()?;
+        }
         self.db.cancel_all_background_work(true);
         Ok(self.into_values())
     }
 
-    fn close_leak(mut self) -> Result<(), Self::Error> {
+    fn close_no_dealloc(mut self) -> Result<(), Self::Error> {
         self.leak = true;
         self.close()
     }
@@ -143,59 +463,344 @@ where
     type Item = T;
 
     fn add(&mut self, item: Self::Item) -> Result<bool, Self::Error> {
+        self.check_writable()?;
         let gen = self.internal.add_generation();
 
-        Self::put_batch(&self.db, &[&item], gen)?;
+        Self::put_new(&self.db, &item, gen)?;
         Ok(self.internal.tree.insert(item, gen))
     }
 
-    fn remove(&mut self, item: &Self::Item) -> Result<Option<Self::Item>, Self::Error> {
+    fn add_with_generation(
+        &mut self,
+        item: Self::Item,
+        generation: Generation,
+    ) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        if self.internal.tree.find_node(&item).is_some() {
+            return Ok(false);
+        }
+
+        Self::put_new(&self.db, &item, generation.get())?;
+        Ok(self.internal.tree.insert(item, generation.get()))
+    }
+
+    fn add_or_get_generation(
+        &mut self,
+        item: Self::Item,
+    ) -> Result<(bool, Generation), Self::Error> {
+        self.check_writable()?;
+        if let Some(node) = self.internal.tree.find_node(&item) {
+            return Ok((false, Generation::new(unsafe { node.as_ref().generation() })));
+        }
+
+        let gen = self.internal.add_generation();
+        Self::put_new(&self.db, &item, gen)?;
+        self.internal.tree.insert(item, gen);
+        Ok((true, Generation::new(gen)))
+    }
+
+    fn remove<Q>(&mut self, item: &Q) -> Result<Option<Self::Item>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        self.check_writable()?;
         let removed = self.internal.inf_remove(item);
-        if removed.is_some() {
-            self.delete(item)?;
+        if let Some(removed) = &removed {
+            self.delete(removed)?;
+        }
+        Ok(removed)
+    }
+
+    fn remove_with_generation<Q>(
+        &mut self,
+        item: &Q,
+    ) -> Result<Option<(Self::Item, Generation)>, Self::Error>
+    where
+        Self::Item: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        self.check_writable()?;
+        let removed = self.internal.inf_remove_with_generation(item);
+        if let Some((removed, _)) = &removed {
+            self.delete(removed)?;
         }
         Ok(removed)
     }
 
+    fn replace(&mut self, old: &Self::Item, new: Self::Item) -> Result<bool, Self::Error> {
+        self.check_writable()?;
+        let old_key = encode::to_vec(old)?;
+        // The size limit only applies if this creates a new key.
+        let new_key = if self.internal.tree.find_node(&new).is_some() {
+            encode::to_vec(&new)?
+        } else {
+            self.db.encode_key(&new)?
+        };
+
+        let Some((_, gen)) = self.internal.tree.delete(old) else {
+            return Ok(false);
+        };
+        let new_present = self.internal.tree.find_node(&new).is_some();
+
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(self.db.cf(), old_key);
+        if !new_present {
+            batch.put_cf(self.db.cf(), new_key, encode::to_vec(&gen)?);
+        }
+        self.db.write(batch)?;
+
+        if !new_present {
+            self.internal.tree.insert(new, gen);
+        }
+        Ok(true)
+    }
+
+    fn retain<F>(&mut self, f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&Self::Item) -> bool,
+    {
+        self.check_writable()?;
+        let removed = self.internal.tree.retain(f);
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        for item in &removed {
+            batch.delete_cf(self.db.cf(), encode::to_vec(item)?);
+        }
+        self.db.write(batch).map_err(Into::into)
+    }
+
     fn next(&mut self) -> Result<Option<&Self::Item>, Self::Error> {
+        self.check_writable()?;
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_next());
+        }
+
         let (gen, reset) = self.internal.next_generation();
         if reset {
             self.handle_reset()?;
         }
 
         let next = self.internal.inf_next();
+        // The in-memory shuffler's flag is overwritten by selecting, after the reset above.
+        self.last_reset = reset;
+        if let Some(next) = next {
+            Self::put_batch(&self.db, &[next], gen.get())?;
+        }
+        Ok(next)
+    }
+
+    fn next_with_bias(&mut self, bias: f64) -> Result<Option<&Self::Item>, Self::Error> {
+        self.check_writable()?;
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_next_with_bias(bias));
+        }
+
+        let (gen, reset) = self.internal.next_generation();
+        if reset {
+            self.handle_reset()?;
+        }
+
+        let next = self.internal.inf_next_with_bias(bias);
+        self.last_reset = reset;
+        if let Some(next) = next {
+            Self::put_batch(&self.db, &[next], gen.get())?;
+        }
+        Ok(next)
+    }
+
+    fn next_with_floor(
+        &mut self,
+        min_generation_gap: u64,
+    ) -> Result<Option<&Self::Item>, Self::Error> {
+        self.check_writable()?;
+        // Check first so that nothing is reset or written when no items are old enough.
+        if self.internal.floor_ceiling(min_generation_gap).is_none() {
+            return Ok(None);
+        }
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_next_with_floor(min_generation_gap));
+        }
+
+        let (gen, reset) = self.internal.next_generation();
+        if reset {
+            self.handle_reset()?;
+        }
+
+        let next = self.internal.inf_next_with_floor(min_generation_gap);
+        self.last_reset = reset;
         if let Some(next) = next {
             Self::put_batch(&self.db, &[next], gen.get())?;
         }
         Ok(next)
     }
 
+    fn weighted_next(&mut self) -> Result<Option<(&Self::Item, f64)>, Self::Error> {
+        self.check_writable()?;
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_weighted_next());
+        }
+
+        let (gen, reset) = self.internal.next_generation();
+        if reset {
+            self.handle_reset()?;
+        }
+
+        let next = self.internal.inf_weighted_next();
+        self.last_reset = reset;
+        if let Some((next, _)) = next {
+            Self::put_batch(&self.db, &[next], gen.get())?;
+        }
+        Ok(next)
+    }
+
     fn next_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        self.check_writable()?;
+        if n == 0 {
+            return Ok(self.internal.inf_next_n(0));
+        }
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_next_n(n));
+        }
+
         let (gen, reset) = self.internal.next_generation();
         if reset {
             self.handle_reset()?;
         }
 
         let next = self.internal.inf_next_n(n);
+        self.last_reset = reset;
         if let Some(next) = &next {
             Self::put_batch(&self.db, next, gen.get())?;
         }
         Ok(next)
     }
 
+    fn next_n_spread(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        self.check_writable()?;
+        let Some((selected, reset)) = self.internal.next_n_spread_nodes(n) else {
+            return Ok(None);
+        };
+        self.last_reset = reset;
+
+        if reset {
+            Self::put_generations(&self.db, &self.internal.tree.dump())?;
+        } else {
+            // Items selected more than once are written with their final generation.
+            let generations: Vec<_> = selected
+                .iter()
+                .map(|n| unsafe { (n.as_ref().get(), n.as_ref().generation()) })
+                .collect();
+            Self::put_generations(&self.db, &generations)?;
+        }
+
+        let output = selected.into_iter().map(|n| unsafe { n.as_ref().get() }).collect();
+
+        Ok(Some(output))
+    }
+
     fn unique_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        self.check_writable()?;
+        if n == 0 {
+            return Ok(self.internal.inf_unique_n(0));
+        }
+        if self.internal.reselects_only_item() {
+            self.last_reset = false;
+            return Ok(self.internal.inf_unique_n(n));
+        }
+
         let (gen, reset) = self.internal.next_generation();
         if reset {
             self.handle_reset()?;
         }
 
         let next = self.internal.inf_unique_n(n);
+        self.last_reset = reset;
         if let Some(next) = &next {
             Self::put_batch(&self.db, next, gen.get())?;
         }
         Ok(next)
     }
 
+    fn preview_n(&mut self, n: usize) -> Result<Option<Vec<&Self::Item>>, Self::Error> {
+        Ok(self.internal.inf_preview_n(n))
+    }
+
+    fn sample(&mut self) -> Option<&Self::Item> {
+        self.internal.sample()
+    }
+
+    fn sample_n(&mut self, n: usize) -> Vec<&Self::Item> {
+        self.internal.sample_n(n)
+    }
+
+    fn sample_from<'a, I>(&mut self, candidates: I, k: usize) -> Vec<&'a Self::Item>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        self.internal.sample_from(candidates, k)
+    }
+
+    fn touch_all<'a, I>(&mut self, items: I) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        self.check_writable()?;
+        let Some((touched, reset)) = self.internal.touch_nodes(items) else {
+            return Ok(0);
+        };
+        self.last_reset = reset;
+
+        if reset {
+            Self::put_generations(&self.db, &self.internal.tree.dump())?;
+        } else {
+            let generations: Vec<_> = touched
+                .iter()
+                .map(|n| unsafe { (n.as_ref().get(), n.as_ref().generation()) })
+                .collect();
+            Self::put_generations(&self.db, &generations)?;
+        }
+        Ok(touched.len())
+    }
+
+    fn rebase_generations(&mut self) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        self.internal.inf_rebase_generations();
+        Self::put_generations(&self.db, &self.internal.tree.dump())
+    }
+
+    fn prune_to_size(&mut self, max: usize) -> Result<usize, Self::Error> {
+        self.check_writable()?;
+        let removed = self.internal.prune_items(max);
+        if removed.is_empty() {
+            return Ok(0);
+        }
+
+        let mut batch = WriteBatch::default();
+        for item in &removed {
+            batch.delete_cf(self.db.cf(), encode::to_vec(item)?);
+        }
+        self.db.write(batch)?;
+        Ok(removed.len())
+    }
+
+    fn on_select<F>(&mut self, f: F)
+    where
+        F: FnMut(&Self::Item, Generation) + Send + 'static,
+    {
+        self.internal.on_select(f);
+    }
+
     fn size(&self) -> usize {
         self.internal.size()
     }
@@ -212,15 +817,72 @@ where
         internal.into_values()
     }
 
-    fn dump(&self) -> Vec<(&Self::Item, u64)> {
+    fn dump(&self) -> Vec<(&Self::Item, Generation)> {
         self.internal.dump()
     }
+
+    fn entries(&self) -> impl Iterator<Item = (&Self::Item, Generation)> + '_ {
+        self.internal.entries()
+    }
+
+    fn generations_of<'a, I>(&self, items: I) -> Vec<Option<Generation>>
+    where
+        I: IntoIterator<Item = &'a Self::Item>,
+        Self::Item: 'a,
+    {
+        self.internal.generations_of(items)
+    }
+
+    fn weights(&self) -> Vec<(&Self::Item, f64)> {
+        self.internal.weights()
+    }
+
+    #[cfg(feature = "selection-counts")]
+    fn selection_counts(&self) -> Vec<(&Self::Item, u64)> {
+        self.internal.selection_counts()
+    }
+
+    fn reset_selection_counts(&mut self) {
+        self.internal.reset_selection_counts();
+    }
+
+    #[cfg(feature = "rotation-count")]
+    fn rotation_count(&self) -> u64 {
+        self.internal.rotation_count()
+    }
+
+    #[cfg(feature = "rotation-count")]
+    fn reset_rotation_count(&mut self) {
+        self.internal.reset_rotation_count();
+    }
+
+    #[cfg(feature = "debug-nodes")]
+    fn debug_nodes(&self) -> Vec<crate::DebugNode<'_, Self::Item>> {
+        self.internal.debug_nodes()
+    }
+
+    fn last_reset_occurred(&self) -> bool {
+        self.last_reset
+    }
+
+    fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        self.internal.verify_integrity()
+    }
 }
 
 impl<T, H, R> Drop for ShufflerGeneric<T, H, R> {
     fn drop(&mut self) {
         if !self.closed {
-            drop(self.db.flush());
+            #[cfg(all(feature = "log", debug_assertions))]
+            if self.db.pending.get() {
+                log::warn!(
+                    "Shuffler for {} dropped with pending writes without being closed",
+                    self.db.path().display()
+                );
+            }
+            if !self.read_only {
+                drop(self.db.flush());
+            }
             self.db.cancel_all_background_work(false);
         }
         if !self.leak {
@@ -240,97 +902,281 @@ where
     H: Hasher + Clone,
     R: Rng,
 {
+    /// See [`crate::ShufflerGeneric::set_cooldown`]. The cooldown is not persisted to the
+    /// database.
+    pub fn set_cooldown(&mut self, k: usize) {
+        self.internal.set_cooldown(k);
+    }
+
+    /// Returns the seed from [`Options::seed`], if one was set. See
+    /// [`crate::ShufflerGeneric::seed`].
+    pub fn seed(&self) -> Option<u64> {
+        self.internal.seed()
+    }
+
+    /// Returns an immutable copy of the items currently loaded in memory, which can be shared
+    /// between threads for read-only queries. See [`crate::ShufflerGeneric::snapshot_reader`].
+    pub fn snapshot_reader(&self) -> SnapshotReader<T>
+    where
+        T: Clone,
+    {
+        self.internal.snapshot_reader()
+    }
+
+    /// Catches up with any changes made by the primary instance of the database, replacing the
+    /// contents of the shuffler with the items and generations currently in the database.
+    ///
+    /// Only valid for shufflers created with [`Shuffler::open_secondary`], otherwise the error from
+    /// RocksDB is returned.
+    pub fn catch_up_with_primary(&mut self) -> Result<(), Error> {
+        self.db.try_catch_up_with_primary()?;
+
+        self.internal.tree.clear();
+        Self::load_all(&self.db, &mut self.internal, false, true, None, false, true, None)
+            .map(|_| ())
+    }
+
+    /// Flushes any pending writes and returns the underlying RocksDB handle, consuming the
+    /// shuffler.
+    ///
+    /// The handle is detached from the shuffler's state. Items are stored as MessagePack keys
+    /// with MessagePack generations as values, in the column family set by
+    /// [`Options::column_family`]. Changes made through the handle are only seen by a shuffler
+    /// opened after the handle is dropped.
+    ///
+    /// If the shuffler was created with [`Shuffler::new_shared`] and the handle is still used
+    /// elsewhere, the shuffler is dropped after flushing and [`Error::SharedDatabase`] is
+    /// returned. If flushing fails the shuffler is dropped and the error is returned.
+    pub fn into_db(self) -> Result<DB, Error> {
+        if !self.read_only {
+            self.db.flush()?;
+        }
+
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: this is never dropped, so internal is only dropped here and db is only moved
+        // out once.
+        let db = unsafe {
+            ManuallyDrop::drop(&mut this.internal);
+            ptr::read(&this.db).db
+        };
+        Arc::try_unwrap(db).map_err(|_| Error::SharedDatabase)
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only { Err(Error::ReadOnly) } else { Ok(()) }
+    }
+
     fn get(&self, item: &T) -> Result<Option<u64>, Error> {
         let key = encode::to_vec(item)?;
 
-        match self.db.get_pinned(key)? {
-            Some(value) => Ok(Some(u64::deserialize(&mut Deserializer::new(&*value))?)),
+        match self.db.get_pinned_cf(self.db.cf(), &key)? {
+            Some(value) => Self::decode_generation(key, &value).map(Some),
             None => Ok(None),
         }
     }
 
+    fn decode_generation(key: Vec<u8>, value: &[u8]) -> Result<u64, Error> {
+        u64::deserialize(&mut Deserializer::new(value))
+            .map_err(|source| Error::DeserializationAt { bytes: key, value: true, source })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn load_all(
-        db: &DB,
+        db: &Database,
         internal: &mut BaseShuffler<T, H, R>,
         remove_error: bool,
         keep_unrecognized: bool,
         items: Option<Vec<T>>,
-    ) -> Result<(), Error> {
+        duplicate_error: bool,
+        read_only: bool,
+        migrate: Option<&Migration>,
+    ) -> Result<OpenReport, Error> {
         let mut batch = WriteBatch::default();
+        // Kept apart from batch so that migrations are still written with keep_unrecognized.
+        let mut migrations = WriteBatch::default();
+        // Migrated items are loaded after the rest so that conflicts don't depend on key order.
+        let mut migrated = Vec::new();
+        let mut report = OpenReport::default();
+
+        let mut valid: Option<AHashSet<_>> = match items {
+            Some(items) if duplicate_error => {
+                let mut set = AHashSet::default();
+                set.reserve(items.len());
+                for item in items {
+                    if let Some(item) = set.replace(item) {
+                        return Err(Error::DuplicateItem { bytes: encode::to_vec(&item)? });
+                    }
+                }
+                Some(set)
+            }
+            items => items.map(|v| v.into_iter().collect()),
+        };
+        let cf = db.cf();
 
-        let mut valid: Option<AHashSet<_>> = items.map(|v| v.into_iter().collect());
-
-        for r in db.iterator(Start) {
+        for r in db.iterator_cf(cf, Start) {
             let (key, value) = match r {
                 Ok((k, v)) => (k, v),
                 Err(e) => return Err(e.into()),
             };
 
             // Fallibly deserialize every key and value pair
-            let item = match T::deserialize(&mut Deserializer::new(&*key)) {
-                Ok(k) => k,
-                Err(e) => {
-                    if remove_error {
-                        batch.delete(key);
-                        continue;
+            let (item, is_migrated) = match T::deserialize(&mut Deserializer::new(&*key)) {
+                Ok(k) => (k, false),
+                Err(source) => {
+                    let item = migrate
+                        .and_then(|m| m(&key))
+                        .and_then(|bytes| T::deserialize(&mut Deserializer::new(&*bytes)).ok());
+                    match item {
+                        Some(item) => (item, true),
+                        None if remove_error => {
+                            batch.delete_cf(cf, key);
+                            report.dropped_bad_keys += 1;
+                            continue;
+                        }
+                        None => {
+                            return Err(Error::DeserializationAt {
+                                bytes: key.into(),
+                                value: false,
+                                source,
+                            });
+                        }
                     }
-                    return Err(e.into());
                 }
             };
 
             let gen = match u64::deserialize(&mut Deserializer::new(&*value)) {
                 Ok(g) => g,
-                Err(e) => {
+                Err(source) => {
                     if remove_error {
-                        batch.delete(key);
+                        batch.delete_cf(cf, key);
+                        report.dropped_bad_keys += 1;
                         continue;
                     }
-                    return Err(e.into());
+                    return Err(Error::DeserializationAt {
+                        bytes: key.into(),
+                        value: true,
+                        source,
+                    });
                 }
             };
 
+            if is_migrated {
+                migrated.push((key, value, item, gen));
+                continue;
+            }
+
             // Add it to the tree if it's a valid item, otherwise plan to delete it.
-            if let Some(valid) = &mut valid {
-                if let Some(item) = valid.take(&item) {
-                    internal.tree.insert(item, gen);
-                } else {
-                    batch.delete(key);
+            let item = match &mut valid {
+                Some(valid) => valid.take(&item),
+                None => Some(item),
+            };
+            let Some(item) = item else {
+                batch.delete_cf(cf, key);
+                report.removed_unrecognized += 1;
+                continue;
+            };
+
+            internal.tree.insert(item, gen);
+            report.loaded += 1;
+        }
+
+        for (key, value, item, gen) in migrated {
+            // The item is already stored under its current key or was migrated from another old
+            // key. Only the newer generation is kept.
+            if let Some(node) = internal.tree.find_node(&item) {
+                migrations.delete_cf(cf, &key);
+                if gen > unsafe { node.as_ref() }.generation() {
+                    Node::set_generation(node, gen);
+                    migrations.put_cf(cf, encode::to_vec(&item)?, &value);
                 }
-            } else {
-                internal.tree.insert(item, gen);
+                report.migration_conflicts += 1;
+                continue;
             }
+
+            let item = match &mut valid {
+                Some(valid) => valid.take(&item),
+                None => Some(item),
+            };
+            let Some(item) = item else {
+                batch.delete_cf(cf, key);
+                report.removed_unrecognized += 1;
+                continue;
+            };
+
+            // Migrated items are moved to their current key. Items already in the database are
+            // loaded regardless of their size, so the size limit isn't applied here.
+            migrations.delete_cf(cf, &key);
+            migrations.put_cf(cf, encode::to_vec(&item)?, &value);
+            report.migrated += 1;
+            internal.tree.insert(item, gen);
+            report.loaded += 1;
         }
 
-        if keep_unrecognized {
+        if keep_unrecognized || read_only {
             batch.clear();
+            report.removed_unrecognized = 0;
+            report.dropped_bad_keys = 0;
+        }
+        if read_only {
+            migrations.clear();
+            report.migrated = 0;
+            report.migration_conflicts = 0;
         }
 
         // Add all of the new items to the tree
         for item in valid.into_iter().flatten() {
             let gen = internal.add_generation();
 
-            let key = encode::to_vec(&item)?;
+            let key = db.encode_key(&item)?;
             let value = encode::to_vec(&gen)?;
-            batch.put(key, value);
+            batch.put_cf(cf, key, value);
 
             internal.tree.insert(item, gen);
         }
 
-        if !batch.is_empty() {
+        // New items are still added in memory, they just can't be saved.
+        if !batch.is_empty() && !read_only {
             db.write(batch)?;
         }
-        Ok(())
+        if !migrations.is_empty() {
+            db.write(migrations)?;
+        }
+        Ok(report)
     }
 
-    fn put_batch(db: &DB, items: &[&T], gen: u64) -> Result<(), Error> {
-        let gen = encode::to_vec(&gen)?;
+    // Writes an item that isn't in the database yet, enforcing the size limit.
+    fn put_new(db: &Database, item: &T, gen: u64) -> Result<(), Error> {
+        let key = db.encode_key(item)?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(db.cf(), key, encode::to_vec(&gen)?);
+        db.write(batch).map_err(Into::into)
+    }
+
+    // Rewrites the generations of items that are already stored. Items over the size limit may
+    // have been loaded from the database, so it isn't applied here.
+    fn put_batch(db: &Database, items: &[&T], gen: u64) -> Result<(), Error> {
+        let gen = encode::to_vec(&gen)?;
 
         let mut batch = WriteBatch::default();
 
         for item in items {
             let key = encode::to_vec(*item)?;
 
-            batch.put(key, &gen);
+            batch.put_cf(db.cf(), key, &gen);
+        }
+
+        db.write(batch).map_err(Into::into)
+    }
+
+    fn put_generations(db: &Database, items: &[(&T, u64)]) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+
+        for (item, gen) in items {
+            let key = encode::to_vec(*item)?;
+            let value = encode::to_vec(gen)?;
+
+            batch.put_cf(db.cf(), key, value);
         }
 
         db.write(batch).map_err(Into::into)
@@ -343,12 +1189,14 @@ where
     fn delete(&self, item: &T) -> Result<(), Error> {
         let key = encode::to_vec(item)?;
 
-        self.db.delete(key).map_err(Into::into)
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(self.db.cf(), key);
+        self.db.write(batch).map_err(Into::into)
     }
 }
 
 
-impl<T: Item> Shuffler<T> {
+impl<T: Item + Ord> Shuffler<T> {
     /// Creates a new [`Shuffler`] pointing to the given RocksDB database with default behaviour.
     ///
     /// The database will be created if it does not exist, but any missing parent directories will
@@ -382,25 +1230,61 @@ impl<T: Item> Shuffler<T> {
         options: Options,
         items: Option<Vec<T>>,
     ) -> Result<Self, Error> {
-        let mut db_options = rocksdb::Options::default();
-        db_options.set_max_open_files(100);
-        db_options.set_compression_type(rocksdb::DBCompressionType::Lz4);
-        db_options.create_if_missing(true);
-        db_options.create_missing_column_families(true);
-        // Much more efficient on slower storage, probably minimal impact on fast storage.
-        db_options.set_compaction_readahead_size(2 * 1024 * 1024);
-        db_options.set_keep_log_file_num(10);
+        Self::new_with_report(path, options, items).map(|(shuffler, _)| shuffler)
+    }
+
+    /// Creates a new [`Shuffler`] pointing to the given RocksDB database, like
+    /// [`new`](Self::new), and also reports what was loaded and removed from the database.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN value in `options.bias`.
+    pub fn new_with_report<P: AsRef<Path>>(
+        path: P,
+        options: Options,
+        items: Option<Vec<T>>,
+    ) -> Result<(Self, OpenReport), Error> {
+        let cf = column_family(&options);
+        let db = open_shared(path, &options, [cf.clone()])?;
+        Self::open_with(Database::new(db, cf, options.max_item_size), &options, items)
+    }
 
-        let db = DB::open(&db_options, path)?;
+    /// Creates a new [`Shuffler`] using a database handle opened with [`open_shared`], so that
+    /// several shufflers with different [`column families`](Options::column_family) can be open
+    /// at once.
+    ///
+    /// The column family must have been opened along with the database, otherwise
+    /// [`Error::MissingColumnFamily`] is returned. Options that only affect how the database is
+    /// opened, like [`Options::max_open_files`], are ignored.
+    ///
+    /// See [`new_default`](Self::new_default) for an explanation of `items`.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN value in `options.bias`.
+    pub fn new_shared(db: Arc<DB>, options: Options, items: Option<Vec<T>>) -> Result<Self, Error> {
+        let cf = column_family(&options);
+        if db.cf_handle(&cf).is_none() {
+            return Err(Error::MissingColumnFamily(cf));
+        }
 
-        let mut internal = crate::Shuffler::new(options.bias, options.new_item_handling);
+        let db = Database::new(db, cf, options.max_item_size);
+        Self::open_with(db, &options, items).map(|(shuffler, _)| shuffler)
+    }
 
-        Self::load_all(
+    fn open_with(
+        db: Database,
+        options: &Options,
+        items: Option<Vec<T>>,
+    ) -> Result<(Self, OpenReport), Error> {
+        let mut internal = Self::internal(options);
+        let report = Self::load_all(
             &db,
             &mut internal,
             options.remove_on_deserialization_error,
             options.keep_unrecognized,
             items,
+            options.error_on_duplicate_items,
+            false,
+            options.migrate.as_ref(),
         )?;
 
         let shuffler = Self {
@@ -408,12 +1292,177 @@ impl<T: Item> Shuffler<T> {
             db,
             closed: false,
             leak: false,
+            read_only: false,
+            last_reset: false,
+        };
+
+        Ok((shuffler, report))
+    }
+
+    fn open_read_only_with(
+        db: Database,
+        options: &Options,
+        items: Option<Vec<T>>,
+    ) -> Result<Self, Error> {
+        let mut internal = Self::internal(options);
+        Self::load_all(&db, &mut internal, false, true, items, false, true, None)?;
+
+        Ok(Self {
+            internal: ManuallyDrop::new(internal),
+            db,
+            closed: false,
+            leak: false,
+            read_only: true,
+            last_reset: false,
+        })
+    }
+
+    fn internal(options: &Options) -> crate::Shuffler<T> {
+        let mut internal = match options.seed {
+            Some(seed) => crate::Shuffler::from_seed(options.bias, options.new_item_handling, seed),
+            None => crate::Shuffler::new(options.bias, options.new_item_handling),
         };
+        if let Some(seed) = options.hash_seed {
+            // Derived the same way as in from_seed. The tree is still empty so it can be replaced.
+            let mut rng = StdRng::seed_from_u64(seed);
+            let hasher = RandomState::with_seeds(rng.gen(), rng.gen(), rng.gen(), rng.gen());
+            internal.tree = Rbtree::new(hasher.build_hasher());
+        }
+        internal
+    }
+
+    /// Opens the RocksDB database at `path` as a secondary instance with default [`Options`],
+    /// using `secondary` to store its own logs.
+    ///
+    /// This does not take the database's lock, so it can be used to read a database that is
+    /// currently open in another process. All items are loaded from the database but any
+    /// operation that would modify the shuffler returns [`Error::ReadOnly`]. Use
+    /// [`catch_up_with_primary`](ShufflerGeneric::catch_up_with_primary) to pick up changes made
+    /// since it was opened.
+    pub fn open_secondary<P: AsRef<Path>>(path: P, secondary: P) -> Result<Self, Error> {
+        Self::open_secondary_with_options(path, secondary, Options::default())
+    }
+
+    /// Opens the RocksDB database at `path` as a secondary instance like
+    /// [`open_secondary`](Self::open_secondary), reading the column family set by
+    /// [`Options::column_family`]. Options that only affect writes are ignored.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN value in `options.bias`.
+    pub fn open_secondary_with_options<P: AsRef<Path>>(
+        path: P,
+        secondary: P,
+        options: Options,
+    ) -> Result<Self, Error> {
+        let mut db_options = rocksdb::Options::default();
+        db_options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+
+        let cf = column_family(&options);
+        let db = DB::open_cf_as_secondary(&db_options, path, secondary, [&cf])?;
+        let db = Database::new(Arc::new(db), cf, options.max_item_size);
+        Self::open_read_only_with(db, &options, None)
+    }
+
+    /// Opens the RocksDB database at `path` in read-only mode with default [`Options`].
+    ///
+    /// Unlike [`open_secondary`](Self::open_secondary) this sees a fixed snapshot of the database
+    /// and can't catch up with later changes, but it doesn't need a directory of its own. Any
+    /// operation that would modify the shuffler returns [`Error::ReadOnly`].
+    ///
+    /// When `items` is provided, only those items are loaded and any that are not in the database
+    /// are added in memory without being saved. Unrecognized items are left in the database.
+    pub fn open_read_only<P: AsRef<Path>>(path: P, items: Option<Vec<T>>) -> Result<Self, Error> {
+        Self::open_read_only_with_options(path, Options::default(), items)
+    }
+
+    /// Opens the RocksDB database at `path` in read-only mode like
+    /// [`open_read_only`](Self::open_read_only), reading the column family set by
+    /// [`Options::column_family`]. Options that only affect writes are ignored.
+    ///
+    /// # Panics
+    /// Panics if given a negative or NaN value in `options.bias`.
+    pub fn open_read_only_with_options<P: AsRef<Path>>(
+        path: P,
+        options: Options,
+        items: Option<Vec<T>>,
+    ) -> Result<Self, Error> {
+        let mut db_options = rocksdb::Options::default();
+        db_options.set_compression_type(rocksdb::DBCompressionType::Lz4);
 
-        Ok(shuffler)
+        let cf = column_family(&options);
+        let db = DB::open_cf_for_read_only(&db_options, path, [&cf], false)?;
+        let db = Database::new(Arc::new(db), cf, options.max_item_size);
+        Self::open_read_only_with(db, &options, items)
     }
 }
 
+/// Opens the RocksDB database at `path` so that it can be shared between several shufflers
+/// created with [`Shuffler::new_shared`], creating the database and any missing column families.
+///
+/// Every column family in `column_families` is opened along with any that already exist in the
+/// database. Only the options that affect how the database is opened, like
+/// [`Options::max_open_files`] and [`Options::expected_items`], are used.
+pub fn open_shared<P, I, N>(
+    path: P,
+    options: &Options,
+    column_families: I,
+) -> Result<Arc<DB>, Error>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = N>,
+    N: Into<String>,
+{
+    let mut db_options = rocksdb::Options::default();
+    db_options.set_max_open_files(options.max_open_files);
+    db_options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+    db_options.create_if_missing(true);
+    db_options.create_missing_column_families(true);
+    db_options.set_compaction_readahead_size(options.compaction_readahead_size);
+    db_options.set_keep_log_file_num(options.keep_log_file_num);
+    if let Some(n) = options.expected_items {
+        tune_for_items(&mut db_options, n);
+    }
+
+    // RocksDB requires every existing column family to be opened. If the database doesn't exist
+    // yet there are none.
+    let mut cfs = DB::list_cf(&db_options, path.as_ref()).unwrap_or_default();
+    for cf in column_families {
+        let cf = cf.into();
+        if !cfs.contains(&cf) {
+            cfs.push(cf);
+        }
+    }
+
+    DB::open_cf(&db_options, path, cfs).map(Arc::new).map_err(Error::from_open)
+}
+
+// Sizes the write buffer so the whole database fits in a few memtables and sizes the levels to
+// match, keeping RocksDB's default ratio between them.
+fn tune_for_items(db_options: &mut rocksdb::Options, n: usize) {
+    let write_buffer = (n.saturating_mul(ESTIMATED_ITEM_SIZE) / 4)
+        .clamp(MIN_WRITE_BUFFER_SIZE, MAX_WRITE_BUFFER_SIZE);
+
+    db_options.set_write_buffer_size(write_buffer);
+    db_options.set_target_file_size_base(write_buffer as u64);
+    db_options.set_max_bytes_for_level_base(write_buffer as u64 * 4);
+}
+
+fn column_family(options: &Options) -> String {
+    options.column_family.clone().unwrap_or_else(|| DEFAULT_COLUMN_FAMILY_NAME.to_string())
+}
+
+/// Attempts to repair a corrupted RocksDB database at `path`, recovering as much data as possible.
+///
+/// This should only be used when opening a [`Shuffler`] fails due to corruption, and the database
+/// must not be open at the time. Some data may be lost, so consider backing up the database
+/// first.
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let mut db_options = rocksdb::Options::default();
+    db_options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+
+    DB::repair(&db_options, path).map_err(Into::into)
+}
+
 
 impl<T, H, R> crate::private::Sealed for ShufflerGeneric<T, H, R>
 where
@@ -422,3 +1471,1135 @@ where
     R: Rng,
 {
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rmp_serde::{decode, encode};
+    use rocksdb::{DB, DEFAULT_COLUMN_FAMILY_NAME};
+    use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+    use tempfile::tempdir;
+
+    use super::{open_shared, Error, ErrorKind, OpenReport, Shuffler};
+    use crate::persistent::{Options, PersistentShuffler};
+    use crate::{AwShuffler, BiasError, Generation, NewItemHandling};
+
+    fn as_gens<T, const N: usize>(dump: [(T, u64); N]) -> [(T, Generation); N] {
+        dump.map(|(item, gen)| (item, Generation::new(gen)))
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Unserializable(u32);
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(ser::Error::custom("unserializable"))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Unserializable {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            u32::deserialize(d).map(Self)
+        }
+    }
+
+    #[test]
+    fn locked() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+
+        let err = Shuffler::<u32>::new_default(&path, None).unwrap_err();
+        assert!(matches!(err, Error::Locked(_)), "{err:?}");
+
+        shuffler.close().unwrap();
+        Shuffler::<u32>::new_default(&path, None).unwrap().close().unwrap();
+
+        // Other IO errors are not lock errors
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let err = Shuffler::<u32>::new_default(&file, None).unwrap_err();
+        assert!(matches!(err, Error::DB(_)), "{err:?}");
+    }
+
+    #[test]
+    fn error_kind() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        let err = shuffler.add(Unserializable(0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Serialization);
+
+        let err = Shuffler::<u32>::new_default(&path, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Locked);
+        shuffler.close().unwrap();
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        shuffler.add("string".to_owned()).unwrap();
+        shuffler.close().unwrap();
+        let err = Shuffler::<u32>::new_default(&path, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Deserialization);
+
+        let err: Error = decode::from_slice::<u32>(b"").unwrap_err().into();
+        assert!(matches!(err, Error::Deserialization(_)));
+        assert_eq!(err.kind(), ErrorKind::Deserialization);
+
+        let err = Shuffler::<u32>::new_default(dir.path().join("missing/db"), None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Db);
+    }
+
+    #[test]
+    fn deserialization_at() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        Shuffler::<u32>::new_default(&path, None).unwrap().close().unwrap();
+
+        let bad_key = encode::to_vec("garbage").unwrap();
+        let good_key = encode::to_vec(&1_u32).unwrap();
+        {
+            let db = DB::open_default(&path).unwrap();
+            db.put(&bad_key, encode::to_vec(&0_u64).unwrap()).unwrap();
+        }
+
+        match Shuffler::<u32>::new_default(&path, None).unwrap_err() {
+            Error::DeserializationAt { bytes, value, .. } => {
+                assert_eq!(bytes, bad_key);
+                assert!(!value);
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+
+        {
+            let db = DB::open_default(&path).unwrap();
+            db.delete(&bad_key).unwrap();
+            db.put(&good_key, encode::to_vec("garbage").unwrap()).unwrap();
+        }
+
+        match Shuffler::<u32>::new_default(&path, None).unwrap_err() {
+            Error::DeserializationAt { bytes, value, .. } => {
+                assert_eq!(bytes, good_key);
+                assert!(value);
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn rebase_generations() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..10_u32 {
+            shuffler.add(i).unwrap();
+            shuffler.next_n(5).unwrap();
+        }
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+
+        shuffler.rebase_generations().unwrap();
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        let reopened: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(after, reopened);
+
+        let mut distinct: Vec<_> = before.iter().map(|(_, g)| *g).collect();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let max = after.iter().map(|(_, g)| g.get()).max();
+        assert_eq!(max, Some(distinct.len() as u64 - 1));
+
+        for ((_, a1), (_, b1)) in before.iter().zip(&after) {
+            for ((_, a2), (_, b2)) in before.iter().zip(&after) {
+                assert_eq!(a1.cmp(a2), b1.cmp(b2));
+            }
+        }
+    }
+
+    #[test]
+    fn add_with_generation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        assert!(shuffler.add_with_generation(1_u32, Generation::new(7)).unwrap());
+        assert!(shuffler.add_with_generation(2, Generation::new(3)).unwrap());
+        assert!(!shuffler.add_with_generation(2, Generation::new(5)).unwrap());
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.dump_sorted(), [(&1, Generation::new(7)), (&2, Generation::new(3))]);
+    }
+
+    #[test]
+    fn add_or_get_generation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let options = Options::default().new_item_handling(NewItemHandling::RecentlySelected);
+        let mut shuffler = Shuffler::new(&path, options, None).unwrap();
+        shuffler.add_with_generation(1_u32, Generation::new(7)).unwrap();
+
+        assert_eq!(shuffler.add_or_get_generation(2).unwrap(), (true, Generation::new(7)));
+        assert_eq!(shuffler.add_or_get_generation(1).unwrap(), (false, Generation::new(7)));
+        shuffler.next().unwrap();
+        assert_eq!(shuffler.add_or_get_generation(3).unwrap(), (true, Generation::new(8)));
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        assert_eq!(shuffler.dump_sorted()[2], (&3, Generation::new(8)));
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn next_n_spread() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some((0..10_u32).collect())).unwrap();
+        for _ in 0..5 {
+            assert_eq!(shuffler.next_n_spread(4).unwrap().unwrap().len(), 4);
+        }
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn try_bias() {
+        assert_eq!(Options::default().try_bias(f64::NAN).err(), Some(BiasError::NaN));
+        assert_eq!(Options::default().try_bias(-1.0).err(), Some(BiasError::Negative(-1.0)));
+        assert_eq!(Options::default().try_bias(0.0).unwrap().bias, 0.0);
+        assert_eq!(Options::default().try_bias(f64::INFINITY).unwrap().bias, f64::INFINITY);
+    }
+
+    #[test]
+    fn next_with_bias() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..5_u32 {
+            assert!(shuffler.add_with_generation(i, Generation::new(u64::from(i))).unwrap());
+        }
+        assert_eq!(shuffler.next_with_bias(f64::INFINITY).unwrap(), Some(&0));
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 5), (&1, 1), (&2, 2), (&3, 3), (&4, 4)]));
+    }
+
+    #[test]
+    fn new_with_report() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let (shuffler, report) =
+            Shuffler::new_with_report(&path, Options::default(), Some(vec![1_u32, 2, 3])).unwrap();
+        assert_eq!(report, OpenReport::default());
+        shuffler.close().unwrap();
+
+        {
+            let db = DB::open_default(&path).unwrap();
+            db.put(encode::to_vec("garbage").unwrap(), encode::to_vec(&0_u64).unwrap()).unwrap();
+            db.put(encode::to_vec("more").unwrap(), encode::to_vec(&0_u64).unwrap()).unwrap();
+            db.put(encode::to_vec(&4_u32).unwrap(), encode::to_vec("garbage").unwrap()).unwrap();
+        }
+
+        // Bad keys are skipped but kept in the database along with unrecognized items.
+        let options =
+            Options::default().remove_on_deserialization_error(true).keep_unrecognized(true);
+        let (shuffler, report) =
+            Shuffler::new_with_report(&path, options, Some(vec![2_u32, 3])).unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.dropped_bad_keys, 0);
+        assert_eq!(report.removed_unrecognized, 0);
+        shuffler.close().unwrap();
+
+        let options = Options::default().remove_on_deserialization_error(true);
+        let (shuffler, report) =
+            Shuffler::new_with_report(&path, options, Some(vec![2_u32, 3, 5])).unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.dropped_bad_keys, 3);
+        assert_eq!(report.removed_unrecognized, 1);
+        assert_eq!(shuffler.values_sorted(), [&2, &3, &5]);
+        shuffler.close().unwrap();
+
+        let (shuffler, report) =
+            Shuffler::<u32>::new_with_report(&path, Options::default(), None).unwrap();
+        assert_eq!(report.loaded, 3);
+        assert_eq!(report.dropped_bad_keys, 0);
+        assert_eq!(report.removed_unrecognized, 0);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn remove_borrowed() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let items = ["a", "b", "c"].map(String::from).to_vec();
+        let mut shuffler = Shuffler::new_default(&path, Some(items)).unwrap();
+        assert_eq!(shuffler.remove("a").unwrap().as_deref(), Some("a"));
+        assert_eq!(shuffler.soft_remove("b").unwrap().as_deref(), Some("b"));
+        assert_eq!(shuffler.remove("d").unwrap(), None);
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<String>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&"b".to_string(), &"c".to_string()]);
+    }
+
+    #[test]
+    fn replace() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..3_u32 {
+            assert!(shuffler.add_with_generation(i, Generation::new(u64::from(i) + 5)).unwrap());
+        }
+        assert!(shuffler.replace(&0, 10).unwrap());
+        assert!(shuffler.replace(&1, 2).unwrap());
+        assert!(!shuffler.replace(&1, 11).unwrap());
+        assert!(shuffler.replace(&2, 2).unwrap());
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.dump_sorted(), [(&2, Generation::new(7)), (&10, Generation::new(5))]);
+    }
+
+    #[test]
+    fn load_all_items() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..5_u32 {
+            assert!(shuffler.add_with_generation(i, Generation::new(u64::from(i) + 10)).unwrap());
+        }
+        for i in 1..4_u32 {
+            assert_eq!(shuffler.soft_remove(&i).unwrap(), Some(i));
+        }
+
+        assert_eq!(shuffler.load_all_items([0, 1, 2, 2, 5, 5]).unwrap(), 3);
+        assert_eq!(shuffler.load_all_items(Vec::new()).unwrap(), 0);
+        let gen = shuffler.dump_sorted()[4].1.get();
+        assert_eq!(
+            shuffler.dump_sorted(),
+            as_gens([(&0, 10), (&1, 11), (&2, 12), (&4, 14), (&5, gen)])
+        );
+        shuffler.close().unwrap();
+
+        let options = Options::default().keep_unrecognized(true);
+        let mut shuffler = Shuffler::new(&path, options, Some(vec![0_u32])).unwrap();
+        assert_eq!(shuffler.load_all_items([3, 4]).unwrap(), 2);
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 10), (&3, 13), (&4, 14)]));
+    }
+
+    #[test]
+    fn open_secondary() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let secondary = dir.path().join("secondary");
+
+        let mut primary = Shuffler::new_default(&path, Some(vec![1_u32, 2])).unwrap();
+        primary.add_with_generation(3, Generation::new(10)).unwrap();
+
+        let mut reader = Shuffler::<u32>::open_secondary(&path, &secondary).unwrap();
+        assert_eq!(reader.values_sorted(), [&1, &2, &3]);
+
+        assert!(matches!(reader.add(4), Err(Error::ReadOnly)));
+        assert!(matches!(reader.next(), Err(Error::ReadOnly)));
+        assert!(matches!(reader.remove(&1), Err(Error::ReadOnly)));
+        assert!(matches!(reader.soft_remove(&1), Err(Error::ReadOnly)));
+        assert_eq!(reader.load(4).unwrap_err().kind(), ErrorKind::ReadOnly);
+        assert_eq!(reader.size(), 3);
+
+        primary.remove(&1).unwrap();
+        primary.add_with_generation(5, Generation::new(20)).unwrap();
+        primary.compact().unwrap();
+        assert_eq!(reader.values_sorted(), [&1, &2, &3]);
+
+        reader.catch_up_with_primary().unwrap();
+        assert_eq!(reader.values_sorted(), [&2, &3, &5]);
+        assert_eq!(reader.dump_sorted()[1..], as_gens([(&3, 10), (&5, 20)]));
+        reader.verify_integrity().unwrap();
+
+        assert!(primary.catch_up_with_primary().is_err());
+        reader.close().unwrap();
+        primary.close().unwrap();
+    }
+
+    #[test]
+    fn column_family() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let secondary = dir.path().join("secondary");
+
+        let options = |cf: &str| Options::default().column_family(cf.to_string());
+
+        let db = open_shared(&path, &Options::default(), ["a", "b"]).unwrap();
+        let mut a = Shuffler::new_shared(db.clone(), options("a"), Some(vec![1_u32, 2])).unwrap();
+        let mut b = Shuffler::new_shared(db.clone(), options("b"), Some(vec![3_u32])).unwrap();
+        let default = Shuffler::new_shared(db.clone(), Options::default(), Some(vec![4_u32]));
+        let default = default.unwrap();
+
+        match Shuffler::<u32>::new_shared(db.clone(), options("c"), None) {
+            Err(Error::MissingColumnFamily(cf)) => assert_eq!(cf, "c"),
+            r => panic!("unexpected result {r:?}"),
+        }
+        assert_eq!(
+            Shuffler::<u32>::new(&path, options("c"), None).unwrap_err().kind(),
+            ErrorKind::Locked
+        );
+
+        a.remove(&1).unwrap();
+        a.add_with_generation(5, Generation::new(10)).unwrap();
+        b.add(6).unwrap();
+        assert_eq!(a.values_sorted(), [&2, &5]);
+        assert_eq!(b.values_sorted(), [&3, &6]);
+        assert_eq!(default.values_sorted(), [&4]);
+
+        a.close().unwrap();
+        b.next().unwrap();
+        b.close().unwrap();
+        default.close().unwrap();
+        drop(db);
+
+        let reader = Shuffler::<u32>::open_read_only_with_options(&path, options("a"), None);
+        let reader = reader.unwrap();
+        assert_eq!(reader.values_sorted(), [&2, &5]);
+        reader.close().unwrap();
+
+        let b = Shuffler::<u32>::new(&path, options("b"), None).unwrap();
+        let reader =
+            Shuffler::<u32>::open_secondary_with_options(&path, &secondary, options("b")).unwrap();
+        assert_eq!(reader.values_sorted(), [&3, &6]);
+        reader.close().unwrap();
+        b.close().unwrap();
+
+        let default = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(default.values_sorted(), [&4]);
+    }
+
+    #[test]
+    fn try_close() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2])).unwrap();
+        shuffler.add_with_generation(3, Generation::new(10)).unwrap();
+        assert!(shuffler.try_close().is_ok());
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        assert!(shuffler.try_close().is_ok());
+
+        let secondary = dir.path().join("secondary");
+        let reader = Shuffler::<u32>::open_secondary(&path, &secondary).unwrap();
+        assert!(reader.try_close().is_ok());
+
+        // A read-only database can't be flushed, so the shuffler is handed back with the error.
+        let db = DB::open_cf_for_read_only(
+            &rocksdb::Options::default(),
+            &path,
+            [DEFAULT_COLUMN_FAMILY_NAME],
+            false,
+        )
+        .unwrap();
+        let shuffler = Shuffler::<u32>::new_shared(Arc::new(db), Options::default(), None).unwrap();
+        let (mut shuffler, e) = shuffler.try_close().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Db);
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        assert_eq!(shuffler.generations_of(&[3]), [Some(Generation::new(10))]);
+        assert!(shuffler.sample().is_some());
+        assert_eq!(shuffler.try_close().unwrap_err().1.kind(), ErrorKind::Db);
+    }
+
+    #[cfg(all(feature = "log", debug_assertions))]
+    #[test]
+    fn drop_without_close() {
+        use std::sync::Mutex;
+
+        struct Capture(Mutex<Vec<String>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+        // Other tests may have already installed it.
+        drop(log::set_logger(&CAPTURE));
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let dir = tempdir().unwrap();
+        let closed = dir.path().join("closed");
+        let dropped = dir.path().join("dropped");
+
+        Shuffler::new_default(&closed, Some(vec![1_u32])).unwrap().close().unwrap();
+        drop(Shuffler::new_default(&dropped, Some(vec![1_u32])).unwrap());
+
+        // Nothing is written when opening an unchanged database.
+        drop(Shuffler::<u32>::new_default(&closed, None).unwrap());
+
+        let mut shuffler = Shuffler::<u32>::new_default(&closed, None).unwrap();
+        shuffler.next().unwrap();
+        shuffler.compact().unwrap();
+        drop(shuffler);
+
+        let tmp = dir.path().file_name().unwrap().to_string_lossy();
+        let logs = CAPTURE.0.lock().unwrap();
+        let warnings = |name: &str| {
+            let path = format!("{tmp}/{name} ");
+            logs.iter().filter(|l| l.contains(&path)).count()
+        };
+        assert_eq!(warnings("dropped"), 1);
+        assert_eq!(warnings("closed"), 0);
+    }
+
+    #[test]
+    fn debug() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let items = (0..100).map(|i| format!("item-{i}")).collect();
+        let shuffler = Shuffler::new_default(&path, Some(items)).unwrap();
+
+        let debug = format!("{shuffler:?}");
+        assert!(debug.starts_with("ShufflerGeneric { size: 100, generations: "), "{debug}");
+        assert!(debug.ends_with("new_items: NeverSelected, closed: false, .. }"), "{debug}");
+        assert!(!debug.contains("item-"));
+    }
+
+    #[test]
+    fn last_reset_occurred() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 3, 4])).unwrap();
+        shuffler.internal.max_generation = 3;
+
+        for _ in 0..3 {
+            shuffler.next().unwrap();
+            assert!(!shuffler.last_reset_occurred());
+        }
+
+        shuffler.next().unwrap();
+        assert!(shuffler.last_reset_occurred());
+        assert_eq!(shuffler.internal.tree.generations(), (0, 1));
+
+        shuffler.next_n(2).unwrap();
+        shuffler.unique_n(2).unwrap();
+        assert!(!shuffler.last_reset_occurred());
+        shuffler.unique_n(2).unwrap();
+        assert!(shuffler.last_reset_occurred());
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.internal.tree.generations(), (0, 1));
+        assert!(!shuffler.last_reset_occurred());
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn tuning() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let options = || {
+            Options::default()
+                .max_open_files(10)
+                .keep_log_file_num(1)
+                .compaction_readahead_size(0)
+        };
+
+        let mut shuffler = Shuffler::new(&path, options(), Some(vec![1_u32, 2, 3])).unwrap();
+        shuffler.add_with_generation(4, Generation::new(7)).unwrap();
+        assert!(shuffler.next().unwrap().is_some());
+        shuffler.compact().unwrap();
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new(&path, options(), None).unwrap();
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(after.len(), 4);
+        assert_eq!(after, before);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn open_read_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2])).unwrap();
+        shuffler.add_with_generation(3, Generation::new(10)).unwrap();
+        shuffler.close().unwrap();
+
+        let mut reader = Shuffler::<u32>::open_read_only(&path, None).unwrap();
+        assert_eq!(reader.values_sorted(), [&1, &2, &3]);
+        assert_eq!(reader.dump_sorted()[2], (&3, Generation::new(10)));
+        assert!(reader.preview_n(2).unwrap().is_some());
+
+        assert!(matches!(reader.add(4), Err(Error::ReadOnly)));
+        assert!(matches!(reader.next(), Err(Error::ReadOnly)));
+        assert!(matches!(reader.remove(&1), Err(Error::ReadOnly)));
+        assert_eq!(reader.compact().unwrap_err().kind(), ErrorKind::ReadOnly);
+        reader.close().unwrap();
+
+        // Neither the unrecognized item nor the new one is written.
+        let items = Some(vec![2_u32, 3, 4]);
+        let reader = Shuffler::open_read_only(&path, items).unwrap();
+        assert_eq!(reader.values_sorted(), [&2, &3, &4]);
+        reader.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn dump_to() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 3])).unwrap();
+        shuffler.add_with_generation(4, Generation::new(10)).unwrap();
+        shuffler.soft_remove(&1).unwrap();
+        let mut expected: Vec<_> = shuffler.dump().into_iter().map(|(i, g)| (*i, g)).collect();
+        expected.push((1, Generation::new(0)));
+        expected.sort_unstable();
+
+        let mut out = Vec::new();
+        shuffler.dump_to(&mut out).unwrap();
+        shuffler.close().unwrap();
+
+        let mut dumped = Vec::new();
+        let mut rest = &out[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let item: u32 = rmp_serde::from_slice(&rest[4..4 + len]).unwrap();
+            let gen = u64::from_be_bytes(rest[4 + len..12 + len].try_into().unwrap());
+            dumped.push((item, Generation::new(gen)));
+            rest = &rest[12 + len..];
+        }
+        dumped.sort_unstable();
+
+        assert_eq!(dumped, expected);
+    }
+
+    #[test]
+    fn hash_seed() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let items: Vec<_> = (0..100).map(|i| format!("item-{i}")).collect();
+        let open = |seed| {
+            Shuffler::<String>::new(&path, Options::default().hash_seed(seed), None).unwrap()
+        };
+
+        Shuffler::new_default(&path, Some(items)).unwrap().close().unwrap();
+
+        let shuffler = open(5);
+        let order: Vec<_> = shuffler.values().into_iter().cloned().collect();
+        shuffler.close().unwrap();
+
+        let shuffler = open(5);
+        assert!(shuffler.values().into_iter().eq(&order));
+        shuffler.close().unwrap();
+
+        let seeded = Shuffler::<String>::new(&path, Options::default().seed(5), None).unwrap();
+        assert_eq!(seeded.seed(), Some(5));
+        assert!(seeded.values().into_iter().eq(&order));
+        seeded.close().unwrap();
+    }
+
+    #[test]
+    fn repair() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        Shuffler::new_default(&path, Some(vec![1_u32, 2, 3])).unwrap().close().unwrap();
+
+        // Point CURRENT at a manifest that doesn't exist.
+        std::fs::write(path.join("CURRENT"), "MANIFEST-999999\n").unwrap();
+        let err = Shuffler::<u32>::new_default(&path, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Db);
+
+        super::repair(&path).unwrap();
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn empty_item() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![String::new()])).unwrap();
+        assert!(!shuffler.add(String::new()).unwrap());
+        assert!(shuffler.add("a".to_string()).unwrap());
+        let gens = shuffler.generations_of(&[String::new(), "b".to_string()]);
+        assert_eq!(gens, [Some(Generation::new(0)), None]);
+
+        let mut out = Vec::new();
+        shuffler.dump_to(&mut out).unwrap();
+        shuffler.close().unwrap();
+
+        let mut dumped = Vec::new();
+        let mut rest = &out[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            assert_ne!(len, 0);
+            let item: String = rmp_serde::from_slice(&rest[4..4 + len]).unwrap();
+            dumped.push(item);
+            rest = &rest[12 + len..];
+        }
+        dumped.sort_unstable();
+        assert_eq!(dumped, ["", "a"]);
+
+        let mut shuffler = Shuffler::<String>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&String::new(), &"a".to_string()]);
+        assert_eq!(shuffler.remove("").unwrap(), Some(String::new()));
+        assert_eq!(shuffler.remove("").unwrap(), None);
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<String>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&"a".to_string()]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn expected_items() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let items: Vec<_> = (0..1000_u32).collect();
+        Shuffler::new_default(&path, Some(items)).unwrap().close().unwrap();
+
+        for n in [0, 10, 1_000_000, usize::MAX] {
+            let options = Options::default().expected_items(n);
+            let (shuffler, report) =
+                Shuffler::<u32>::new_with_report(&path, options, None).unwrap();
+            assert_eq!(report.loaded, 1000);
+            shuffler.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn duplicate_items() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 2, 3, 1])).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        shuffler.close().unwrap();
+
+        let options = || Options::default().error_on_duplicate_items(true);
+        match Shuffler::new(&path, options(), Some(vec![1_u32, 2, 3, 2])) {
+            Err(e @ Error::DuplicateItem { .. }) => {
+                assert_eq!(e.kind(), ErrorKind::DuplicateItem);
+                let Error::DuplicateItem { bytes } = e else { unreachable!() };
+                assert_eq!(bytes, encode::to_vec(&2_u32).unwrap());
+            }
+            r => panic!("expected a duplicate item error, got {r:?}"),
+        }
+
+        let shuffler = Shuffler::new(&path, options(), Some(vec![1_u32, 2, 4])).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &4]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn into_db() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 3])).unwrap();
+        shuffler.add_with_generation(4, Generation::new(7)).unwrap();
+        let db = shuffler.into_db().unwrap();
+
+        let value = db.get(encode::to_vec(&4_u32).unwrap()).unwrap().unwrap();
+        assert_eq!(rmp_serde::from_slice::<u64>(&value).unwrap(), 7);
+        assert_eq!(db.iterator(rocksdb::IteratorMode::Start).count(), 4);
+        db.delete(encode::to_vec(&1_u32).unwrap()).unwrap();
+        drop(db);
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&2, &3, &4]);
+        shuffler.close().unwrap();
+
+        let shared = open_shared(&path, &Options::default(), ["a"]).unwrap();
+        let options = Options::default().column_family("a".to_string());
+        let mut a = Shuffler::new_shared(shared.clone(), options, None).unwrap();
+        a.add(5_u32).unwrap();
+        assert_eq!(a.into_db().unwrap_err().kind(), ErrorKind::SharedDatabase);
+
+        let db = Arc::try_unwrap(shared).unwrap();
+        let cf = db.cf_handle("a").unwrap();
+        assert!(db.get_pinned_cf(cf, encode::to_vec(&5_u32).unwrap()).unwrap().is_some());
+    }
+
+    #[test]
+    fn retain() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let shuffler = Shuffler::new_default(&path, Some((0..10_u32).collect())).unwrap();
+        shuffler.close().unwrap();
+
+        // 0 through 4 are left on disk but never loaded into memory.
+        let options = Options::default().keep_unrecognized(true);
+        let mut shuffler = Shuffler::new(&path, options, Some((5..10_u32).collect())).unwrap();
+        assert_eq!(shuffler.soft_remove(&9).unwrap(), Some(9));
+        shuffler.retain(|i| i % 2 == 1).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&5, &7]);
+        shuffler.retain(|_| true).unwrap();
+        assert_eq!(shuffler.verify_integrity(), Ok(()));
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&0, &1, &2, &3, &4, &5, &7, &9]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn next_single_item() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32])).unwrap();
+        assert_eq!(shuffler.next().unwrap(), Some(&1));
+        let seq = shuffler.db.latest_sequence_number();
+
+        for _ in 0..10 {
+            assert_eq!(shuffler.next().unwrap(), Some(&1));
+        }
+        assert_eq!(shuffler.next_with_bias(0.0).unwrap(), Some(&1));
+        assert_eq!(shuffler.next_n(3).unwrap(), Some(vec![&1, &1, &1]));
+        assert_eq!(shuffler.unique_n(1).unwrap(), Some(vec![&1]));
+        assert_eq!(shuffler.db.latest_sequence_number(), seq);
+        assert_eq!(shuffler.dump(), [(&1, Generation::new(1))]);
+
+        shuffler.add(2).unwrap();
+        shuffler.next().unwrap().unwrap();
+        assert!(shuffler.db.latest_sequence_number() > seq);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn touch_all() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..5_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i))).unwrap();
+        }
+
+        assert_eq!(shuffler.touch_all(&[0, 2, 9]).unwrap(), 2);
+        assert!(!shuffler.last_reset_occurred());
+        shuffler.internal.max_generation = 5;
+        assert_eq!(shuffler.touch_all(&[1]).unwrap(), 1);
+        assert!(shuffler.last_reset_occurred());
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 0), (&1, 1), (&2, 0), (&3, 0), (&4, 0)]));
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn next_with_floor() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..5_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i))).unwrap();
+        }
+
+        let seq = shuffler.db.latest_sequence_number();
+        assert_eq!(shuffler.next_with_floor(5).unwrap(), None);
+        assert_eq!(shuffler.db.latest_sequence_number(), seq);
+        assert_eq!(shuffler.next_with_floor(4).unwrap(), Some(&0));
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.dump_sorted(), as_gens([(&0, 5), (&1, 1), (&2, 2), (&3, 3), (&4, 4)]));
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn maybe_persistent() {
+        fn finish<S: crate::MaybePersistent<Item = u32>>(mut shuffler: S) -> Result<(), S::Error> {
+            shuffler.add(7)?;
+            shuffler.compact()?;
+            shuffler.close()
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        finish(crate::Shuffler::new(2.0, crate::NewItemHandling::NeverSelected)).unwrap();
+        finish(Shuffler::new_default(&path, Some(vec![1_u32])).unwrap()).unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &7]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn close_no_dealloc() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec![1_u32, 2, 3])).unwrap();
+        let next = *shuffler.next().unwrap().unwrap();
+        shuffler.close_no_dealloc().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&1, &2, &3]);
+        assert_eq!(shuffler.generations_of(&[next]), [Some(Generation::new(1))]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn max_item_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let large = "a".repeat(9 * 1024 * 1024);
+
+        let mut shuffler = Shuffler::new_default(&path, Some(vec!["small".to_string()])).unwrap();
+        match shuffler.add(large.clone()) {
+            Err(e @ Error::ItemTooLarge { .. }) => {
+                assert_eq!(e.kind(), ErrorKind::ItemTooLarge);
+                assert!(matches!(e, Error::ItemTooLarge { limit: 8388608, .. }));
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+        assert_eq!(shuffler.size(), 1);
+        shuffler.close().unwrap();
+
+        let options = Options::default().max_item_size(16 * 1024 * 1024);
+        let mut shuffler = Shuffler::<String>::new(&path, options, None).unwrap();
+        assert!(shuffler.add(large.clone()).unwrap());
+        shuffler.close().unwrap();
+
+        // Items already in the database are loaded even when over the limit.
+        let shuffler = Shuffler::<String>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&large, "small"]);
+        shuffler.close().unwrap();
+
+        let options = Options::default().max_item_size(4);
+        match Shuffler::new(&path, options, Some(vec!["small".to_string(), "large".to_string()])) {
+            Err(Error::ItemTooLarge { size: 6, limit: 4 }) => {}
+            r => panic!("unexpected result {r:?}"),
+        }
+    }
+
+    #[test]
+    fn max_item_size_existing() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let items = vec!["small".to_string(), "large".to_string()];
+        Shuffler::new_default(&path, Some(items)).unwrap().close().unwrap();
+
+        // Generations of stored items can still be rewritten after lowering the limit.
+        let options = || Options::default().max_item_size(4);
+        let mut shuffler = Shuffler::<String>::new(&path, options(), None).unwrap();
+        shuffler.internal.max_generation = 2;
+        for _ in 0..2 {
+            shuffler.next().unwrap().unwrap();
+        }
+        assert!(!shuffler.last_reset_occurred());
+        shuffler.next().unwrap().unwrap();
+        assert!(shuffler.last_reset_occurred());
+        assert_eq!(shuffler.touch_all(&["small".to_string()]).unwrap(), 1);
+        assert!(shuffler.replace(&"large".to_string(), "small".to_string()).unwrap());
+        assert!(matches!(
+            shuffler.replace(&"small".to_string(), "larger".to_string()),
+            Err(Error::ItemTooLarge { size: 7, limit: 4 })
+        ));
+        let dump: Vec<_> =
+            shuffler.dump_sorted().into_iter().map(|(i, g)| (i.clone(), g)).collect();
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<String>::new(&path, options(), None).unwrap();
+        let reopened: Vec<_> =
+            shuffler.dump_sorted().into_iter().map(|(i, g)| (i.clone(), g)).collect();
+        assert_eq!(dump, reopened);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn prune_to_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        for i in 0..20_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i))).unwrap();
+        }
+        assert_eq!(shuffler.prune_to_size(20).unwrap(), 0);
+        assert_eq!(shuffler.prune_to_size(5).unwrap(), 15);
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.values_sorted(), [&0, &1, &2, &3, &4]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn apply_op() {
+        for seed in 0..3 {
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("db");
+
+            let mut shuffler = Shuffler::new(&path, Options::default().seed(seed), None).unwrap();
+            crate::tests::replay_random_ops(&mut shuffler, seed);
+            let expected = shuffler.size();
+            shuffler.close().unwrap();
+
+            let shuffler = Shuffler::<u8>::new_default(&path, None).unwrap();
+            assert_eq!(shuffler.size(), expected);
+            assert_eq!(shuffler.verify_integrity(), Ok(()));
+            shuffler.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn huge_n() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, Some((0..10_u32).collect())).unwrap();
+        let before: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+
+        assert!(shuffler.next_n(usize::MAX).unwrap().is_none());
+        assert!(shuffler.next_n_spread(usize::MAX).unwrap().is_none());
+        assert!(shuffler.unique_n(usize::MAX).unwrap().is_none());
+        assert!(shuffler.preview_n(usize::MAX).unwrap().is_none());
+        assert!(shuffler.sample_n(usize::MAX).is_empty());
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        let after: Vec<_> = shuffler.dump_sorted().into_iter().map(|(i, g)| (*i, g)).collect();
+        assert_eq!(before, after);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn weighted_next() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let mut shuffler = Shuffler::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.weighted_next().unwrap(), None);
+        for i in 0..5_u32 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i) + 1)).unwrap();
+        }
+
+        let (item, probability) = shuffler.weighted_next().unwrap().unwrap();
+        let item = *item;
+        assert!(probability > 0.0 && probability <= 1.0);
+        shuffler.close().unwrap();
+
+        let shuffler = Shuffler::<u32>::new_default(&path, None).unwrap();
+        assert_eq!(shuffler.generations_of(&[item]), [Some(Generation::new(6))]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        // Items used to be stored as numbers and are now strings.
+        {
+            let db = DB::open_default(&path).unwrap();
+            db.put(encode::to_vec(&1_u32).unwrap(), encode::to_vec(&3_u64).unwrap()).unwrap();
+            db.put(encode::to_vec(&2_u32).unwrap(), encode::to_vec(&5_u64).unwrap()).unwrap();
+            db.put(encode::to_vec("b").unwrap(), encode::to_vec(&1_u64).unwrap()).unwrap();
+            db.put(encode::to_vec(&vec![0_u8]).unwrap(), encode::to_vec(&0_u64).unwrap()).unwrap();
+        }
+
+        let options = Options::default().remove_on_deserialization_error(true).migrate(Box::new(
+            |bytes| {
+                let n: u32 = decode::from_slice(bytes).ok()?;
+                encode::to_vec(&n.to_string()).ok()
+            },
+        ));
+        let (shuffler, report) =
+            Shuffler::<String>::new_with_report(&path, options, None).unwrap();
+        assert_eq!(report.loaded, 3);
+        assert_eq!(report.migrated, 2);
+        assert_eq!(report.dropped_bad_keys, 1);
+        shuffler.close().unwrap();
+
+        let (shuffler, report) =
+            Shuffler::<String>::new_with_report(&path, Options::default(), None).unwrap();
+        assert_eq!(report.loaded, 3);
+        assert_eq!(report.migrated, 0);
+        let dump = shuffler.dump_sorted();
+        let dump: Vec<_> = dump.iter().map(|(s, g)| (s.as_str(), g.get())).collect();
+        assert_eq!(dump, [("1", 3), ("2", 5), ("b", 1)]);
+        shuffler.close().unwrap();
+    }
+
+    #[test]
+    fn migrate_conflict() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        {
+            let db = DB::open_default(&path).unwrap();
+            db.put(encode::to_vec(&1_u32).unwrap(), encode::to_vec(&3_u64).unwrap()).unwrap();
+            db.put(encode::to_vec("1").unwrap(), encode::to_vec(&7_u64).unwrap()).unwrap();
+            db.put(encode::to_vec(&2_u32).unwrap(), encode::to_vec(&9_u64).unwrap()).unwrap();
+            db.put(encode::to_vec("2").unwrap(), encode::to_vec(&4_u64).unwrap()).unwrap();
+        }
+
+        let options = Options::default().migrate(Box::new(|bytes| {
+            let n: u32 = decode::from_slice(bytes).ok()?;
+            encode::to_vec(&n.to_string()).ok()
+        }));
+        let (shuffler, report) =
+            Shuffler::<String>::new_with_report(&path, options, None).unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.migrated, 0);
+        assert_eq!(report.migration_conflicts, 2);
+        let dump = shuffler.dump_sorted();
+        let dump: Vec<_> = dump.iter().map(|(s, g)| (s.as_str(), g.get())).collect();
+        assert_eq!(dump, [("1", 7), ("2", 9)]);
+        shuffler.close().unwrap();
+
+        // The old keys are gone, so the database loads without a migration.
+        let (shuffler, report) =
+            Shuffler::<String>::new_with_report(&path, Options::default(), None).unwrap();
+        assert_eq!(report.loaded, 2);
+        let dump = shuffler.dump_sorted();
+        let dump: Vec<_> = dump.iter().map(|(s, g)| (s.as_str(), g.get())).collect();
+        assert_eq!(dump, [("1", 7), ("2", 9)]);
+        shuffler.close().unwrap();
+    }
+}
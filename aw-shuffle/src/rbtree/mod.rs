@@ -1,23 +1,42 @@
 #![allow(missing_docs)]
 
-use std::cmp::{max, min, Ordering};
-use std::fmt::Debug;
-use std::hash::{BuildHasher, Hasher};
-use std::mem::swap;
-use std::ptr::NonNull;
-
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::{max, min, Ordering};
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use core::hash::BuildHasher;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::mem::swap;
+use core::ptr::NonNull;
+
+#[cfg(feature = "std")]
 use ahash::{AHasher, RandomState};
 
-use crate::Item;
+#[cfg(feature = "debug-nodes")]
+use crate::Generation;
+use crate::{Item, Query};
 
 // This was originally written in Go, translated to a version using Rc<RefCell<>>, debugged and
 // fuzzed, then converted into this code.
 
+// Orders items with identical hashes. Trees without one order them by when they were inserted.
+pub(crate) type Comparator<T> = fn(&T, &T) -> Ordering;
+
 pub struct Node<T> {
     item: T,
     hash: u64,
     gen: u64,
     red: bool,
+    // Temporarily excluded from selection and from min_gen and max_gen.
+    suspended: bool,
+    // Set when the node enters the shuffler's cooldown, to tell it apart from other nodes with the
+    // same hash. Fits in the padding after the flags.
+    ticket: u32,
+    #[cfg(feature = "selection-counts")]
+    selections: u64,
     children: usize,
     min_gen: u64,
     max_gen: u64,
@@ -26,28 +45,8 @@ pub struct Node<T> {
     right: Option<NonNull<Node<T>>>,
 }
 
-impl<T: Ord> Ord for Node<T> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.hash.cmp(&other.hash).then_with(|| self.item.cmp(&other.item))
-    }
-}
-
-impl<T: Ord> PartialOrd for Node<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl<T: Eq> Eq for Node<T> {}
-
-impl<T: PartialEq> PartialEq for Node<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.hash == other.hash && self.item == other.item
-    }
-}
-
 impl<T: Debug> Debug for Node<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Node")
             .field("item", &self.item)
             .field("hash", &self.hash)
@@ -60,6 +59,73 @@ impl<T: Debug> Debug for Node<T> {
     }
 }
 
+/// A broken invariant found when verifying the integrity of a shuffler's internal tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IntegrityError {
+    /// The recorded size doesn't match the number of items in the tree.
+    Size,
+    /// The root has a parent or is red.
+    Root,
+    /// A child's parent pointer doesn't point back at its parent.
+    Parent,
+    /// Items are not correctly ordered within the tree.
+    Order,
+    /// A red node has a red child.
+    RedRed,
+    /// Different paths through the tree pass through different numbers of black nodes.
+    BlackHeight,
+    /// A node's count of its descendants is wrong.
+    Children,
+    /// A node's minimum or maximum generation is wrong.
+    Generations,
+}
+
+impl core::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Size => "tree size does not match its contents",
+            Self::Root => "root node has a parent or is red",
+            Self::Parent => "child node has an incorrect parent",
+            Self::Order => "nodes are out of order",
+            Self::RedRed => "red node has a red child",
+            Self::BlackHeight => "black height is unbalanced",
+            Self::Children => "node has an incorrect number of children",
+            Self::Generations => "node has incorrect minimum or maximum generations",
+        })
+    }
+}
+
+impl core::error::Error for IntegrityError {}
+
+/// A read-only snapshot of one node of a shuffler's internal tree, for debugging and
+/// visualization. Requires the `debug-nodes` feature.
+///
+/// Nodes are identified by their index, which is their position in the order of the tree, the
+/// same order as [`AwShuffler::values`](crate::AwShuffler::values).
+#[cfg(feature = "debug-nodes")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugNode<'a, T> {
+    /// The item stored in this node.
+    pub item: &'a T,
+    /// The generation of the item.
+    pub generation: Generation,
+    /// The smallest generation in the subtree rooted at this node, excluding suspended nodes.
+    pub min_gen: Generation,
+    /// The largest generation in the subtree rooted at this node, excluding suspended nodes.
+    pub max_gen: Generation,
+    /// The number of descendants of this node, not including itself.
+    pub children: usize,
+    /// Whether this node is red rather than black.
+    pub red: bool,
+    /// The index of the parent of this node, or `None` for the root.
+    pub parent: Option<usize>,
+    /// The index of the left child of this node.
+    pub left: Option<usize>,
+    /// The index of the right child of this node.
+    pub right: Option<usize>,
+}
+
 enum SoleRedChild<T> {
     Right(NonNull<Node<T>>),
     Left(NonNull<Node<T>>),
@@ -72,13 +138,52 @@ impl<T> Node<T> {
         &self.item
     }
 
+    #[inline]
+    pub(crate) const fn generation(&self) -> u64 {
+        self.gen
+    }
+
+    #[inline]
+    pub(crate) const fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    #[inline]
+    pub(crate) const fn ticket(&self) -> u32 {
+        self.ticket
+    }
+
+    pub(crate) fn set_ticket(mut node: NonNull<Self>, ticket: u32) {
+        unsafe { node.as_mut() }.ticket = ticket;
+    }
+
+    // The next node in order, if any.
+    fn successor(n: NonNull<Self>) -> Option<NonNull<Self>> {
+        let nb = unsafe { n.as_ref() };
+        if let Some(mut s) = nb.right {
+            while let Some(l) = unsafe { s.as_ref() }.left {
+                s = l;
+            }
+            return Some(s);
+        }
+
+        let mut c = n;
+        while let Some(p) = unsafe { c.as_ref() }.parent {
+            if unsafe { p.as_ref() }.is_left_child(unsafe { c.as_ref() }) {
+                return Some(p);
+            }
+            c = p;
+        }
+        None
+    }
+
     fn other_child(&self, c: &Self) -> &Option<NonNull<Self>> {
         if self.is_left_child(c) { &self.right } else { &self.left }
     }
 
     fn is_left_child(&self, c: &Self) -> bool {
         if let Some(left) = self.left {
-            unsafe { std::ptr::eq(c, left.as_ref()) }
+            unsafe { core::ptr::eq(c, left.as_ref()) }
         } else {
             false
         }
@@ -124,8 +229,13 @@ impl<T> Node<T> {
 
     fn recalculate(&mut self) {
         self.children = 0;
-        self.max_gen = self.gen;
-        self.min_gen = self.gen;
+        if self.suspended {
+            self.min_gen = u64::MAX;
+            self.max_gen = 0;
+        } else {
+            self.max_gen = self.gen;
+            self.min_gen = self.gen;
+        }
 
         if let Some(left) = self.left {
             let lb = unsafe { left.as_ref() };
@@ -163,10 +273,69 @@ impl<T> Node<T> {
         }
     }
 
-    // Finds the first node with index >= i and gen <= g
+    // The number of nodes in the subtree rooted at n.
+    #[cfg(feature = "debug-nodes")]
+    fn subtree_size(n: Option<NonNull<Self>>) -> usize {
+        n.map_or(0, |n| unsafe { n.as_ref() }.children + 1)
+    }
+
+    // Appends the subtree rooted at this node in order, where offset is the index of the first
+    // node in the subtree.
+    #[cfg(feature = "debug-nodes")]
+    fn debug_nodes<'a>(
+        &'a self,
+        offset: usize,
+        parent: Option<usize>,
+        nodes: &mut Vec<DebugNode<'a, T>>,
+    ) {
+        let index = offset + Self::subtree_size(self.left);
+        let left = self.left.map(|l| offset + Self::subtree_size(unsafe { l.as_ref() }.left));
+        let right =
+            self.right.map(|r| index + 1 + Self::subtree_size(unsafe { r.as_ref() }.left));
+
+        if let Some(l) = self.left {
+            unsafe { l.as_ref() }.debug_nodes(offset, Some(index), nodes);
+        }
+        nodes.push(DebugNode {
+            item: &self.item,
+            generation: Generation::new(self.gen),
+            min_gen: Generation::new(self.min_gen),
+            max_gen: Generation::new(self.max_gen),
+            children: self.children,
+            red: self.red,
+            parent,
+            left,
+            right,
+        });
+        if let Some(r) = self.right {
+            unsafe { r.as_ref() }.debug_nodes(index + 1, Some(index), nodes);
+        }
+    }
+
+    // Gives a selected node its new generation and counts the selection, if enabled.
+    pub(crate) fn select(node: NonNull<Self>, next_gen: u64) {
+        #[cfg(feature = "selection-counts")]
+        unsafe {
+            (*node.as_ptr()).selections += 1;
+        }
+        Self::set_generation(node, next_gen);
+    }
+
+    // Suspended nodes are never returned by find_next and don't count towards the generations of
+    // the tree. At least one node must be left unsuspended.
+    pub(crate) fn set_suspended(mut node: NonNull<Self>, suspended: bool) {
+        let n = unsafe { node.as_mut() };
+        if n.suspended != suspended {
+            n.suspended = suspended;
+            Self::recalc_ancestors(node);
+        }
+    }
+
+    // Finds the first node with index >= i and gen <= g, otherwise returns the size of the subtree.
     fn find_above(node: NonNull<Self>, i: usize, g: u64) -> Result<NonNull<Self>, usize> {
         let nb = unsafe { node.as_ref() };
-        if nb.min_gen > g || nb.children + 1 < i {
+        // The indices in this subtree are 0..=children, so nothing is at or above children + 1.
+        if nb.min_gen > g || nb.children < i {
             return Err(nb.children + 1);
         }
 
@@ -179,7 +348,7 @@ impl<T> Node<T> {
             }
         }
 
-        if i <= left_children && nb.gen <= g {
+        if i <= left_children && nb.gen <= g && !nb.suspended {
             return Ok(node);
         }
 
@@ -193,6 +362,29 @@ impl<T> Node<T> {
         Err(nb.children + 1)
     }
 
+    // Returns the minimum generation of any unsuspended node with index >= i, or u64::MAX if there
+    // are none.
+    fn min_gen_above(node: NonNull<Self>, i: usize) -> u64 {
+        let nb = unsafe { node.as_ref() };
+        if i == 0 {
+            return nb.min_gen;
+        }
+        if i > nb.children {
+            return u64::MAX;
+        }
+
+        let left_children = nb.left.map_or(0, |l| unsafe { l.as_ref() }.children + 1);
+        if i > left_children {
+            return nb.right.map_or(u64::MAX, |r| Self::min_gen_above(r, i - left_children - 1));
+        }
+
+        let mut m = nb.left.map_or(u64::MAX, |l| Self::min_gen_above(l, i));
+        if !nb.suspended {
+            m = min(m, nb.gen);
+        }
+        nb.right.map_or(m, |r| min(m, unsafe { r.as_ref() }.min_gen))
+    }
+
     fn values<'a>(&'a self, vals: &mut Vec<&'a T>) {
         if let Some(left) = self.left {
             unsafe {
@@ -221,6 +413,47 @@ impl<T> Node<T> {
         }
     }
 
+    #[cfg(feature = "selection-counts")]
+    fn selection_counts<'a>(&'a self, vals: &mut Vec<(&'a T, u64)>) {
+        if let Some(left) = self.left {
+            unsafe {
+                left.as_ref().selection_counts(vals);
+            }
+        }
+        vals.push((&self.item, self.selections));
+        if let Some(right) = &self.right {
+            unsafe {
+                right.as_ref().selection_counts(vals);
+            }
+        }
+    }
+
+    #[cfg(feature = "selection-counts")]
+    fn reset_selection_counts(&mut self) {
+        self.selections = 0;
+        unsafe {
+            if let Some(mut left) = self.left {
+                left.as_mut().reset_selection_counts();
+            }
+            if let Some(mut right) = self.right {
+                right.as_mut().reset_selection_counts();
+            }
+        }
+    }
+
+    fn rebase(&mut self, gens: &[u64]) {
+        unsafe {
+            if let Some(mut left) = self.left {
+                left.as_mut().rebase(gens);
+            }
+            if let Some(mut right) = self.right {
+                right.as_mut().rebase(gens);
+            }
+        }
+        self.gen = gens.binary_search(&self.gen).expect("Corrupt tree") as u64;
+        self.recalculate();
+    }
+
     fn reset(&mut self) {
         self.gen = 0;
         self.min_gen = 0;
@@ -279,12 +512,76 @@ impl<T> Node<T> {
     }
 }
 
+impl<T> Node<T> {
+    // Orders by hash and then by the comparator, if there is one.
+    fn cmp_with(&self, other: &Self, cmp: Option<Comparator<T>>) -> Ordering {
+        match cmp {
+            Some(cmp) => self.hash.cmp(&other.hash).then_with(|| cmp(&self.item, &other.item)),
+            None => self.hash.cmp(&other.hash),
+        }
+    }
+
+    // Checks the invariants of the subtree rooted at this node, returning its black height.
+    fn check(&self, cmp: Option<Comparator<T>>) -> Result<usize, IntegrityError> {
+        let (mut min_gen, mut max_gen) =
+            if self.suspended { (u64::MAX, 0) } else { (self.gen, self.gen) };
+        let mut children = 0;
+        let mut black_heights = [0, 0];
+
+        for (i, child) in [self.left, self.right].into_iter().enumerate() {
+            let Some(child) = child else {
+                continue;
+            };
+            let cb = unsafe { child.as_ref() };
+
+            if !cb.parent.is_some_and(|p| core::ptr::eq(p.as_ptr(), self)) {
+                return Err(IntegrityError::Parent);
+            }
+
+            // Without a comparator identical hashes are in insertion order and can't be checked.
+            let ordered = match cb.cmp_with(self, cmp) {
+                Ordering::Equal => cmp.is_none(),
+                ordering => ordering.is_lt() == (i == 0),
+            };
+            if !ordered {
+                return Err(IntegrityError::Order);
+            }
+
+            // red nodes cannot have red children
+            if self.red && cb.red {
+                return Err(IntegrityError::RedRed);
+            }
+
+            black_heights[i] = cb.check(cmp)?;
+            children += cb.children + 1;
+            min_gen = min(min_gen, cb.min_gen);
+            max_gen = max(max_gen, cb.max_gen);
+        }
+
+        if black_heights[0] != black_heights[1] {
+            return Err(IntegrityError::BlackHeight);
+        }
+        if self.children != children {
+            return Err(IntegrityError::Children);
+        }
+        if self.min_gen != min_gen || self.max_gen != max_gen {
+            return Err(IntegrityError::Generations);
+        }
+
+        Ok(if self.red { black_heights[0] } else { black_heights[0] + 1 })
+    }
+}
+
 // TODO -- it'd be possible to drop the Clone requirement here.
 #[derive(Debug)]
 pub struct Rbtree<T, H> {
     root: Option<NonNull<Node<T>>>,
     size: usize,
     hasher: H,
+    cmp: Option<Comparator<T>>,
+    // Only for instrumenting the tree.
+    #[cfg(feature = "rotation-count")]
+    rotations: u64,
 }
 
 unsafe impl<T, H> Send for Rbtree<T, H>
@@ -295,16 +592,55 @@ where
 }
 // Implementing Sync would likely be safe but functionally probably useless.
 
-impl<T> Default for Rbtree<T, AHasher> {
+// Iterates over the items and generations of a tree in order without collecting them.
+pub(crate) struct Entries<'a, T> {
+    next: Option<NonNull<Node<T>>>,
+    remaining: usize,
+    _tree: PhantomData<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for Entries<'a, T> {
+    type Item = (&'a T, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next?;
+        self.next = Node::successor(node);
+        self.remaining -= 1;
+
+        let node = unsafe { node.as_ref() };
+        Some((&node.item, node.gen))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Entries<'_, T> {}
+
+#[cfg(feature = "std")]
+impl<T: Ord> Default for Rbtree<T, AHasher> {
     fn default() -> Self {
         Self {
             root: None,
             size: 0,
             hasher: RandomState::new().build_hasher(),
+            cmp: Some(T::cmp),
+            #[cfg(feature = "rotation-count")]
+            rotations: 0,
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl<T: Item + Ord> Rbtree<T, AHasher> {
+    // Hashes items identically on every run, so benchmarks always build the same trees.
+    pub fn new_seeded(seeds: [u64; 4]) -> Self {
+        let [k0, k1, k2, k3] = seeds;
+        Self::new(RandomState::with_seeds(k0, k1, k2, k3).build_hasher())
+    }
+}
+
 impl<T, H> Drop for Rbtree<T, H> {
     fn drop(&mut self) {
         if let Some(root) = self.root.take() {
@@ -323,33 +659,120 @@ where
     T: Item,
     H: Hasher + Clone,
 {
-    pub(crate) const fn new(hasher: H) -> Self {
-        Self { root: None, size: 0, hasher }
+    pub(crate) const fn new(hasher: H) -> Self
+    where
+        T: Ord,
+    {
+        let mut tree = Self::new_hash_only(hasher);
+        tree.cmp = Some(T::cmp);
+        tree
+    }
+
+    // Orders items with identical hashes by when they were inserted, so T need not be Ord.
+    pub(crate) const fn new_hash_only(hasher: H) -> Self {
+        Self {
+            root: None,
+            size: 0,
+            hasher,
+            cmp: None,
+            #[cfg(feature = "rotation-count")]
+            rotations: 0,
+        }
+    }
+
+    #[cfg(feature = "debug-nodes")]
+    pub(crate) fn debug_nodes(&self) -> Vec<DebugNode<'_, T>> {
+        let mut nodes = Vec::with_capacity(self.size);
+        if let Some(root) = self.root {
+            unsafe { root.as_ref() }.debug_nodes(0, None, &mut nodes);
+        }
+        nodes
+    }
+
+    // Only valid while the tree is empty.
+    pub(crate) fn set_comparator(&mut self, cmp: Comparator<T>) {
+        debug_assert_eq!(self.size, 0);
+        self.cmp = Some(cmp);
     }
 
-    fn hash(&self, item: &T) -> u64 {
+    fn hash<Q: Hash + ?Sized>(&self, item: &Q) -> u64 {
         let mut hasher = self.hasher.clone();
         item.hash(&mut hasher);
         hasher.finish()
     }
 
-    pub(crate) fn find_node(&self, item: &T) -> Option<NonNull<Node<T>>> {
-        let mut n = self.root?;
-
-        let h = self.hash(item);
+    // Borrowed forms of items must hash and compare the same as the items themselves.
+    pub(crate) fn find_node<Q>(&self, item: &Q) -> Option<NonNull<Node<T>>>
+    where
+        T: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        self.find_hashed(self.hash(item), item)
+    }
+
+    // Nodes with identical hashes are adjacent but the comparator can't be applied to borrowed
+    // forms, so scan them all starting from the leftmost.
+    fn find_hashed<Q>(&self, h: u64, item: &Q) -> Option<NonNull<Node<T>>>
+    where
+        T: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        let mut leftmost = None;
+        let mut next = self.root;
+        while let Some(n) = next {
+            let nb = unsafe { n.as_ref() };
+            next = match h.cmp(&nb.hash) {
+                Ordering::Equal if nb.item.borrow() == item => return Some(n),
+                Ordering::Equal => {
+                    leftmost = Some(n);
+                    nb.left
+                }
+                Ordering::Less => nb.left,
+                Ordering::Greater => nb.right,
+            };
+        }
 
+        let mut n = leftmost?;
         loop {
             let nb = unsafe { n.as_ref() };
-            let next = match (h, item).cmp(&(nb.hash, &nb.item)) {
-                Ordering::Equal => break,
+            if nb.hash != h {
+                return None;
+            }
+            if nb.item.borrow() == item {
+                return Some(n);
+            }
+            n = Node::successor(n)?;
+        }
+    }
+
+    // Finds the node with the given hash and cooldown ticket.
+    pub(crate) fn find_ticket(&self, h: u64, ticket: u32) -> Option<NonNull<Node<T>>> {
+        let mut leftmost = None;
+        let mut next = self.root;
+        while let Some(n) = next {
+            let nb = unsafe { n.as_ref() };
+            next = match h.cmp(&nb.hash) {
+                Ordering::Equal if nb.ticket == ticket => return Some(n),
+                Ordering::Equal => {
+                    leftmost = Some(n);
+                    nb.left
+                }
                 Ordering::Less => nb.left,
                 Ordering::Greater => nb.right,
             };
-
-            n = next?;
         }
 
-        Some(n)
+        let mut n = leftmost?;
+        loop {
+            let nb = unsafe { n.as_ref() };
+            if nb.hash != h {
+                return None;
+            }
+            if nb.ticket == ticket {
+                return Some(n);
+            }
+            n = Node::successor(n)?;
+        }
     }
 
     pub fn insert(&mut self, item: T, gen: u64) -> bool {
@@ -358,11 +781,20 @@ where
     }
 
     pub fn reinsert(&mut self, item: T, hash: u64, gen: u64) -> bool {
+        // The descent below can't find duplicates when they're ordered by insertion.
+        if self.cmp.is_none() && self.find_hashed(hash, &item).is_some() {
+            return false;
+        }
+
         let mut node = Node {
             item,
             hash,
             gen,
             red: true,
+            suspended: false,
+            ticket: 0,
+            #[cfg(feature = "selection-counts")]
+            selections: 0,
             children: 0,
             min_gen: gen,
             max_gen: gen,
@@ -383,10 +815,20 @@ where
             p = c;
 
             let next = unsafe {
-                match node.cmp(c.as_ref()) {
-                    Ordering::Equal => return false,
+                match node.cmp_with(c.as_ref(), self.cmp) {
+                    Ordering::Equal if self.cmp.is_some() => {
+                        // Only possible when Ord or the comparator is inconsistent with Eq, and
+                        // the new item would be lost.
+                        debug_assert!(
+                            node.item == c.as_ref().item,
+                            "unequal items with the same hash compared as equal, Ord is \
+                             inconsistent with Eq, or the comparator is"
+                        );
+                        return false;
+                    }
                     Ordering::Less => c.as_ref().left,
-                    Ordering::Greater => c.as_ref().right,
+                    // Without a comparator new items go after any with the same hash.
+                    Ordering::Equal | Ordering::Greater => c.as_ref().right,
                 }
             };
 
@@ -401,10 +843,9 @@ where
         let node = unsafe { NonNull::new_unchecked(Box::into_raw(Box::from(node))) };
 
         unsafe {
-            match node.as_ref().cmp(p.as_ref()) {
-                Ordering::Equal => unreachable!(),
+            match node.as_ref().cmp_with(p.as_ref(), self.cmp) {
                 Ordering::Less => p.as_mut().left = Some(node),
-                Ordering::Greater => p.as_mut().right = Some(node),
+                Ordering::Equal | Ordering::Greater => p.as_mut().right = Some(node),
             }
         }
 
@@ -432,9 +873,63 @@ where
         true
     }
 
-    pub fn delete(&mut self, item: &T) -> Option<(T, u64)> {
-        let mut n = self.find_node(item)?;
+    // Returns the deleted item and its generation.
+    pub fn delete<Q>(&mut self, item: &Q) -> Option<(T, u64)>
+    where
+        T: Borrow<Q>,
+        Q: Query + ?Sized,
+    {
+        let n = self.find_node(item)?;
+        Some(self.delete_node(n))
+    }
+
+    // Removes the item with the newest generation, preferring the last in the tree's order when
+    // several share it.
+    pub(crate) fn pop_newest(&mut self) -> Option<(T, u64)> {
+        let mut n = self.root?;
+        let newest = unsafe { n.as_ref() }.max_gen;
+
+        loop {
+            let nb = unsafe { n.as_ref() };
+            match nb.right {
+                Some(r) if unsafe { r.as_ref() }.max_gen == newest => n = r,
+                _ if nb.gen == newest => break,
+                _ => n = nb.left.expect("Corrupt tree"),
+            }
+        }
+
+        Some(self.delete_node(n))
+    }
+
+    // Removes every item for which f returns false, returning the removed items.
+    pub(crate) fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Vec<T> {
+        let mut removed = Vec::new();
+
+        let mut next = self.root.map(|mut n| {
+            while let Some(l) = unsafe { n.as_ref() }.left {
+                n = l;
+            }
+            n
+        });
+
+        while let Some(n) = next {
+            let nb = unsafe { n.as_ref() };
+            if f(&nb.item) {
+                next = Node::successor(n);
+                continue;
+            }
+
+            // A node with two children takes its successor's contents and stays in the tree,
+            // otherwise it is freed and its successor is untouched.
+            let two_children = nb.left.is_some() && nb.right.is_some();
+            next = if two_children { Some(n) } else { Node::successor(n) };
+            removed.push(self.delete_node(n).0);
+        }
+
+        removed
+    }
 
+    fn delete_node(&mut self, mut n: NonNull<Node<T>>) -> (T, u64) {
         self.size -= 1;
 
         let nb = unsafe { n.as_mut() };
@@ -446,11 +941,14 @@ where
             }
 
             let sb = unsafe { s.as_mut() };
-            // Only item, hash, and gen need to be swapped,
+            // Only item, hash, gen, ticket, and selections need to be swapped,
             // the rest will be recalculated in the next step
             swap(&mut nb.item, &mut sb.item);
             swap(&mut nb.hash, &mut sb.hash);
             swap(&mut nb.gen, &mut sb.gen);
+            swap(&mut nb.ticket, &mut sb.ticket);
+            #[cfg(feature = "selection-counts")]
+            swap(&mut nb.selections, &mut sb.selections);
             s
         } else {
             n
@@ -475,7 +973,7 @@ where
             // By now there are no other pointers to n and it can be dropped.
             let n = unsafe { Box::from_raw(n.as_ptr()) };
 
-            return Some((n.item, n.hash));
+            return (n.item, n.gen);
         };
 
         let (c, c_red) = match (nb.left, nb.right) {
@@ -520,7 +1018,7 @@ where
         // By now there are no other pointers to n and it can be dropped.
         let n = unsafe { Box::from_raw(n.as_ptr()) };
 
-        Some((n.item, n.hash))
+        (n.item, n.gen)
     }
 
     fn fix_after_insert(&mut self, node: NonNull<Node<T>>) {
@@ -554,7 +1052,7 @@ where
 
                 if gb.is_left_child(pnd.as_ref()) {
                     if let Some(pright) = pnd.as_ref().right {
-                        if std::ptr::eq(c.as_ptr(), pright.as_ptr()) {
+                        if core::ptr::eq(c.as_ptr(), pright.as_ptr()) {
                             self.rotate_left(pnd);
                             pnd = c;
                         }
@@ -563,7 +1061,7 @@ where
                     self.rotate_right(g);
                 } else {
                     if let Some(pleft) = pnd.as_ref().left.as_ref() {
-                        if std::ptr::eq(c.as_ptr(), pleft.as_ptr()) {
+                        if core::ptr::eq(c.as_ptr(), pleft.as_ptr()) {
                             self.rotate_right(pnd);
                             pnd = c;
                         }
@@ -686,6 +1184,11 @@ where
     }
 
     fn rotate_right(&mut self, mut parent: NonNull<Node<T>>) {
+        #[cfg(feature = "rotation-count")]
+        {
+            self.rotations += 1;
+        }
+
         // Left child becomes the new parent
         let pb = unsafe { parent.as_mut() };
         let mut l = pb.left.expect("Tried to make None child into parent");
@@ -716,6 +1219,11 @@ where
     }
 
     fn rotate_left(&mut self, mut parent: NonNull<Node<T>>) {
+        #[cfg(feature = "rotation-count")]
+        {
+            self.rotations += 1;
+        }
+
         // Right child becomes the new parent
         let pb = unsafe { parent.as_mut() };
         let mut r = pb.right.expect("Tried to make None child into parent");
@@ -752,6 +1260,19 @@ where
         }
     }
 
+    // Maps the distinct generations onto 0..k, preserving their order.
+    pub(crate) fn rebase(&mut self) {
+        let Some(mut root) = self.root else {
+            return;
+        };
+
+        let mut gens: Vec<_> = self.dump().into_iter().map(|(_, g)| g).collect();
+        gens.sort_unstable();
+        gens.dedup();
+
+        unsafe { root.as_mut().rebase(&gens) }
+    }
+
     // Finds the next item with a generation <= g after index (inclusive).
     // Wraps around to the start of the tree if one isn't found.
     #[allow(clippy::missing_panics_doc)]
@@ -765,6 +1286,23 @@ where
             .expect("Corrupt tree")
     }
 
+    // Finds the next item with a generation <= g after index (inclusive).
+    // Instead of wrapping, if one isn't found the generation is raised to the oldest generation
+    // after index. Only wraps if every item after index is suspended.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn find_next_widening(&self, index: usize, gen: u64) -> NonNull<Node<T>> {
+        assert!(self.size > 0);
+        assert!(index < self.size);
+        let root = self.root.expect("Root cannot be None in a tree with size > 0");
+
+        Node::find_above(root, index, gen)
+            .or_else(|_| match Node::min_gen_above(root, index) {
+                u64::MAX => Node::find_above(root, 0, gen),
+                widened => Node::find_above(root, index, widened),
+            })
+            .expect("Corrupt tree")
+    }
+
     pub(crate) fn values(&self) -> Vec<&T> {
         let mut out = Vec::with_capacity(self.size);
 
@@ -775,6 +1313,14 @@ where
         out
     }
 
+    #[cfg(feature = "rocks")]
+    pub(crate) fn clear(&mut self) {
+        if let Some(root) = self.root.take() {
+            unsafe { Node::destroy_tree(root) }
+        }
+        self.size = 0;
+    }
+
     pub(crate) fn into_values(mut self) -> Vec<T> {
         let mut out = Vec::with_capacity(self.size);
 
@@ -787,6 +1333,40 @@ where
         out
     }
 
+    pub(crate) fn entries(&self) -> Entries<'_, T> {
+        let mut next = self.root;
+        while let Some(l) = next.and_then(|n| unsafe { n.as_ref() }.left) {
+            next = Some(l);
+        }
+
+        Entries { next, remaining: self.size, _tree: PhantomData }
+    }
+
+    // Returns the generation of every node in order, with None for suspended nodes, along with
+    // the index of target.
+    pub(crate) fn selectable_generations(
+        &self,
+        target: NonNull<Node<T>>,
+    ) -> (Vec<Option<u64>>, usize) {
+        let mut gens = Vec::with_capacity(self.size);
+        let mut index = 0;
+
+        let mut next = self.root;
+        while let Some(l) = next.and_then(|n| unsafe { n.as_ref() }.left) {
+            next = Some(l);
+        }
+        while let Some(node) = next {
+            if node == target {
+                index = gens.len();
+            }
+            let nb = unsafe { node.as_ref() };
+            gens.push((!nb.suspended).then_some(nb.gen));
+            next = Node::successor(node);
+        }
+
+        (gens, index)
+    }
+
     pub(crate) fn dump(&self) -> Vec<(&T, u64)> {
         let mut out = Vec::with_capacity(self.size);
 
@@ -797,6 +1377,34 @@ where
         out
     }
 
+    #[cfg(feature = "selection-counts")]
+    pub(crate) fn selection_counts(&self) -> Vec<(&T, u64)> {
+        let mut out = Vec::with_capacity(self.size);
+
+        if let Some(root) = &self.root {
+            unsafe { root.as_ref().selection_counts(&mut out) };
+        }
+
+        out
+    }
+
+    pub(crate) fn reset_selection_counts(&mut self) {
+        #[cfg(feature = "selection-counts")]
+        if let Some(mut root) = self.root {
+            unsafe { root.as_mut().reset_selection_counts() };
+        }
+    }
+
+    #[cfg(feature = "rotation-count")]
+    pub(crate) const fn rotation_count(&self) -> u64 {
+        self.rotations
+    }
+
+    #[cfg(feature = "rotation-count")]
+    pub(crate) fn reset_rotation_count(&mut self) {
+        self.rotations = 0;
+    }
+
     pub(crate) const fn size(&self) -> usize {
         if let Some(root) = &self.root {
             unsafe { root.as_ref().children + 1 }
@@ -813,9 +1421,27 @@ where
             (0, 0)
         }
     }
+
+    // Checks the red-black invariants and the cached children counts and generations.
+    pub(crate) fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        let Some(root) = self.root else {
+            return if self.size == 0 { Ok(()) } else { Err(IntegrityError::Size) };
+        };
+        let rb = unsafe { root.as_ref() };
+
+        if rb.parent.is_some() || rb.red {
+            return Err(IntegrityError::Root);
+        }
+        rb.check(self.cmp)?;
+
+        if self.size != rb.children + 1 {
+            return Err(IntegrityError::Size);
+        }
+        Ok(())
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 impl<T> Node<T>
 where
     T: Item + std::fmt::Display + Debug,
@@ -858,57 +1484,9 @@ where
 
         format!("({} {} {c} {left} {right})", self.item, self.gen)
     }
-
-    fn verify(&self) -> usize {
-        let mut min_gen = self.gen;
-        let mut max_gen = self.gen;
-        let mut children = 0;
-
-        unsafe {
-            let (l_black, l_red) = if let Some(left) = self.left {
-                let lb = left.as_ref();
-                assert_eq!(self, lb.parent.unwrap().as_ref());
-
-                assert!(self.hash >= lb.hash);
-                assert!(self > lb);
-
-                children += lb.children + 1;
-                min_gen = min(min_gen, lb.min_gen);
-                max_gen = max(max_gen, lb.max_gen);
-                (lb.verify(), lb.red)
-            } else {
-                (0, false)
-            };
-
-            let (r_black, r_red) = if let Some(right) = self.right {
-                let rb = right.as_ref();
-                assert_eq!(self, rb.parent.unwrap().as_ref());
-
-                assert!(self.hash <= rb.hash);
-                assert!(self < rb);
-
-                children += rb.children + 1;
-                min_gen = min(min_gen, rb.min_gen);
-                max_gen = max(max_gen, rb.max_gen);
-                (rb.verify(), rb.red)
-            } else {
-                (0, false)
-            };
-
-            // red nodes cannot have red children
-            assert!(!self.red || !(l_red || r_red));
-
-            assert_eq!(self.min_gen, min_gen);
-            assert_eq!(self.max_gen, max_gen);
-            assert_eq!(self.children, children);
-            assert_eq!(l_black, r_black);
-
-            if self.red { l_black } else { l_black + 1 }
-        }
-    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 impl<T, H> Rbtree<T, H>
 where
     T: Item + std::fmt::Display + Debug,
@@ -930,24 +1508,18 @@ where
     }
 
     fn verify(&self) {
-        match self.root {
-            None => {
-                assert_eq!(self.size, 0);
-            }
-            Some(root) => {
-                let rb = unsafe { root.as_ref() };
-
-                assert_eq!(self.size, rb.children + 1);
-                assert!(rb.parent.is_none());
-                assert!(!rb.red);
+        self.verify_integrity().unwrap();
+    }
 
-                rb.verify();
-            }
+    // Breaks the children count of the root for testing integrity checks.
+    pub(crate) fn corrupt_children(&mut self) {
+        if let Some(mut root) = self.root {
+            unsafe { root.as_mut().children += 1 };
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 pub mod tests {
     use std::convert::TryInto;
     use std::hash::{BuildHasher, Hasher};
@@ -957,8 +1529,10 @@ pub mod tests {
 
     use ahash::{AHashMap, RandomState};
     use rand::prelude::SliceRandom;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
-    use super::{Node, Rbtree};
+    use super::{IntegrityError, Node, Rbtree};
 
     #[derive(Clone)]
     pub(crate) struct DummyHasher {
@@ -979,13 +1553,16 @@ pub mod tests {
         }
     }
 
-    impl Rbtree<&'static str, DummyHasher> {
+    impl Rbtree<&str, DummyHasher> {
         pub(crate) fn new_dummy(entries: &[(&'static str, u64)]) -> Self {
             let hashes: AHashMap<_, _> = entries.iter().copied().collect();
             Self {
                 root: None,
                 size: 0,
                 hasher: DummyHasher { val: 0, values: Rc::from(hashes) },
+                cmp: Some(Ord::cmp),
+                #[cfg(feature = "rotation-count")]
+                rotations: 0,
             }
         }
     }
@@ -1018,12 +1595,27 @@ pub mod tests {
         assert_eq!(rb.print(), "(6 2 b (5 0 r  ) (4 1 r  ))");
     }
 
+    #[test]
+    fn new_seeded() {
+        let build = |seeds| {
+            let mut rb = Rbtree::new_seeded(seeds);
+            for (i, s) in sequential_strings(100).into_iter().enumerate() {
+                assert!(rb.insert(s, i.try_into().unwrap()));
+            }
+            rb.verify();
+            rb.print()
+        };
+
+        assert_eq!(build([1, 2, 3, 4]), build([1, 2, 3, 4]));
+        assert_ne!(build([1, 2, 3, 4]), build([4, 3, 2, 1]));
+    }
+
     #[test]
     fn test_hasher() {
         // ahash may change output when updated, so this test may fail after updating dependencies
         // Can also fail in miri due to different hash output, but not UB.
         let hasher = RandomState::with_seeds(100, 200, 300, 400).build_hasher();
-        let mut rb = Rbtree { root: None, size: 0, hasher };
+        let mut rb = Rbtree::new(hasher);
 
         assert!(rb.insert("5", 0));
         assert!(rb.insert("4", 1));
@@ -1033,7 +1625,7 @@ pub mod tests {
         assert_eq!(rb.print(), "(4 1 b (5 0 r  ) (6 2 r  ))");
 
         let hasher = RandomState::with_seeds(400, 300, 200, 100).build_hasher();
-        let mut rb = Rbtree { root: None, size: 0, hasher };
+        let mut rb = Rbtree::new(hasher);
 
         assert!(rb.insert("5", 0));
         assert!(rb.insert("4", 1));
@@ -1057,6 +1649,41 @@ pub mod tests {
         assert_eq!(rb.print(), "(4 1 b (2 3 b (1 4 r  ) (3 2 r  )) (5 0 b  ))");
     }
 
+    #[cfg(feature = "debug-nodes")]
+    #[test]
+    fn debug_nodes() {
+        use super::DebugNode;
+        use crate::Generation;
+
+        let mut rb = Rbtree::new_dummy(&[]);
+        assert!(rb.debug_nodes().is_empty());
+        for (s, gen) in [("5", 0), ("4", 1), ("3", 2), ("2", 3), ("1", 4)] {
+            assert!(rb.insert(s, gen));
+        }
+        assert_eq!(rb.print(), "(4 1 b (2 3 b (1 4 r  ) (3 2 r  )) (5 0 b  ))");
+
+        let node = |item, generation, (min_gen, max_gen), children, red, parent, left, right| {
+            DebugNode {
+                item,
+                generation: Generation::new(generation),
+                min_gen: Generation::new(min_gen),
+                max_gen: Generation::new(max_gen),
+                children,
+                red,
+                parent,
+                left,
+                right,
+            }
+        };
+        assert_eq!(rb.debug_nodes(), [
+            node(&"1", 4, (4, 4), 0, true, Some(1), None, None),
+            node(&"2", 3, (2, 4), 2, false, Some(3), Some(0), Some(2)),
+            node(&"3", 2, (2, 2), 0, true, Some(1), None, None),
+            node(&"4", 1, (0, 4), 4, false, None, Some(1), Some(4)),
+            node(&"5", 0, (0, 0), 0, false, Some(3), None, None),
+        ]);
+    }
+
     #[test]
     fn right_insert() {
         let mut rb = Rbtree::new_dummy(&[]);
@@ -1112,6 +1739,24 @@ pub mod tests {
         assert_eq!(rb.print(), "(5 0 b (2 0 b (1 0 r  ) (3 0 r  )) (7 0 b (6 0 r  ) (8 0 r  )))");
     }
 
+    #[test]
+    fn rebase() {
+        let mut rb = Rbtree::new_dummy(&[]);
+        rb.rebase();
+
+        assert!(rb.insert("5", 50));
+        assert!(rb.insert("2", 20));
+        assert!(rb.insert("7", 50));
+        assert!(rb.insert("1", 10));
+        assert!(rb.insert("3", 90));
+        assert!(rb.insert("6", 20));
+        assert!(rb.insert("8", 10));
+
+        rb.rebase();
+        rb.verify();
+        assert_eq!(rb.print(), "(5 2 b (2 1 b (1 0 r  ) (3 3 r  )) (7 2 b (6 1 r  ) (8 0 r  )))");
+        assert_eq!(rb.generations(), (0, 3));
+    }
 
     #[test]
     fn delete_root() {
@@ -1124,33 +1769,33 @@ pub mod tests {
         assert!(rb.insert("6", 6));
         assert!(rb.insert("8", 8));
 
-        assert_eq!(rb.delete(&"5"), Some(("5", 0)));
+        assert_eq!(rb.delete(&"5"), Some(("5", 5)));
         assert_eq!(rb.print(), "(6 6 b (2 2 b (1 1 r  ) (3 3 r  )) (7 7 b  (8 8 r  )))");
         rb.verify();
 
-        assert_eq!(rb.delete(&"6"), Some(("6", 0)));
+        assert_eq!(rb.delete(&"6"), Some(("6", 6)));
         assert_eq!(rb.print(), "(7 7 b (2 2 b (1 1 r  ) (3 3 r  )) (8 8 b  ))");
         rb.verify();
 
         println!("{}", rb.pprint());
-        assert_eq!(rb.delete(&"7"), Some(("7", 0)));
+        assert_eq!(rb.delete(&"7"), Some(("7", 7)));
         println!("{}", rb.pprint());
         assert_eq!(rb.print(), "(2 2 b (1 1 b  ) (8 8 b (3 3 r  ) ))");
         rb.verify();
 
-        assert_eq!(rb.delete(&"2"), Some(("2", 0)));
+        assert_eq!(rb.delete(&"2"), Some(("2", 2)));
         assert_eq!(rb.print(), "(3 3 b (1 1 b  ) (8 8 b  ))");
         rb.verify();
 
-        assert_eq!(rb.delete(&"3"), Some(("3", 0)));
+        assert_eq!(rb.delete(&"3"), Some(("3", 3)));
         assert_eq!(rb.print(), "(8 8 b (1 1 r  ) )");
         rb.verify();
 
-        assert_eq!(rb.delete(&"8"), Some(("8", 0)));
+        assert_eq!(rb.delete(&"8"), Some(("8", 8)));
         assert_eq!(rb.print(), "(1 1 b  )");
         rb.verify();
 
-        assert_eq!(rb.delete(&"1"), Some(("1", 0)));
+        assert_eq!(rb.delete(&"1"), Some(("1", 1)));
         assert_eq!(rb.print(), "");
         rb.verify();
 
@@ -1358,6 +2003,38 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn retain() {
+        let input = sequential_strings(1000);
+        let mut rng = StdRng::seed_from_u64(72);
+
+        let mut rb = Rbtree::default();
+        let mut shuffled = input.clone();
+        shuffled.shuffle(&mut rng);
+        for (i, s) in shuffled.into_iter().enumerate() {
+            assert!(rb.insert(s, i.try_into().unwrap()));
+        }
+
+        let mut seen = 0;
+        let mut removed = rb.retain(|s| {
+            seen += 1;
+            !s.ends_with(['0', '3', '4', '9'])
+        });
+        assert_eq!(seen, 1000);
+        rb.verify();
+
+        removed.sort_unstable();
+        let expected: Vec<_> = input.iter().filter(|s| s.ends_with(['0', '3', '4', '9'])).collect();
+        assert!(removed.iter().eq(expected));
+        assert_eq!(rb.size, 600);
+
+        assert!(rb.retain(|_| true).is_empty());
+        assert_eq!(rb.retain(|_| false).len(), 600);
+        rb.verify();
+        assert_eq!(rb.size, 0);
+        assert!(rb.root.is_none());
+    }
+
     #[test]
     fn find_next() {
         let strings = sequential_strings(11);
@@ -1529,4 +2206,229 @@ pub mod tests {
         assert_eq!(rb.print(), "(5 5 b (2 1000 r  ) (7 7 r  ))");
         rb.verify();
     }
+
+    #[test]
+    fn verify_integrity() {
+        let mut rb = Rbtree::new_dummy(&[]);
+        assert_eq!(rb.verify_integrity(), Ok(()));
+
+        for (s, gen) in [("5", 5), ("2", 2), ("7", 7), ("1", 1), ("3", 3), ("6", 6), ("8", 8)] {
+            assert!(rb.insert(s, gen));
+        }
+        assert_eq!(rb.verify_integrity(), Ok(()));
+
+        let mut root = rb.root.unwrap();
+        let mut left = unsafe { root.as_ref() }.left.unwrap();
+        let mut left_left = unsafe { left.as_ref() }.left.unwrap();
+
+        rb.corrupt_children();
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Children));
+        unsafe { root.as_mut().children -= 1 };
+
+        unsafe { left_left.as_mut().children += 1 };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Children));
+        unsafe { left_left.as_mut().children -= 1 };
+
+        unsafe { left_left.as_mut().max_gen = 100 };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Generations));
+        unsafe { left_left.as_mut().max_gen = 1 };
+
+        unsafe { left.as_mut().red = true };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::RedRed));
+        unsafe { left.as_mut().red = false };
+
+        unsafe { left_left.as_mut().red = false };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::BlackHeight));
+        unsafe { left_left.as_mut().red = true };
+
+        unsafe { left.as_mut().item = "9" };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Order));
+        unsafe { left.as_mut().item = "2" };
+
+        let hash = unsafe { left.as_ref() }.hash;
+        unsafe { left.as_mut().hash = u64::MAX };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Order));
+        unsafe { left.as_mut().hash = hash };
+
+        unsafe { root.as_mut().red = true };
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Root));
+        unsafe { root.as_mut().red = false };
+
+        rb.size += 1;
+        assert_eq!(rb.verify_integrity(), Err(IntegrityError::Size));
+        rb.size -= 1;
+
+        assert_eq!(rb.verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn find_next_widening() {
+        let mut rb = Rbtree::new_dummy(&[]);
+        let gens = [0, 10, 20, 5, 30, 40, 35, 50];
+        for (s, gen) in ["1", "2", "3", "4", "5", "6", "7", "8"].into_iter().zip(gens) {
+            assert!(rb.insert(s, gen));
+        }
+
+        unsafe {
+            assert_eq!(rb.find_next(1, 10).as_ref().item, "2");
+            assert_eq!(rb.find_next_widening(1, 10).as_ref().item, "2");
+
+            assert_eq!(rb.find_next(5, 10).as_ref().item, "1");
+            assert_eq!(rb.find_next_widening(5, 10).as_ref().item, "7");
+            assert_eq!(rb.find_next_widening(7, 0).as_ref().item, "8");
+            assert_eq!(rb.find_next_widening(4, 0).as_ref().item, "5");
+            assert_eq!(rb.find_next_widening(0, 0).as_ref().item, "1");
+        }
+
+        // Only wraps when every item after the index is suspended.
+        let suspended: Vec<_> = ["6", "7", "8"].iter().map(|s| rb.find_node(s).unwrap()).collect();
+        suspended.iter().for_each(|n| Node::set_suspended(*n, true));
+        unsafe {
+            assert_eq!(rb.find_next_widening(5, 10).as_ref().item, "1");
+            assert_eq!(rb.find_next_widening(4, 10).as_ref().item, "5");
+        }
+        suspended.iter().for_each(|n| Node::set_suspended(*n, false));
+        rb.verify();
+    }
+
+    #[test]
+    fn find_next_uniform() {
+        let mut rb = Rbtree::default();
+        for s in sequential_strings(10) {
+            assert!(rb.insert(s, 5));
+        }
+
+        let mut rng = StdRng::seed_from_u64(44);
+        let mut counts = [0; 10];
+        for _ in 0..100_000 {
+            let node = rb.find_next(rng.gen_range(0..10), 5);
+            let index = rb.values().iter().position(|v| *v == unsafe { node.as_ref() }.get());
+            counts[index.unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|c| (9_500..10_500).contains(c)), "{counts:?}");
+
+        // Every index maps to the item at that index.
+        for (i, v) in rb.values().into_iter().enumerate() {
+            assert_eq!(unsafe { rb.find_next(i, 5).as_ref() }.get(), v);
+            assert_eq!(unsafe { rb.find_next_widening(i, 5).as_ref() }.get(), v);
+        }
+    }
+
+    #[cfg(feature = "rotation-count")]
+    #[test]
+    fn rotation_count() {
+        let mut rb = Rbtree::new_dummy(&[]);
+
+        // Ascending inserts rotate at the 3rd, 5th, and 7th items, recoloring in between.
+        for s in ["1", "2", "3", "4", "5", "6", "7"] {
+            rb.insert(s, 0);
+        }
+        rb.verify();
+        assert_eq!(rb.rotation_count(), 3);
+        assert_eq!(rb.print(), "(2 0 b (1 0 b  ) (4 0 r (3 0 b  ) (6 0 b (5 0 r  ) (7 0 r  ))))");
+
+        rb.reset_rotation_count();
+        assert_eq!(rb.rotation_count(), 0);
+
+        // A zig-zag needs a double rotation.
+        let mut rb = Rbtree::new_dummy(&[]);
+        for s in ["3", "1", "2"] {
+            rb.insert(s, 0);
+        }
+        assert_eq!(rb.rotation_count(), 2);
+
+        // Only recoloring, then deleting a black leaf with a red sibling rotates the sibling up.
+        let mut rb = Rbtree::new_dummy(&[]);
+        for s in ["2", "1", "4", "3", "5", "6"] {
+            rb.insert(s, 0);
+        }
+        assert_eq!(rb.rotation_count(), 0);
+        rb.delete("1");
+        rb.verify();
+        assert_eq!(rb.rotation_count(), 1);
+    }
+
+    #[test]
+    fn hash_only_collisions() {
+        // Deliberately not Ord.
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Unordered(&'static str);
+
+        let order = |rb: &Rbtree<Unordered, DummyHasher>| -> Vec<_> {
+            rb.dump().into_iter().map(|(u, gen)| (u.0, gen)).collect()
+        };
+
+        // Everything except x and y collides with a hash of 0.
+        let hashes: AHashMap<_, _> = [("x", 1), ("y", 2)].into_iter().collect();
+        let mut rb = Rbtree::new_hash_only(DummyHasher { val: 0, values: Rc::from(hashes) });
+
+        let items = ["d", "x", "a", "f", "y", "c", "b", "e"];
+        for (gen, s) in items.into_iter().enumerate() {
+            assert!(rb.insert(Unordered(s), gen as u64));
+            assert_eq!(rb.verify_integrity(), Ok(()));
+        }
+        for s in items {
+            assert!(!rb.insert(Unordered(s), 100));
+            assert!(rb.find_node(&Unordered(s)).is_some());
+        }
+        assert!(rb.find_node(&Unordered("g")).is_none());
+        assert_eq!(rb.size(), 8);
+
+        // Colliding items are ordered by insertion, not by their contents.
+        let expected = [("d", 0), ("a", 2), ("f", 3), ("c", 5), ("b", 6), ("e", 7), ("x", 1)];
+        assert_eq!(order(&rb)[..7], expected);
+
+        for s in ["a", "x", "d", "c"] {
+            assert_eq!(rb.delete(&Unordered(s)).map(|(u, _)| u.0), Some(s));
+            assert!(rb.delete(&Unordered(s)).is_none());
+            assert_eq!(rb.verify_integrity(), Ok(()));
+        }
+        assert_eq!(order(&rb), [("f", 3), ("b", 6), ("e", 7), ("y", 4)]);
+
+        assert!(rb.insert(Unordered("a"), 8));
+        assert_eq!(rb.verify_integrity(), Ok(()));
+        assert_eq!(order(&rb), [("f", 3), ("b", 6), ("e", 7), ("a", 8), ("y", 4)]);
+        for s in ["f", "b", "e", "a", "y"] {
+            assert!(rb.find_node(&Unordered(s)).is_some());
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn inconsistent_ord() {
+        use std::cmp::Ordering;
+        use std::hash::Hash;
+
+        // Every value hashes to 0 and compares equal, but only equal values are Eq.
+        #[derive(Debug, PartialEq, Eq)]
+        struct Bad(u32);
+
+        impl Hash for Bad {
+            fn hash<H: Hasher>(&self, _state: &mut H) {}
+        }
+
+        impl PartialOrd for Bad {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Bad {
+            fn cmp(&self, _other: &Self) -> Ordering {
+                Ordering::Equal
+            }
+        }
+
+        let hasher = DummyHasher { val: 0, values: Rc::default() };
+        let mut rb = Rbtree::new(hasher);
+        assert!(rb.insert(Bad(1), 0));
+        assert!(!rb.insert(Bad(1), 1));
+
+        let result = catch_unwind(AssertUnwindSafe(|| rb.insert(Bad(2), 2)));
+        let msg = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert!(msg.contains("Ord is inconsistent with Eq"), "{msg}");
+
+        assert_eq!(rb.size(), 1);
+        assert_eq!(rb.verify_integrity(), Ok(()));
+    }
 }
@@ -0,0 +1,208 @@
+use alloc::vec::Vec;
+
+use rand::Rng;
+
+use crate::{random_generation, weight, Generation};
+
+/// An immutable copy of a shuffler's items and generations, for answering read-only queries
+/// from many threads while the shuffler itself keeps changing.
+///
+/// Created by [`ShufflerGeneric::snapshot_reader`](crate::ShufflerGeneric::snapshot_reader).
+/// Readers see the shuffler as it was when the snapshot was taken. Since nothing in the snapshot
+/// can change it is [`Send`] and [`Sync`] whenever the items are, and can be shared in an
+/// [`Arc`](alloc::sync::Arc) without locking.
+///
+/// Sampling is weighted exactly like the shuffler's [`sample`](crate::AwShuffler::sample) with
+/// the bias the shuffler had, except that the cooldown is ignored and selection always wraps
+/// around. Each sample scans the snapshot from a random position, so it is slower than sampling
+/// the shuffler for strongly biased, very large snapshots.
+///
+#[cfg_attr(feature = "std", doc = "```")]
+#[cfg_attr(not(feature = "std"), doc = "```ignore")]
+/// use aw_shuffle::{AwShuffler, NewItemHandling, Shuffler};
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let mut shuffler = Shuffler::new(2.0, NewItemHandling::NeverSelected);
+/// shuffler.add(1).unwrap();
+/// let snapshot = shuffler.snapshot_reader();
+///
+/// shuffler.add(2).unwrap();
+/// assert_eq!(snapshot.values(), [&1]);
+/// assert_eq!(snapshot.sample(&mut StdRng::seed_from_u64(1)), Some(&1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SnapshotReader<T> {
+    // In the same order as the tree, so sampling finds the same items selection would.
+    items: Vec<(T, u64)>,
+    bias: f64,
+    min_gen: u64,
+    max_gen: u64,
+}
+
+impl<T> SnapshotReader<T> {
+    pub(crate) fn new(items: Vec<(T, u64)>, bias: f64, (min_gen, max_gen): (u64, u64)) -> Self {
+        Self { items, bias, min_gen, max_gen }
+    }
+
+    /// Returns the number of items in the snapshot.
+    pub fn size(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the snapshot contains no items.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns all of the values in the snapshot in no specific order.
+    pub fn values(&self) -> Vec<&T> {
+        self.items.iter().map(|(item, _)| item).collect()
+    }
+
+    /// Returns all of the values in the snapshot and their generations in no specific order.
+    pub fn dump(&self) -> Vec<(&T, Generation)> {
+        self.items.iter().map(|(item, gen)| (item, Generation::new(*gen))).collect()
+    }
+
+    /// Returns all of the values in the snapshot and their weights in no specific order, like
+    /// [`AwShuffler::weights`](crate::AwShuffler::weights).
+    pub fn weights(&self) -> Vec<(&T, f64)> {
+        self.items
+            .iter()
+            .map(|(item, gen)| (item, weight(self.bias, self.min_gen, self.max_gen, *gen)))
+            .collect()
+    }
+
+    /// Returns the `n` least recently selected items, oldest first. Items with the same
+    /// generation are returned in no specific order.
+    ///
+    /// Returns every item if the snapshot contains no more than `n`.
+    pub fn oldest_n(&self, n: usize) -> Vec<&T> {
+        let mut items: Vec<_> = self.items.iter().collect();
+        if n < items.len() {
+            items.select_nth_unstable_by_key(n, |(_, gen)| *gen);
+            items.truncate(n);
+        }
+        items.sort_by_key(|(_, gen)| *gen);
+        items.into_iter().map(|(item, _)| item).collect()
+    }
+
+    /// Returns an item chosen the same way the shuffler would have chosen one when the snapshot
+    /// was taken, using `rng` for randomness.
+    ///
+    /// Returns `None` when the snapshot is empty.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&T> {
+        if self.items.is_empty() {
+            return None;
+        }
+
+        let random_gen = random_generation(rng, self.bias, self.min_gen, self.max_gen);
+        let index = rng.gen_range(0..self.items.len());
+
+        // The oldest items are always eligible, so this always finds an item.
+        let (head, tail) = self.items.split_at(index);
+        tail.iter()
+            .chain(head)
+            .find(|(_, gen)| *gen <= random_gen)
+            .map(|(item, _)| item)
+    }
+
+    /// Returns `n` items sampled independently, as if by calling [`sample`](Self::sample) `n`
+    /// times.
+    ///
+    /// Returns an empty vector when the snapshot is empty.
+    pub fn sample_n<R: Rng + ?Sized>(&self, rng: &mut R, n: usize) -> Vec<&T> {
+        (0..n).map_while(|_| self.sample(rng)).collect()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::{AwShuffler, Generation, NewItemHandling, Shuffler};
+
+    fn new_shuffler() -> Shuffler<u32> {
+        let mut shuffler = Shuffler::from_seed(2.0, NewItemHandling::NeverSelected, 7);
+        for i in 0..100 {
+            shuffler.add_with_generation(i, Generation::new(u64::from(i))).unwrap();
+        }
+        shuffler
+    }
+
+    #[test]
+    fn queries() {
+        let shuffler = new_shuffler();
+        let snapshot = shuffler.snapshot_reader();
+
+        assert_eq!(snapshot.size(), 100);
+        assert!(!snapshot.is_empty());
+        assert_eq!(snapshot.values(), shuffler.values());
+        assert_eq!(snapshot.dump(), shuffler.dump());
+        assert_eq!(snapshot.weights(), shuffler.weights());
+        assert_eq!(snapshot.oldest_n(3), [&0, &1, &2]);
+        assert_eq!(snapshot.oldest_n(200).len(), 100);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(snapshot.sample_n(&mut rng, 50).len(), 50);
+
+        let empty = Shuffler::<u32>::default().snapshot_reader();
+        assert!(empty.is_empty());
+        assert_eq!(empty.sample(&mut rng), None);
+        assert!(empty.sample_n(&mut rng, 5).is_empty());
+        assert!(empty.oldest_n(5).is_empty());
+    }
+
+    #[test]
+    fn biased_samples() {
+        // With an infinite bias only the oldest item can be selected.
+        let mut shuffler = Shuffler::from_seed(f64::INFINITY, NewItemHandling::NeverSelected, 2);
+        for i in 0..100_u32 {
+            shuffler.add_with_generation(i, Generation::new(100 - u64::from(i))).unwrap();
+        }
+        let snapshot = shuffler.snapshot_reader();
+
+        let mut rng = StdRng::seed_from_u64(3);
+        assert!(snapshot.sample_n(&mut rng, 100).into_iter().all(|i| *i == 99));
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let mut shuffler = new_shuffler();
+        let snapshot = Arc::new(shuffler.snapshot_reader());
+        let expected: HashSet<_> = (0..100).collect();
+
+        let readers: Vec<_> = (0..4)
+            .map(|seed| {
+                let snapshot = Arc::clone(&snapshot);
+                let expected = expected.clone();
+                thread::spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    for _ in 0..1000 {
+                        assert!(expected.contains(snapshot.sample(&mut rng).unwrap()));
+                        assert_eq!(snapshot.size(), 100);
+                    }
+                    assert_eq!(snapshot.oldest_n(1), [&0]);
+                })
+            })
+            .collect();
+
+        for i in 0..1000 {
+            shuffler.add(100 + i).unwrap();
+            shuffler.next().unwrap().unwrap();
+            shuffler.remove(&(i % 100)).unwrap();
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(snapshot.values().into_iter().copied().collect::<HashSet<_>>(), expected);
+        assert_eq!(shuffler.size(), 1000);
+    }
+}
@@ -1,13 +1,16 @@
 use std::cmp::max;
+use std::fmt::Display;
+use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use aw_shuffle::persistent::rocksdb::Shuffler;
-use aw_shuffle::persistent::PersistentShuffler;
-use aw_shuffle::AwShuffler;
-use clap::{Parser, Subcommand};
-use rocksdb::{Options, DB};
+use aw_shuffle::persistent::rocksdb::{self as shuffler, Shuffler};
+use aw_shuffle::persistent::{self, PersistentShuffler};
+use aw_shuffle::{AwShuffler, NewItemHandling};
+use clap::{Parser, Subcommand, ValueEnum};
+use rocksdb::{Options, WriteBatch, DB};
 use tempfile::tempdir;
 use unicode_width::UnicodeWidthStr;
 
@@ -18,73 +21,362 @@ struct Opt {
     /// The RocksDB database used for storing persistent data between runs.
     db: PathBuf,
 
+    #[arg(short = '0', long)]
+    /// Read and print NUL-separated strings instead of newline-separated strings.
+    null: bool,
+
     #[command(subcommand)]
     cmd: Command,
 }
 
 #[derive(Subcommand)]
 enum Command {
-    /// Read strings from stdin and pick NUM of them, attempting to make them unique.
+    /// Read strings from stdin, or from a file with --from, and pick NUM of them, attempting to
+    /// make them unique.
     /// If no strings are provided the DB will be read as-is.
-    Pick { num: usize },
+    Pick {
+        num: usize,
+
+        #[arg(long, default_value_t = 2.0, value_parser = parse_bias)]
+        /// How strongly to favour less recently selected strings. Must be non-negative.
+        bias: f64,
+
+        #[arg(long, value_enum, default_value_t = NewItems::Never)]
+        /// How to treat strings that are not already in the database.
+        new_item_handling: NewItems,
+
+        #[arg(long)]
+        /// Seed the random number generator to make picks reproducible for the same database.
+        seed: Option<u64>,
+
+        #[arg(long, value_parser, value_name = "FILE")]
+        /// Read strings from FILE instead of stdin. Cannot be used when something is piped into
+        /// stdin.
+        from: Option<PathBuf>,
+
+        #[arg(long)]
+        /// Only pick unique strings. If there are fewer than NUM nothing is printed and strpick
+        /// exits with status 3.
+        strict: bool,
+    },
+    /// Read strings from stdin and add them to the database without picking any.
+    Add,
+    /// Read strings from stdin and remove them from the database.
+    Remove,
     /// Dump the current contents of the database to stdout.
     /// This will work on any aw-shuffler databases that store strings.
-    Dump,
+    Dump {
+        #[arg(long)]
+        /// Print a JSON array of objects instead of a table.
+        json: bool,
+    },
     /// Dump the contents of any valid aw-shuffler database.
-    DumpRaw,
+    DumpRaw {
+        #[arg(long)]
+        /// Print a JSON array of objects instead of a table.
+        json: bool,
+    },
+    /// Print summary statistics about the generations stored in any valid aw-shuffler database.
+    Stats,
+    /// Print the number of strings in the database without reading stdin or picking any.
+    Count,
+    /// Write the raw contents of any valid aw-shuffler database to stdout.
+    /// The output can be restored into another database with import.
+    Export,
+    /// Read the output of export from stdin and write it into the database.
+    /// Existing items are kept, but their generations are overwritten if they are also imported.
+    Import,
     /// Repair an existing database if rocksdb has corrupted itself.
     Repair,
 }
 
-fn main() {
+// Returned by pick --strict when there aren't enough unique strings. Errors use 1 and clap uses 2.
+const NOT_ENOUGH_UNIQUE: u8 = 3;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NewItems {
+    /// Treat new strings as if they had never been selected.
+    Never,
+    /// Treat new strings as if they were just selected.
+    Recent,
+    /// Treat new strings as if they were selected at a random point in the past.
+    Random,
+}
+
+impl From<NewItems> for NewItemHandling {
+    fn from(n: NewItems) -> Self {
+        match n {
+            NewItems::Never => Self::NeverSelected,
+            NewItems::Recent => Self::RecentlySelected,
+            NewItems::Random => Self::Random,
+        }
+    }
+}
+
+fn parse_bias(s: &str) -> Result<f64, String> {
+    let bias: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    persistent::Options::default().try_bias(bias).map_err(|e| e.to_string())?;
+    Ok(bias)
+}
+
+enum Error {
+    Shuffler(shuffler::Error),
+    DB(rocksdb::Error),
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl From<shuffler::Error> for Error {
+    fn from(e: shuffler::Error) -> Self {
+        Self::Shuffler(e)
+    }
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        Self::DB(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<rmpv::decode::Error> for Error {
+    fn from(e: rmpv::decode::Error) -> Self {
+        Self::Invalid(format!("failed to decode MessagePack: {e}"))
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shuffler(shuffler::Error::Locked(_)) => {
+                f.write_str("the database is in use by another process")
+            }
+            Self::Shuffler(e) => write!(f, "database error: {e}"),
+            Self::DB(e) => write!(f, "database error: {e}"),
+            Self::Io(e) => write!(f, "IO error: {e}"),
+            Self::Invalid(s) => f.write_str(s),
+        }
+    }
+}
+
+impl Error {
+    const fn is_db(&self) -> bool {
+        match self {
+            Self::Shuffler(shuffler::Error::Locked(_)) => false,
+            Self::Shuffler(_) | Self::DB(_) => true,
+            Self::Io(_) | Self::Invalid(_) => false,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let opt = Opt::parse();
 
+    match run(&opt) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("strpick: {e}");
+            if e.is_db() && !matches!(opt.cmd, Command::Repair) {
+                eprintln!(
+                    "If the database is corrupted, try running `strpick --db {} repair`.",
+                    opt.db.display()
+                );
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    match &opt.cmd {
-        Command::Pick { num } => pick(&opt.db, *num),
-        Command::Dump => dump(&opt.db, |v| {
-            if let rmpv::Value::String(s) = v {
-                s.as_str().unwrap().to_owned()
-            } else {
-                panic!("Item {v} is not string")
+fn run(opt: &Opt) -> Result<ExitCode, Error> {
+    let result = match &opt.cmd {
+        Command::Pick { num, bias, new_item_handling, seed, from, strict } => {
+            let mut options = persistent::Options::default()
+                .bias(*bias)
+                .new_item_handling((*new_item_handling).into());
+            if let Some(seed) = seed {
+                options = options.seed(*seed);
             }
+            return pick(&opt.db, *num, opt.null, from.as_deref(), *strict, options);
+        }
+        Command::Add => add(&opt.db, opt.null),
+        Command::Remove => remove(&opt.db, opt.null),
+        Command::Dump { json } => dump(&opt.db, *json, |v| match v {
+            rmpv::Value::String(s) if s.is_str() => Ok(s.into_str().unwrap()),
+            v => Err(Error::Invalid(format!("item {v} is not a string"))),
         }),
-        Command::DumpRaw => dump(&opt.db, |v| v.to_string()),
+        Command::DumpRaw { json: false } => dump(&opt.db, false, |v| Ok(v.to_string())),
+        Command::DumpRaw { json: true } => dump_raw_json(&opt.db),
+        Command::Stats => stats(&opt.db),
+        Command::Count => count(&opt.db),
+        Command::Export => export(&opt.db),
+        Command::Import => import(&opt.db),
         Command::Repair => repair(&opt.db),
+    };
+
+    result.map(|()| ExitCode::SUCCESS)
+}
+
+fn dump<F>(db: &Path, json: bool, f: F) -> Result<(), Error>
+where
+    F: Fn(rmpv::Value) -> Result<String, Error>,
+{
+    let mut contents = read_all(db, f)?;
+    contents.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    if json {
+        print_json(contents);
+    } else {
+        print(contents);
     }
+    Ok(())
 }
 
-fn dump<F: Fn(rmpv::Value) -> String>(db: &Path, f: F) {
-    let tdir = tempdir().unwrap();
+fn dump_raw_json(db: &Path) -> Result<(), Error> {
+    // Converted so that items keep their types instead of being printed as MessagePack strings.
+    let contents = read_all(db, |v| {
+        rmpv::ext::from_value::<serde_json::Value>(v)
+            .map_err(|e| Error::Invalid(format!("item cannot be represented as JSON: {e}")))
+    })?;
+
+    print_json(contents);
+    Ok(())
+}
+
+fn read_all<T, F>(db: &Path, f: F) -> Result<Vec<(T, u64)>, Error>
+where
+    F: Fn(rmpv::Value) -> Result<T, Error>,
+{
+    let tdir = tempdir()?;
     let mut options = Options::default();
     options.set_compression_type(rocksdb::DBCompressionType::Lz4);
 
-    let db = DB::open_as_secondary(&options, db, tdir.path()).unwrap();
+    let db = DB::open_as_secondary(&options, db, tdir.path())?;
 
     let mut contents = Vec::new();
 
-    for (key, value) in db.iterator(rocksdb::IteratorMode::Start).flatten() {
-        let k = rmpv::decode::value::read_value(&mut key.as_ref()).unwrap();
-        let gen = rmpv::decode::value::read_value(&mut value.as_ref()).unwrap();
+    for r in db.iterator(rocksdb::IteratorMode::Start) {
+        let (key, value) = r?;
+        let k = rmpv::decode::value::read_value(&mut key.as_ref())?;
+        let gen = rmpv::decode::value::read_value(&mut value.as_ref())?;
 
-        let gen = if let rmpv::Value::Integer(g) = gen {
-            g.as_u64().unwrap()
-        } else {
-            panic!("Generation not integer")
+        let Some(gen) = gen.as_u64() else {
+            return Err(Error::Invalid(format!("generation {gen} for {k} is not an integer")));
         };
 
-        contents.push((f(k), gen));
+        contents.push((f(k)?, gen));
+    }
+
+    drop(db);
+    drop(tdir);
+    Ok(contents)
+}
+
+fn stats(db: &Path) -> Result<(), Error> {
+    let gens: Vec<_> = read_all(db, |_| Ok(()))?.into_iter().map(|(_, g)| g).collect();
+
+    let mut rows = vec![("items", gens.len().to_string())];
+
+    if let (Some(min), Some(max)) = (gens.iter().min(), gens.iter().max()) {
+        let mean = gens.iter().map(|g| *g as f64).sum::<f64>() / gens.len() as f64;
+        let at_min = gens.iter().filter(|g| *g == min).count();
+
+        rows.push(("min generation", min.to_string()));
+        rows.push(("max generation", max.to_string()));
+        rows.push(("mean generation", format!("{mean:.2}")));
+        rows.push(("at min generation", at_min.to_string()));
     }
 
-    print(contents);
+    let kw = rows.iter().map(|(k, _)| k.len()).max().unwrap_or_default();
+    let vw = rows.iter().map(|(_, v)| v.len()).max().unwrap_or_default();
+
+    for (k, v) in rows {
+        println!("{k:<kw$} | {v:>vw$}");
+    }
+    Ok(())
+}
+
+fn count(db: &Path) -> Result<(), Error> {
+    // Read-only so nothing is written and a database in use by another process can be counted.
+    let s: Shuffler<String> = Shuffler::open_read_only(db, None)?;
+    println!("{}", s.size());
+
+    s.close()?;
+    Ok(())
+}
+
+// Each record is a big-endian u32 length followed by a MessagePack array containing the raw key
+// and value as binary blobs.
+fn export(db: &Path) -> Result<(), Error> {
+    let tdir = tempdir()?;
+    let mut options = Options::default();
+    options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+
+    let db = DB::open_as_secondary(&options, db, tdir.path())?;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut buf = Vec::new();
+
+    for r in db.iterator(rocksdb::IteratorMode::Start) {
+        let (key, value) = r?;
+        let record = rmpv::Value::Array(vec![key.into_vec().into(), value.into_vec().into()]);
+
+        buf.clear();
+        rmpv::encode::write_value(&mut buf, &record)?;
+
+        let len = u32::try_from(buf.len())
+            .map_err(|_| Error::Invalid(format!("record {record} is too large to export")))?;
+        out.write_all(&len.to_be_bytes())?;
+        out.write_all(&buf)?;
+    }
+
+    out.flush()?;
 
     drop(db);
     drop(tdir);
+    Ok(())
 }
 
-fn print(mut vals: Vec<(String, u64)>) {
-    vals.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+fn import(db: &Path) -> Result<(), Error> {
+    let mut options = Options::default();
+    options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+    options.create_if_missing(true);
+
+    let db = DB::open(&options, db).map_err(shuffler::Error::from_open)?;
+
+    let mut input = io::stdin().lock();
+    let mut batch = WriteBatch::default();
+
+    loop {
+        let mut len = [0; 4];
+        match input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
 
+        let mut buf = vec![0; u32::from_be_bytes(len) as usize];
+        input.read_exact(&mut buf)?;
+
+        let record = rmpv::decode::value::read_value(&mut buf.as_slice())?;
+        match record {
+            rmpv::Value::Array(kv) => match kv.as_slice() {
+                [rmpv::Value::Binary(k), rmpv::Value::Binary(v)] => batch.put(k, v),
+                _ => return Err(Error::Invalid(format!("record {kv:?} is not a key and value"))),
+            },
+            v => return Err(Error::Invalid(format!("record {v} is not an array"))),
+        }
+    }
+
+    db.write(batch)?;
+    Ok(())
+}
+
+fn print(vals: Vec<(String, u64)>) {
     let (kw, vw) = vals.iter().fold((0, 0), |(kw, vw), (s, g)| {
         let gw = if *g == 0 { 1 } else { (*g as f64).log10() as usize + 1 };
         (max(kw, UnicodeWidthStr::width(s.as_str())), max(vw, gw))
@@ -96,25 +388,122 @@ fn print(mut vals: Vec<(String, u64)>) {
     }
 }
 
-fn pick(db: &Path, num: usize) {
+fn print_json<T: Into<serde_json::Value>>(vals: Vec<(T, u64)>) {
+    let vals: Vec<_> = vals
+        .into_iter()
+        .map(|(s, g)| serde_json::json!({ "item": s.into(), "generation": g }))
+        .collect();
+
+    println!("{}", serde_json::Value::Array(vals));
+}
+
+fn read_strings(input: impl BufRead, null: bool) -> Result<Vec<String>, Error> {
+    if null {
+        input
+            .split(b'\0')
+            .map(|b| {
+                String::from_utf8(b?)
+                    .map_err(|_| Error::Invalid("input is not valid UTF-8".to_owned()))
+            })
+            .collect()
+    } else {
+        input.lines().map(|l| Ok(l?)).collect()
+    }
+}
+
+fn read_stdin(null: bool) -> Result<Vec<String>, Error> {
+    read_strings(io::stdin().lock(), null)
+}
+
+fn read_file(file: &Path, null: bool) -> Result<Vec<String>, Error> {
+    read_strings(BufReader::new(File::open(file)?), null)
+}
+
+// Terminals and /dev/null can't be holding strings the caller expected to be read.
+fn stdin_is_piped() -> bool {
     let stdin = io::stdin();
-    let strings: Vec<_> = stdin.lock().lines().map_while(Result::ok).collect();
+    if stdin.is_terminal() {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        use std::os::unix::fs::FileTypeExt;
+
+        let meta = stdin.as_fd().try_clone_to_owned().map(File::from).and_then(|f| f.metadata());
+        if let Ok(meta) = meta {
+            return !meta.file_type().is_char_device();
+        }
+    }
+    true
+}
+
+fn pick(
+    db: &Path,
+    num: usize,
+    null: bool,
+    from: Option<&Path>,
+    strict: bool,
+    options: persistent::Options,
+) -> Result<ExitCode, Error> {
+    let strings = match from {
+        Some(_) if stdin_is_piped() => {
+            return Err(Error::Invalid("--from cannot be combined with piped stdin".to_owned()));
+        }
+        Some(file) => read_file(file, null)?,
+        None => read_stdin(null)?,
+    };
 
     let strings = if !strings.is_empty() { Some(strings) } else { None };
 
-    let mut s: Shuffler<String> = Shuffler::new_default(db, strings)
-        .unwrap_or_else(|e| panic!("Failed to open the database at {db:?}: {e}"));
+    let mut s: Shuffler<String> = Shuffler::new(db, options, strings)?;
 
-    for s in s.try_unique_n(num).unwrap().into_iter().flatten() {
-        println!("{s}")
+    let picked = if strict { s.unique_n(num)? } else { s.try_unique_n(num)? };
+    // An empty database can still satisfy a request for nothing.
+    let code = if strict && num > 0 && picked.is_none() {
+        ExitCode::from(NOT_ENOUGH_UNIQUE)
+    } else {
+        ExitCode::SUCCESS
+    };
+
+    for s in picked.into_iter().flatten() {
+        if null {
+            print!("{s}\0")
+        } else {
+            println!("{s}")
+        }
     }
 
-    s.close_leak().unwrap();
+    s.close_no_dealloc()?;
+    Ok(code)
 }
 
-fn repair(db: &Path) {
-    let mut options = Options::default();
-    options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+fn add(db: &Path, null: bool) -> Result<(), Error> {
+    let strings = read_stdin(null)?;
+    let mut s: Shuffler<String> = Shuffler::new_default(db, None)?;
+
+    for string in strings {
+        s.add(string)?;
+    }
+
+    s.close()?;
+    Ok(())
+}
+
+fn remove(db: &Path, null: bool) -> Result<(), Error> {
+    let strings = read_stdin(null)?;
+    let mut s: Shuffler<String> = Shuffler::new_default(db, None)?;
+
+    for string in strings {
+        s.remove(&string)?;
+    }
+
+    s.close()?;
+    Ok(())
+}
 
-    DB::repair(&options, db).unwrap();
+fn repair(db: &Path) -> Result<(), Error> {
+    shuffler::repair(db)?;
+    Ok(())
 }
@@ -0,0 +1,405 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use aw_shuffle::persistent::rocksdb::Shuffler;
+use aw_shuffle::persistent::PersistentShuffler;
+use aw_shuffle::AwShuffler;
+use tempfile::tempdir;
+
+fn strpick(db: &Path, args: &[&str], stdin: impl AsRef<[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_strpick"))
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin.as_ref()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn strpick_without_stdin(db: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_strpick"))
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn lines(output: &Output) -> Vec<&str> {
+    std::str::from_utf8(&output.stdout).unwrap().lines().collect()
+}
+
+fn dumped(db: &Path) -> Vec<String> {
+    let out = strpick(db, &["dump"], "");
+    assert!(out.status.success());
+    lines(&out)
+        .into_iter()
+        .map(|l| l.rsplit_once(" | ").unwrap().0.trim_end().to_owned())
+        .collect()
+}
+
+#[test]
+fn pick_flags() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let out = strpick(&db, &["pick", "2"], "a\nb\nc\n");
+    assert!(out.status.success());
+    assert_eq!(lines(&out).len(), 2);
+
+    let out = strpick(&db, &["pick", "3", "--bias", "0"], "a\nb\nc\n");
+    assert!(out.status.success());
+    let mut picked = lines(&out);
+    picked.sort_unstable();
+    assert_eq!(picked, ["a", "b", "c"]);
+
+    for handling in ["never", "recent", "random"] {
+        let out = strpick(
+            &db,
+            &["pick", "1", "--bias", "inf", "--new-item-handling", handling],
+            "a\nb\nc\nd\n",
+        );
+        assert!(out.status.success());
+        assert_eq!(lines(&out).len(), 1);
+    }
+}
+
+#[test]
+fn pick_seeded() {
+    let dir = tempdir().unwrap();
+    let strings: String = (0..100).map(|i| format!("{i}\n")).collect();
+
+    // Picking updates the database, so pick from two identical databases.
+    let picks: Vec<_> = ["a", "b"]
+        .into_iter()
+        .map(|name| {
+            let db = dir.path().join(name);
+            assert!(strpick(&db, &["add"], &strings).status.success());
+
+            let out = strpick(&db, &["pick", "10", "--seed", "42"], "");
+            assert!(out.status.success());
+            out.stdout
+        })
+        .collect();
+
+    assert_eq!(std::str::from_utf8(&picks[0]).unwrap().lines().count(), 10);
+    assert_eq!(picks[0], picks[1]);
+}
+
+#[test]
+fn pick_invalid_flags() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    for args in [
+        &["pick", "1", "--bias=-1"][..],
+        &["pick", "1", "--bias", "NaN"],
+        &["pick", "1", "--bias", "x"],
+        &["pick", "1", "--new-item-handling", "sometimes"],
+        &["pick", "1", "--seed", "-1"],
+    ] {
+        let out = strpick(&db, args, "a\n");
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(out.stdout.is_empty());
+        assert!(!String::from_utf8_lossy(&out.stderr).contains("panicked"));
+    }
+
+    let out = strpick(&db, &["pick", "1", "--bias=-1"], "a\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("bias -1 cannot be negative"));
+
+    // Nothing should have been created
+    assert!(!db.exists());
+}
+
+#[test]
+fn pick_strict() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let out = strpick(&db, &["pick", "3", "--strict"], "a\nb\n");
+    assert_eq!(out.status.code(), Some(3));
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
+    // The strings were still added.
+    assert_eq!(dumped(&db), ["a", "b"]);
+
+    let out = strpick(&db, &["pick", "2", "--strict"], "");
+    assert!(out.status.success());
+    let mut picked = lines(&out);
+    picked.sort_unstable();
+    assert_eq!(picked, ["a", "b"]);
+
+    // Without --strict repeats fill in the rest.
+    let out = strpick(&db, &["pick", "3"], "");
+    assert!(out.status.success());
+    assert_eq!(lines(&out).len(), 3);
+
+    let empty = dir.path().join("empty");
+    assert!(strpick(&empty, &["pick", "0", "--strict"], "").status.success());
+    assert_eq!(strpick(&empty, &["pick", "1", "--strict"], "").status.code(), Some(3));
+}
+
+#[test]
+fn pick_from_file() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+    let file = dir.path().join("strings");
+    std::fs::write(&file, "a\nb\nc\n").unwrap();
+    let from = file.to_str().unwrap();
+
+    let out = strpick_without_stdin(&db, &["pick", "3", "--from", from]);
+    assert!(out.status.success());
+    let mut picked = lines(&out);
+    picked.sort_unstable();
+    assert_eq!(picked, ["a", "b", "c"]);
+    assert_eq!(dumped(&db), ["a", "b", "c"]);
+
+    let missing = dir.path().join("missing");
+    let out = strpick_without_stdin(&db, &["pick", "1", "--from", missing.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("strpick: IO error"));
+}
+
+#[test]
+fn pick_from_file_piped_stdin() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+    let file = dir.path().join("strings");
+    std::fs::write(&file, "a\nb\nc\n").unwrap();
+
+    for stdin in ["", "d\n"] {
+        let out = strpick(&db, &["pick", "1", "--from", file.to_str().unwrap()], stdin);
+        assert_eq!(out.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&out.stderr),
+            "strpick: --from cannot be combined with piped stdin\n"
+        );
+    }
+    assert!(!db.exists());
+}
+
+#[test]
+fn dump_json() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    assert!(strpick(&db, &["pick", "2"], "a\nb\nc\n\"quoted\"\nback\\slash\n").status.success());
+    assert!(strpick(&db, &["pick", "3"], "").status.success());
+
+    let shuffler: Shuffler<String> = Shuffler::new_default(&db, None).unwrap();
+    let expected: Vec<_> =
+        shuffler.dump_sorted().into_iter().map(|(s, g)| (s.clone(), g.get())).collect();
+    shuffler.close().unwrap();
+
+    let out = strpick(&db, &["dump", "--json"], "");
+    assert!(out.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let dumped: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["item"].as_str().unwrap().to_owned(), v["generation"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(dumped, expected);
+
+    let out = strpick(&db, &["dump-raw", "--json"], "");
+    assert!(out.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let mut dumped: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["item"].as_str().unwrap().to_owned(), v["generation"].as_u64().unwrap()))
+        .collect();
+    dumped.sort_unstable();
+    assert_eq!(dumped, expected);
+}
+
+#[test]
+fn null_delimited() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let out = strpick(&db, &["-0", "pick", "2"], "dir/with\nnewline\0other\0");
+    assert!(out.status.success());
+    let mut picked: Vec<_> = out.stdout.split(|b| *b == b'\0').collect();
+    assert_eq!(picked.pop(), Some(&b""[..]));
+    picked.sort_unstable();
+    assert_eq!(picked, [&b"dir/with\nnewline"[..], b"other"]);
+
+    // Read the database as-is
+    let out = strpick(&db, &["--null", "pick", "2"], "");
+    assert!(out.status.success());
+    let mut picked: Vec<_> = out.stdout.split(|b| *b == b'\0').collect();
+    assert_eq!(picked.pop(), Some(&b""[..]));
+    picked.sort_unstable();
+    assert_eq!(picked, [&b"dir/with\nnewline"[..], b"other"]);
+
+    let out = strpick(&db, &["pick", "2"], "");
+    assert!(out.status.success());
+    assert_eq!(out.stdout.iter().filter(|b| **b == b'\n').count(), 3);
+}
+
+#[test]
+fn add_remove() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let out = strpick(&db, &["add"], "a\nb\nc\n");
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    assert_eq!(dumped(&db), ["a", "b", "c"]);
+
+    assert!(strpick(&db, &["add"], "b\nd\n").status.success());
+    assert_eq!(dumped(&db), ["a", "b", "c", "d"]);
+
+    let out = strpick(&db, &["remove"], "a\nc\nmissing\n");
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    assert_eq!(dumped(&db), ["b", "d"]);
+
+    assert!(strpick(&db, &["-0", "remove"], "b\0d\0").status.success());
+    assert!(dumped(&db).is_empty());
+}
+
+#[test]
+fn stats() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let stats = || {
+        let out = strpick(&db, &["stats"], "");
+        assert!(out.status.success());
+        lines(&out)
+            .into_iter()
+            .map(|l| {
+                let (k, v) = l.split_once(" | ").unwrap();
+                (k.trim_end().to_owned(), v.trim_start().to_owned())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert!(strpick(&db, &["add"], "").status.success());
+    assert_eq!(stats(), [("items".to_owned(), "0".to_owned())]);
+
+    // With an infinite bias the least recently selected strings are always picked, leaving
+    // generations of 0, 1, 1, and 2.
+    assert!(strpick(&db, &["add"], "a\nb\nc\nd\n").status.success());
+    assert!(strpick(&db, &["pick", "2", "--bias", "inf"], "").status.success());
+    assert!(strpick(&db, &["pick", "1", "--bias", "inf"], "").status.success());
+
+    let expected = [
+        ("items", "4"),
+        ("min generation", "0"),
+        ("max generation", "2"),
+        ("mean generation", "1.00"),
+        ("at min generation", "1"),
+    ];
+    assert_eq!(stats(), expected.map(|(k, v)| (k.to_owned(), v.to_owned())));
+}
+
+#[test]
+fn count() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let count = || {
+        let out = strpick(&db, &["count"], "ignored\n");
+        assert!(out.status.success());
+        lines(&out).concat()
+    };
+
+    assert!(strpick(&db, &["add"], "").status.success());
+    assert_eq!(count(), "0");
+
+    assert!(strpick(&db, &["add"], "a\nb\nc\nd\n").status.success());
+    assert_eq!(count(), "4");
+
+    // Counting neither reads stdin nor picks anything.
+    assert!(strpick(&db, &["remove"], "b\n").status.success());
+    assert_eq!(count(), "3");
+    assert_eq!(count(), "3");
+    assert_eq!(dumped(&db), ["a", "c", "d"]);
+
+    let out = strpick(&db, &["stats"], "");
+    assert!(lines(&out).iter().any(|l| l.starts_with("max generation") && l.ends_with("| 0")));
+}
+
+#[test]
+fn export_import() {
+    let dir = tempdir().unwrap();
+    let a = dir.path().join("a");
+    let b = dir.path().join("b");
+
+    assert!(strpick(&a, &["add"], "a\nb\nc\nd\n").status.success());
+    assert!(strpick(&a, &["pick", "2"], "").status.success());
+    assert!(strpick(&a, &["pick", "1"], "").status.success());
+
+    let exported = strpick(&a, &["export"], "");
+    assert!(exported.status.success());
+    assert!(!exported.stdout.is_empty());
+
+    let out = strpick(&b, &["import"], &exported.stdout);
+    assert!(out.status.success());
+
+    let dump = |db| {
+        let out = strpick(db, &["dump", "--json"], "");
+        assert!(out.status.success());
+        out.stdout
+    };
+    assert_eq!(dump(&a), dump(&b));
+}
+
+
+#[test]
+fn not_a_database() {
+    let dir = tempdir().unwrap();
+    let file = dir.path().join("file");
+    std::fs::write(&file, "not a database").unwrap();
+    let corrupt = dir.path().join("corrupt");
+    std::fs::create_dir(&corrupt).unwrap();
+    std::fs::write(corrupt.join("CURRENT"), "garbage").unwrap();
+
+    let commands =
+        [&["pick", "1"][..], &["add"], &["remove"], &["dump"], &["stats"], &["count"], &["export"]];
+
+    for db in [&file, &corrupt] {
+        for args in commands {
+            let out = strpick(db, args, "a\n");
+            assert_eq!(out.status.code(), Some(1), "{db:?} {args:?}");
+            assert!(out.stdout.is_empty());
+
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            assert!(stderr.starts_with("strpick: "), "{stderr}");
+            assert!(stderr.contains("repair"), "{stderr}");
+            assert!(!stderr.contains("panicked"), "{stderr}");
+        }
+    }
+}
+
+#[test]
+fn locked() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("db");
+
+    let shuffler: Shuffler<String> = Shuffler::new_default(&db, None).unwrap();
+
+    for args in [&["pick", "1"][..], &["add"], &["remove"], &["import"]] {
+        let out = strpick(&db, args, "a\n");
+        assert_eq!(out.status.code(), Some(1), "{args:?}");
+
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert_eq!(stderr, "strpick: the database is in use by another process\n");
+    }
+
+    shuffler.close().unwrap();
+    assert!(strpick(&db, &["pick", "1"], "a\n").status.success());
+}
+